@@ -0,0 +1,22 @@
+-- Tables that predate migrations, existing databases already have them
+CREATE TABLE IF NOT EXISTS users (
+    username TEXT PRIMARY KEY,
+    pw_hash TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS bans (
+    ban_id SERIAL PRIMARY KEY,
+    identifier TEXT,
+    reason TEXT,
+    banned_on TIMESTAMPTZ NOT NULL DEFAULT now(),
+    unban_date TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS bans_identifier_idx ON bans (identifier);
+
+CREATE TABLE IF NOT EXISTS eulas (
+    version INTEGER NOT NULL,
+    lang TEXT NOT NULL,
+    contents TEXT NOT NULL,
+    PRIMARY KEY (version, lang)
+);
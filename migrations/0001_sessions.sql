@@ -0,0 +1,11 @@
+-- Server side session storage, only used when server_side_sessions is enabled
+CREATE TABLE IF NOT EXISTS sessions (
+    session_id SERIAL PRIMARY KEY,
+    key_hash TEXT NOT NULL UNIQUE,
+    username TEXT,
+    state TEXT NOT NULL,
+    created_on TIMESTAMPTZ NOT NULL DEFAULT now(),
+    expires_on TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS sessions_username_idx ON sessions (username);
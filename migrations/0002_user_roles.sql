@@ -0,0 +1,4 @@
+-- Existing accounts keep the full access they had before roles existed, new accounts default to viewer
+ALTER TABLE users ADD COLUMN IF NOT EXISTS role TEXT NOT NULL DEFAULT 'admin'
+    CHECK (role IN ('viewer', 'moderator', 'admin'));
+ALTER TABLE users ALTER COLUMN role SET DEFAULT 'viewer';
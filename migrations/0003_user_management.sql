@@ -0,0 +1,10 @@
+-- Accounts are now provisioned with one time invite tokens instead of a placeholder password
+ALTER TABLE users ALTER COLUMN pw_hash DROP NOT NULL;
+ALTER TABLE users ADD COLUMN IF NOT EXISTS disabled BOOLEAN NOT NULL DEFAULT false;
+ALTER TABLE users ADD COLUMN IF NOT EXISTS created_on TIMESTAMPTZ NOT NULL DEFAULT now();
+ALTER TABLE users ADD COLUMN IF NOT EXISTS last_login TIMESTAMPTZ;
+ALTER TABLE users ADD COLUMN IF NOT EXISTS invite_hash TEXT UNIQUE;
+ALTER TABLE users ADD COLUMN IF NOT EXISTS invite_expires TIMESTAMPTZ;
+
+-- Accounts still waiting for a first login under the old mechanism have to be re-invited
+UPDATE users SET pw_hash = NULL WHERE pw_hash = 'placeholder';
@@ -0,0 +1,5 @@
+-- Optional RFC 6238 two factor auth for panel users
+ALTER TABLE users ADD COLUMN IF NOT EXISTS totp_secret TEXT;
+-- Last time step a code was accepted for, stops a code being replayed within its window
+ALTER TABLE users ADD COLUMN IF NOT EXISTS totp_last_step BIGINT;
+ALTER TABLE users ADD COLUMN IF NOT EXISTS totp_recovery_hashes TEXT[] NOT NULL DEFAULT '{}';
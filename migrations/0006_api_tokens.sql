@@ -0,0 +1,13 @@
+-- Bearer tokens for calling panel endpoints outside the browser, only the sha256 of the token is stored
+CREATE TABLE IF NOT EXISTS api_tokens (
+    id SERIAL PRIMARY KEY,
+    name TEXT NOT NULL,
+    token_hash TEXT NOT NULL UNIQUE,
+    owner TEXT NOT NULL,
+    permissions TEXT[] NOT NULL DEFAULT '{}',
+    created_on TIMESTAMPTZ NOT NULL DEFAULT now(),
+    expires_on TIMESTAMPTZ,
+    last_used TIMESTAMPTZ,
+    last_used_ip TEXT,
+    revoked BOOLEAN NOT NULL DEFAULT false
+);
@@ -0,0 +1,28 @@
+-- Host accounts own the keys their game servers post with, revoked keys are kept so they can not be registered again
+ALTER TABLE users DROP CONSTRAINT IF EXISTS users_role_check;
+ALTER TABLE users ADD CONSTRAINT users_role_check
+    CHECK (role IN ('viewer', 'moderator', 'admin', 'host'));
+
+CREATE TABLE IF NOT EXISTS server_keys (
+    id SERIAL PRIMARY KEY,
+    name TEXT NOT NULL,
+    server_key TEXT NOT NULL UNIQUE,
+    owner TEXT NOT NULL,
+    created_on TIMESTAMPTZ NOT NULL DEFAULT now(),
+    revoked_on TIMESTAMPTZ,
+    revoked_by TEXT,
+    revoke_reason TEXT
+);
+
+CREATE INDEX IF NOT EXISTS server_keys_owner ON server_keys(owner);
+
+-- Moderation actions taken against a host's servers
+CREATE TABLE IF NOT EXISTS host_notifications (
+    id SERIAL PRIMARY KEY,
+    owner TEXT NOT NULL,
+    created_on TIMESTAMPTZ NOT NULL DEFAULT now(),
+    message TEXT NOT NULL,
+    read BOOLEAN NOT NULL DEFAULT false
+);
+
+CREATE INDEX IF NOT EXISTS host_notifications_owner ON host_notifications(owner, created_on);
@@ -0,0 +1,29 @@
+-- One row per listing of a server, from its first heartbeat until it expires or is removed
+-- A relisted server is given a new uid so it starts a new session, sessions are grouped by server_key
+CREATE TABLE IF NOT EXISTS server_sessions (
+    uid TEXT PRIMARY KEY,
+    server_key TEXT NOT NULL,
+    name TEXT NOT NULL,
+    address TEXT NOT NULL,
+    hidden BOOLEAN NOT NULL,
+    owner TEXT,
+    first_seen TIMESTAMPTZ NOT NULL,
+    last_heartbeat TIMESTAMPTZ NOT NULL,
+    ended BOOLEAN NOT NULL DEFAULT false,
+    heartbeats BIGINT NOT NULL DEFAULT 0,
+    -- Sum of the player count from every heartbeat, divided by heartbeats for the average
+    player_total BIGINT NOT NULL DEFAULT 0,
+    peak_players INTEGER NOT NULL DEFAULT 0,
+    maps TEXT[] NOT NULL DEFAULT '{}',
+    playlists TEXT[] NOT NULL DEFAULT '{}'
+);
+
+CREATE INDEX IF NOT EXISTS server_sessions_key ON server_sessions(server_key, first_seen);
+CREATE INDEX IF NOT EXISTS server_sessions_last_heartbeat ON server_sessions(last_heartbeat);
+
+-- Network wide totals, written every history_flush_seconds
+CREATE TABLE IF NOT EXISTS network_samples (
+    sampled_on TIMESTAMPTZ PRIMARY KEY DEFAULT now(),
+    servers INTEGER NOT NULL,
+    players INTEGER NOT NULL
+);
@@ -0,0 +1,11 @@
+-- A key only belongs to a host once one of their servers has posted with the challenge handed out on registration
+-- Keys registered before this have no challenge and stay unverified until their host registers them again
+ALTER TABLE server_keys ADD COLUMN IF NOT EXISTS challenge_hash TEXT;
+ALTER TABLE server_keys ADD COLUMN IF NOT EXISTS verified_on TIMESTAMPTZ;
+
+-- Several hosts may have an unverified claim on one key, only a verified or revoked one is exclusive
+ALTER TABLE server_keys DROP CONSTRAINT IF EXISTS server_keys_server_key_key;
+CREATE UNIQUE INDEX IF NOT EXISTS server_keys_claimed ON server_keys(server_key)
+    WHERE verified_on IS NOT NULL OR revoked_on IS NOT NULL;
+CREATE UNIQUE INDEX IF NOT EXISTS server_keys_pending ON server_keys(server_key, owner)
+    WHERE verified_on IS NULL AND revoked_on IS NULL;
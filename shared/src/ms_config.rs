@@ -5,6 +5,7 @@ use {
         fs::File,
         io::{ErrorKind, Read, Write},
     },
+    tracing::info,
 };
 
 const CFG_FILE_PATH: &str = "ms.cfg";
@@ -12,6 +13,7 @@ const CFG_FILE_PATH: &str = "ms.cfg";
 pub static GLOBAL_CONFIG: once_cell::sync::OnceCell<Config> = OnceCell::new();
 
 #[derive(Serialize, Deserialize)]
+#[serde(default)]
 pub struct Config {
     pub validate_server_conn: bool,
     pub server_timeout: u16,
@@ -25,6 +27,160 @@ pub struct Config {
     pub allowed_sdk_versions: Vec<String>,
     pub ban_fail_condition: bool,
     pub postgres_connection_uri: String,
+    //Start serving without waiting for the database and keep retrying it in the background instead of running without it,
+    //readyz reports not ready while it is unreachable
+    pub require_database: bool,
+    //Seconds between connection attempts while waiting for the database
+    pub database_retry_seconds: u16,
+    pub listeners: Vec<ListenerConfig>,
+    //How often (in seconds) cert and key files are checked for changes, 0 disables the check
+    pub tls_reload_interval: u16,
+    //Hex encoded key used to sign session cookies (at least 64 bytes), if empty session_key_path is used instead
+    pub session_key: String,
+    //File the session key is read from, a new key is generated and written here if it does not exist
+    pub session_key_path: String,
+    pub session_ttl_minutes: u32,
+    //Store session state in the database instead of the cookie, allows listing and revoking sessions
+    pub server_side_sessions: bool,
+    //How long invite and password reset links stay valid for
+    pub invite_expiry_hours: u32,
+    //Panel roles (viewer, moderator, admin) that have to enrol in two factor auth before they can do anything
+    pub totp_required_roles: Vec<String>,
+    //Failed panel logins allowed from an IP or for a username before exponential backoff kicks in
+    pub login_free_attempts: u32,
+    //Upper bound (in seconds) for the login backoff
+    pub login_backoff_max_seconds: u32,
+    //Consecutive failed logins after which the account is locked, 0 disables lockout
+    pub login_lockout_threshold: u32,
+    pub login_lockout_minutes: u32,
+    //Reject heartbeats that do not send the server's secret. Off by default as current game builds do not send it yet,
+    //a server that has sent its secret once always has to though. Turn on once every server in use sends it
+    pub server_secret_required: bool,
+    //Only accept heartbeats for a server from the IP that registered it
+    pub server_bind_ip: bool,
+    //How often a server is handed a new secret, 0 keeps the first one for the server's lifetime
+    pub server_secret_rotation_minutes: u32,
+    //Only list servers whose key a host has registered on the panel, revoked keys are always refused
+    pub require_known_server_key: bool,
+    //Servers that can be listed from one IP and with one key at the same time, 0 for no limit
+    pub max_servers_per_ip: u32,
+    pub max_servers_per_key: u32,
+    //New registrations allowed from one IP per minute, 0 for no limit
+    pub registration_rate_per_minute: u32,
+    //Heartbeats allowed from one IP per minute, shared by every server on it, 0 for no limit
+    pub heartbeat_rate_per_minute: u32,
+    //More registrations than this across all IPs within flood_window_seconds quarantines new servers, 0 disables
+    pub flood_registration_threshold: u32,
+    pub flood_window_seconds: u32,
+    //Token bucket limits per client IP (per /64 for IPv6), a request uses the policy with the longest path prefix
+    //matching whole segments of its path
+    pub rate_limits: Vec<RateLimitPolicy>,
+    //IPs or CIDR ranges that are never rate limited, for trusted infrastructure
+    pub rate_limit_allow_list: Vec<String>,
+    //IPs or CIDR ranges of reverse proxies whose Forwarded, X-Forwarded-For and PROXY protocol headers are believed
+    pub trusted_proxies: Vec<String>,
+    //Serve Prometheus metrics at /metrics
+    pub metrics_enabled: bool,
+    //Listeners that only serve /metrics, when empty it is served on the normal listeners
+    pub metrics_listeners: Vec<ListenerConfig>,
+    //IPs or CIDR ranges allowed to scrape /metrics, empty allows anyone
+    pub metrics_allow_list: Vec<String>,
+    //Default level (trace, debug, info, warn, error), RUST_LOG replaces this and log_filters when set
+    pub log_level: String,
+    //Extra tracing directives such as "sqlx=warn" or "security=info"
+    pub log_filters: Vec<String>,
+    pub log_format: LogFormat,
+    pub log_stdout: bool,
+    //Directory for daily rotated log files, empty disables file logging
+    pub log_directory: String,
+    pub log_file_prefix: String,
+    //Seconds in flight requests get to finish after SIGTERM before they are dropped
+    pub shutdown_grace_seconds: u16,
+    //How often the scrub task wakes to check for expired servers
+    pub scrub_interval_seconds: u16,
+    //Scrub at least this often even if no expired server has been noticed
+    pub scrub_max_interval_seconds: u32,
+    //Up to this many milliseconds are added to each wake up
+    pub scrub_jitter_ms: u32,
+    //Record server sessions and network wide totals for the panel's history pages, needs the database
+    pub history_enabled: bool,
+    //How often recorded heartbeats are written out and a network sample is taken
+    pub history_flush_seconds: u16,
+    //History older than this is deleted, 0 keeps it forever
+    pub history_retention_days: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+//A single address the web server binds to, each listener serves the full app
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct ListenerConfig {
+    pub address: String,
+    pub port: u16,
+    pub tls: bool,
+    pub cert_path: String,
+    pub key_path: String,
+    //Connections start with a PROXY protocol header, only accepted from trusted_proxies
+    pub proxy_protocol: bool,
+    //Mark the session cookie Secure, follows tls when unset. Browsers never send a Secure cookie over plain http,
+    //so only set this on a plain listener that sits behind a TLS terminating proxy
+    pub secure_cookies: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct RateLimitPolicy {
+    pub path_prefix: String,
+    //Requests a client can make at once before being limited
+    pub burst: u32,
+    //Rate the bucket refills at
+    pub per_second: f64,
+}
+
+impl Default for RateLimitPolicy {
+    fn default() -> RateLimitPolicy {
+        RateLimitPolicy {
+            path_prefix: String::from("/"),
+            burst: 60,
+            per_second: 10.0,
+        }
+    }
+}
+
+impl ListenerConfig {
+    pub fn secure_cookies(&self) -> bool {
+        self.secure_cookies.unwrap_or(self.tls)
+    }
+}
+
+impl RateLimitPolicy {
+    fn new(path_prefix: &str, burst: u32, per_second: f64) -> RateLimitPolicy {
+        RateLimitPolicy {
+            path_prefix: path_prefix.to_string(),
+            burst,
+            per_second,
+        }
+    }
+}
+
+impl Default for ListenerConfig {
+    fn default() -> ListenerConfig {
+        ListenerConfig {
+            address: String::from("127.0.0.1"),
+            port: 443,
+            tls: true,
+            cert_path: String::from("cert.pem"),
+            key_path: String::from("key.pem"),
+            proxy_protocol: false,
+            secure_cookies: None,
+        }
+    }
 }
 
 //Creates a blank cfg with default options
@@ -45,6 +201,55 @@ impl Default for Config {
             allowed_sdk_versions: Vec::new(),
             ban_fail_condition: true,
             postgres_connection_uri: Default::default(),
+            require_database: false,
+            database_retry_seconds: 5,
+            listeners: vec![ListenerConfig::default()],
+            tls_reload_interval: 60,
+            session_key: String::new(),
+            session_key_path: String::from("session.key"),
+            session_ttl_minutes: 10,
+            server_side_sessions: false,
+            invite_expiry_hours: 48,
+            totp_required_roles: Vec::new(),
+            login_free_attempts: 3,
+            login_backoff_max_seconds: 300,
+            login_lockout_threshold: 10,
+            login_lockout_minutes: 15,
+            server_secret_required: false,
+            server_bind_ip: false,
+            server_secret_rotation_minutes: 60,
+            require_known_server_key: false,
+            max_servers_per_ip: 10,
+            max_servers_per_key: 0,
+            registration_rate_per_minute: 5,
+            heartbeat_rate_per_minute: 600,
+            flood_registration_threshold: 30,
+            flood_window_seconds: 60,
+            rate_limits: vec![
+                RateLimitPolicy::default(),
+                RateLimitPolicy::new("/servers", 30, 2.0),
+                RateLimitPolicy::new("/banlist", 120, 20.0),
+                RateLimitPolicy::new("/eula", 10, 1.0),
+                RateLimitPolicy::new("/panel", 60, 5.0),
+            ],
+            rate_limit_allow_list: Vec::new(),
+            trusted_proxies: Vec::new(),
+            metrics_enabled: true,
+            metrics_listeners: Vec::new(),
+            metrics_allow_list: vec![String::from("127.0.0.1"), String::from("::1")],
+            log_level: String::from("info"),
+            log_filters: Vec::new(),
+            log_format: LogFormat::Text,
+            log_stdout: true,
+            log_directory: String::new(),
+            log_file_prefix: String::from("ms.log"),
+            shutdown_grace_seconds: 30,
+            scrub_interval_seconds: 5,
+            scrub_max_interval_seconds: 1800,
+            scrub_jitter_ms: 1000,
+            history_enabled: true,
+            history_flush_seconds: 60,
+            history_retention_days: 90,
         }
     }
 }
@@ -66,7 +271,7 @@ impl Config {
                 file.write_all(str.as_bytes())
                     .expect("Failed to write json");
 
-                println!("Cfg file written please check the configuration");
+                info!("Cfg file written please check the configuration");
                 std::process::exit(0);
             } else {
                 panic!("Could not read the cfg file: {:?}", error);
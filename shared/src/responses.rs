@@ -1,5 +1,5 @@
 use {
-    crate::server::{HostInfo, Player, Server, ServerInfo},
+    crate::server::{HostInfo, Player, Server, ServerCommand, ServerInfo},
     serde::{Deserialize, Serialize},
     uuid::Uuid,
 };
@@ -12,6 +12,9 @@ pub struct BanIdentifiers {
     pub ip: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub reason: Option<String>,
+    //Only set for kicks, the id of the kick command to acknowledge once the player has been removed
+    #[serde(default, skip_serializing_if = "Option::is_none", rename = "kickId")]
+    pub kick_id: Option<u64>,
 }
 
 #[derive(Default, Serialize)]
@@ -51,6 +54,9 @@ struct ServerResponse {
     #[serde(skip_serializing_if = "Option::is_none")]
     uid: Option<String>,
 
+    #[serde(skip_serializing_if = "Option::is_none")]
+    commands: Option<Vec<ServerCommand>>,
+
 }
 
 pub fn ms_error_format(str: impl Into<String>) -> String {
@@ -97,10 +103,14 @@ pub fn ms_return_host_info(info: HostInfo) -> String {
     }
 }
 
-pub fn ms_bulk_check_response(identifiers: Vec<BanIdentifiers>) -> String {
+pub fn ms_bulk_check_response(
+    identifiers: Vec<BanIdentifiers>,
+    commands: Vec<ServerCommand>,
+) -> String {
     match serde_json::to_string(&ServerResponse {
         success: true,
         banned_players: Some(identifiers),
+        commands: Some(commands),
         ..Default::default()
     }) {
         Ok(str) => str,
@@ -10,7 +10,117 @@ pub struct ServerInfo {
     pub server: Server,
     pub internal: InternalServerData,
     pub players: Vec<Player>,
-    pub kick_list: Vec<u64>,
+    pub commands: Vec<ServerCommand>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Info,
+    Warning,
+    Critical,
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Critical => "critical",
+        }
+    }
+}
+
+//What a queued command asks the server to do, sent as {"id": .., "type": "broadcast", ..}
+#[derive(Serialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum CommandKind {
+    Broadcast {
+        message: String,
+    },
+    ChangeMap {
+        map: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        playlist: Option<String>,
+    },
+    DelistNotice {
+        reason: String,
+    },
+    Announcement {
+        announcement_id: u64,
+        message: String,
+        severity: Severity,
+    },
+    Kick {
+        player_uid: u64,
+        reason: String,
+        //Unix time until which the player is kicked again if they rejoin
+        cooldown_until: Option<u64>,
+    },
+}
+
+impl CommandKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            CommandKind::Broadcast { .. } => "broadcast",
+            CommandKind::ChangeMap { .. } => "change_map",
+            CommandKind::DelistNotice { .. } => "delist_notice",
+            CommandKind::Announcement { .. } => "announcement",
+            CommandKind::Kick { .. } => "kick",
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            CommandKind::Broadcast { message } => message.clone(),
+            CommandKind::ChangeMap { map, playlist } => match playlist {
+                Some(playlist) => format!("{} ({})", map, playlist),
+                None => map.clone(),
+            },
+            CommandKind::DelistNotice { reason } => reason.clone(),
+            CommandKind::Announcement {
+                message, severity, ..
+            } => format!("[{}] {}", severity.as_str(), message),
+            CommandKind::Kick {
+                player_uid, reason, ..
+            } => format!("{}: {}", player_uid, reason),
+        }
+    }
+}
+
+//A command queued from the panel, resent with every heartbeat and bulk check until the server acknowledges its id
+#[derive(Serialize, Clone)]
+pub struct ServerCommand {
+    pub id: u64,
+    #[serde(flatten)]
+    pub kind: CommandKind,
+    #[serde(skip)]
+    pub issued_by: String,
+    #[serde(skip)]
+    pub issued_at: u64,
+    //Set the first time the command is sent to the server
+    #[serde(skip)]
+    pub delivered_at: Option<u64>,
+    #[serde(skip)]
+    pub acknowledged_at: Option<u64>,
+}
+
+impl ServerCommand {
+    pub fn cooldown_until(&self) -> Option<u64> {
+        match self.kind {
+            CommandKind::Kick { cooldown_until, .. } => cooldown_until,
+            _ => None,
+        }
+    }
+
+    pub fn in_cooldown(&self, time: u64) -> bool {
+        self.cooldown_until().is_some_and(|until| until > time)
+    }
+
+    //Nothing left to send once acknowledged and out of any kick cooldown
+    pub fn is_finished(&self, time: u64) -> bool {
+        self.acknowledged_at.is_some() && !self.in_cooldown(time)
+    }
 }
 
 //This contains the details about an individual player that is on a server
@@ -28,6 +138,20 @@ pub struct ServerWithUID {
     pub server: Server,
     #[serde(rename = "timeStamp")]
     pub time_stamp: u64,
+    //Ids of commands from earlier responses that the server has carried out
+    #[serde(
+        default,
+        rename = "acknowledgedCommands",
+        alias = "acknowledgedKicks",
+        skip_serializing
+    )]
+    pub acknowledged_commands: Vec<u64>,
+    //Handed out on registration, has to be sent with every update after that
+    #[serde(default, skip_serializing)]
+    pub secret: Option<String>,
+    //Challenge a host got when registering the server's key on the panel, posting it proves the key is theirs
+    #[serde(default, rename = "keyChallenge", skip_serializing)]
+    pub key_challenge: Option<String>,
 }
 
 //This is needed as the game needs to know what its own IP is
@@ -39,6 +163,21 @@ pub struct HostInfo {
 
     #[serde(skip_serializing_if = "Option::is_none")]
     pub token: Option<Uuid>,
+
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub commands: Vec<ServerCommand>,
+
+    //Sent on registration and when the secret is rotated, the server should use it from then on
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+
+    //Held for review and not shown on the list yet, the server should keep posting as normal
+    #[serde(skip_serializing_if = "is_false")]
+    pub pending: bool,
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
 }
 
 //This contains details about the server we handle internally
@@ -50,6 +189,19 @@ pub struct InternalServerData {
     pub token: Option<Uuid>,
     #[serde(rename = "timeStamp")]
     pub time_stamp: u64,
+    //Only the hash of the server's secret is kept
+    #[serde(skip)]
+    pub secret_hash: String,
+    //A rotated secret that has been sent out but not used yet, the old one stays valid until it is
+    #[serde(skip)]
+    pub pending_secret: Option<String>,
+    #[serde(skip)]
+    pub secret_issued_at: u64,
+    //Set once the server has sent its secret, after that it always has to
+    #[serde(skip)]
+    pub secret_in_use: bool,
+    //Host account that registered the server's key
+    pub owner: Option<String>,
 }
 
 //Data about the server that the game client needs
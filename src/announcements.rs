@@ -0,0 +1,191 @@
+use {
+    crate::server_list::{current_time, next_command_id},
+    once_cell::sync::Lazy,
+    parking_lot::RwLock,
+    shared::server::{CommandKind, ServerCommand, ServerInfo, Severity},
+    std::{
+        collections::HashSet,
+        sync::atomic::{AtomicU64, Ordering},
+    },
+};
+
+//Finished announcements stay on the panel for a day so their delivery counts can be checked
+const HISTORY_SECONDS: u64 = 24 * 60 * 60;
+
+static ANNOUNCEMENTS: Lazy<Announcements> = Lazy::new(Announcements::default);
+
+pub fn get_announcements() -> &'static Announcements {
+    &ANNOUNCEMENTS
+}
+
+#[derive(Clone)]
+pub struct Announcement {
+    pub id: u64,
+    pub message: String,
+    pub severity: Severity,
+    pub starts_at: u64,
+    pub ends_at: u64,
+    //Empty filters match every server
+    pub versions: Vec<String>,
+    pub playlists: Vec<String>,
+    pub regions: Vec<String>,
+    pub created_by: String,
+    pub created_at: u64,
+    pub cancelled: bool,
+    //Server uids, kept so each server is only handed an announcement once
+    delivered_to: HashSet<String>,
+    acknowledged_by: HashSet<String>,
+}
+
+impl Announcement {
+    pub fn is_active(&self, time: u64) -> bool {
+        !self.cancelled && self.starts_at <= time && time < self.ends_at
+    }
+
+    pub fn status(&self, time: u64) -> &'static str {
+        if self.cancelled {
+            "Cancelled"
+        } else if time < self.starts_at {
+            "Scheduled"
+        } else if time < self.ends_at {
+            "Active"
+        } else {
+            "Ended"
+        }
+    }
+
+    pub fn delivered_count(&self) -> usize {
+        self.delivered_to.len()
+    }
+
+    pub fn acknowledged_count(&self) -> usize {
+        self.acknowledged_by.len()
+    }
+
+    fn matches(&self, server: &ServerInfo) -> bool {
+        let matches = |filter: &[String], value: &str| {
+            filter.is_empty() || filter.iter().any(|item| item.eq_ignore_ascii_case(value))
+        };
+
+        matches(&self.versions, &server.server.version)
+            && matches(&self.playlists, &server.server.playlist)
+            && matches(&self.regions, &server.internal.region)
+    }
+}
+
+pub struct NewAnnouncement {
+    pub message: String,
+    pub severity: Severity,
+    pub starts_at: u64,
+    pub ends_at: u64,
+    pub versions: Vec<String>,
+    pub playlists: Vec<String>,
+    pub regions: Vec<String>,
+}
+
+//Network wide messages, queued as a command on each matching server the next time it posts or bulk checks
+pub struct Announcements {
+    list: RwLock<Vec<Announcement>>,
+    next_id: AtomicU64,
+}
+
+impl Default for Announcements {
+    fn default() -> Announcements {
+        Announcements {
+            list: RwLock::new(Vec::new()),
+            next_id: AtomicU64::new(1),
+        }
+    }
+}
+
+impl Announcements {
+    pub fn create(&self, new: NewAnnouncement, created_by: &str) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let time = current_time();
+
+        let mut list = self.list.write();
+        list.retain(|announcement| announcement.ends_at.saturating_add(HISTORY_SECONDS) > time);
+        list.push(Announcement {
+            id,
+            message: new.message,
+            severity: new.severity,
+            starts_at: new.starts_at,
+            ends_at: new.ends_at,
+            versions: new.versions,
+            playlists: new.playlists,
+            regions: new.regions,
+            created_by: created_by.to_string(),
+            created_at: time,
+            cancelled: false,
+            delivered_to: HashSet::new(),
+            acknowledged_by: HashSet::new(),
+        });
+
+        id
+    }
+
+    //Returns the message of the cancelled announcement, or None if there is no such announcement
+    pub fn cancel(&self, id: u64) -> Option<String> {
+        let mut list = self.list.write();
+        let announcement = list.iter_mut().find(|announcement| announcement.id == id)?;
+        announcement.cancelled = true;
+        Some(announcement.message.clone())
+    }
+
+    //Newest first
+    pub fn all(&self) -> Vec<Announcement> {
+        let mut list = self.list.read().clone();
+        list.reverse();
+        list
+    }
+
+    //Queues every active announcement the server matches and has not been given yet,
+    //and drops unacknowledged ones that were cancelled or ran out in the meantime
+    pub fn deliver(&self, server: &mut ServerInfo, time: u64) {
+        let mut list = self.list.write();
+
+        server.commands.retain(|command| match command.kind {
+            CommandKind::Announcement {
+                announcement_id, ..
+            } if command.acknowledged_at.is_none() => list.iter().any(|announcement| {
+                announcement.id == announcement_id && announcement.is_active(time)
+            }),
+            _ => true,
+        });
+
+        for announcement in list.iter_mut() {
+            if !announcement.is_active(time)
+                || announcement.delivered_to.contains(&server.internal.uid)
+                || !announcement.matches(server)
+            {
+                continue;
+            }
+
+            announcement
+                .delivered_to
+                .insert(server.internal.uid.clone());
+            server.commands.push(ServerCommand {
+                id: next_command_id(),
+                kind: CommandKind::Announcement {
+                    announcement_id: announcement.id,
+                    message: announcement.message.clone(),
+                    severity: announcement.severity,
+                },
+                issued_by: announcement.created_by.clone(),
+                issued_at: time,
+                delivered_at: None,
+                acknowledged_at: None,
+            });
+        }
+    }
+
+    pub fn acknowledged(&self, announcement_id: u64, server_uid: &str) {
+        let mut list = self.list.write();
+        if let Some(announcement) = list
+            .iter_mut()
+            .find(|announcement| announcement.id == announcement_id)
+        {
+            announcement.acknowledged_by.insert(server_uid.to_string());
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use {
+    crate::{
+        client_ip::client_ip_string, database::audit::insert_audit_entry, permissions::PanelUser,
+    },
+    actix_web::{dev::Payload, error, Error, FromRequest, HttpMessage, HttpRequest},
+    tracing::{error, info},
+};
+
+//Who is acting and from where, pulled from the PanelUser ProtectedEndpoint inserted
+pub struct Auditor {
+    actor: String,
+    api_token: Option<String>,
+    ip: String,
+}
+
+impl Auditor {
+    //For actions taken before there is a PanelUser, like logging in
+    pub fn new(actor: &str, ip: &str) -> Auditor {
+        Auditor {
+            actor: actor.to_string(),
+            api_token: None,
+            ip: ip.to_string(),
+        }
+    }
+
+    //Failing to write the entry is logged but never fails the action itself
+    pub async fn record(&self, action: &str, target: Option<&str>, details: serde_json::Value) {
+        info!(
+            target: "audit",
+            actor = %self.actor,
+            api_token = self.api_token.as_deref(),
+            action,
+            target_name = target,
+            details = %details,
+            ip = %self.ip,
+            "Audit"
+        );
+
+        if let Err(err) = insert_audit_entry(
+            &self.actor,
+            self.api_token.as_deref(),
+            action,
+            target,
+            &details,
+            &self.ip,
+        )
+        .await
+        {
+            error!("Failed to write audit entry for '{}': {}", action, err);
+        }
+    }
+}
+
+impl FromRequest for Auditor {
+    type Error = Error;
+    type Future = std::future::Ready<Result<Auditor, Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let ip = client_ip_string(req);
+
+        let auditor = match req.extensions().get::<PanelUser>() {
+            Some(user) => Ok(Auditor {
+                actor: user.username.clone(),
+                api_token: user.api_token.clone(),
+                ip,
+            }),
+            None => Err(error::ErrorUnauthorized("")),
+        };
+
+        std::future::ready(auditor)
+    }
+}
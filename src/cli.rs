@@ -0,0 +1,99 @@
+use {
+    crate::{
+        database::users::{
+            create_user, get_users, reset_password, set_user_disabled, set_user_role, unlock_user,
+        },
+        permissions::Role,
+    },
+    std::str::FromStr,
+};
+
+const USAGE: &str = "Usage:
+    r5r_ms_rs user list
+    r5r_ms_rs user create <username> <viewer|moderator|admin|host>
+    r5r_ms_rs user reset <username>
+    r5r_ms_rs user disable <username>
+    r5r_ms_rs user enable <username>
+    r5r_ms_rs user unlock <username>
+    r5r_ms_rs user role <username> <viewer|moderator|admin|host>";
+
+fn print_invite(username: &str, token: String) {
+    println!(
+        "Invite link for '{}': https://<panel address>/invite/{}",
+        username, token
+    );
+}
+
+fn parse_role(role: Option<&String>) -> Result<Role, String> {
+    match role {
+        Some(role) => Role::from_str(role),
+        None => Err(String::from("No role specified")),
+    }
+}
+
+async fn run_user_command(args: &[String]) -> Result<(), String> {
+    let username = args.get(1);
+
+    match (args.first().map(String::as_str), username) {
+        (Some("list"), _) => {
+            let users = get_users()
+                .await
+                .ok_or_else(|| String::from("Failed to list users"))?;
+
+            println!(
+                "{:<24} {:<10} {:<16} Last login",
+                "Username", "Role", "Status"
+            );
+            for user in users {
+                let status = match (user.disabled, user.locked_until, user.pending_invite) {
+                    (true, _, _) => "disabled",
+                    (false, Some(_), _) => "locked",
+                    (false, None, true) => "invite pending",
+                    (false, None, false) => "active",
+                };
+                let last_login = user
+                    .last_login
+                    .map(|time| time.to_rfc3339())
+                    .unwrap_or_else(|| String::from("never"));
+                println!(
+                    "{:<24} {:<10} {:<16} {}",
+                    user.username, user.role, status, last_login
+                );
+            }
+            Ok(())
+        }
+        (Some("create"), Some(username)) => {
+            let role = parse_role(args.get(2))?;
+            let token = create_user(username, role).await?;
+            print_invite(username, token);
+            Ok(())
+        }
+        (Some("reset"), Some(username)) => {
+            let token = reset_password(username).await?;
+            print_invite(username, token);
+            Ok(())
+        }
+        (Some("disable"), Some(username)) => set_user_disabled(username, true).await,
+        (Some("enable"), Some(username)) => set_user_disabled(username, false).await,
+        (Some("unlock"), Some(username)) => unlock_user(username).await,
+        (Some("role"), Some(username)) => set_user_role(username, parse_role(args.get(2))?).await,
+        _ => Err(String::from(USAGE)),
+    }
+}
+
+//Returns None if there was no command and the server should start as normal, otherwise the exit code
+pub async fn run(args: &[String]) -> Option<i32> {
+    let result = match args.first().map(String::as_str) {
+        None => return None,
+        Some("user") => run_user_command(&args[1..]).await,
+        Some(_) => Err(String::from(USAGE)),
+    };
+
+    match result {
+        Ok(_) => Some(0),
+        Err(err) => {
+            eprintln!("{}", err);
+            Some(1)
+        }
+    }
+}
@@ -0,0 +1,300 @@
+use {
+    actix_web::{http::header::HeaderMap, HttpRequest},
+    ipnet::IpNet,
+    once_cell::sync::Lazy,
+    parking_lot::RwLock,
+    shared::ms_config::get_global_config,
+    std::{
+        collections::HashMap,
+        net::{IpAddr, SocketAddr},
+    },
+    tracing::error,
+};
+
+static TRUSTED_PROXIES: Lazy<Vec<IpNet>> =
+    Lazy::new(|| parse_networks(&get_global_config().trusted_proxies, "trusted_proxies"));
+
+//Connections forwarded from a PROXY protocol listener, keyed by the local address they reach actix from
+static PROXIED_PEERS: Lazy<RwLock<HashMap<SocketAddr, SocketAddr>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+//Accepts single IPs as well as CIDR ranges, bad entries are logged and skipped
+pub fn parse_networks(entries: &[String], setting: &str) -> Vec<IpNet> {
+    entries
+        .iter()
+        .filter_map(|entry| {
+            let parsed = entry
+                .parse::<IpNet>()
+                .or_else(|_| entry.parse::<IpAddr>().map(IpNet::from));
+
+            match parsed {
+                Ok(network) => Some(network),
+                Err(_) => {
+                    error!("Ignoring invalid entry '{}' in {}", entry, setting);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+pub fn is_trusted_proxy(ip: IpAddr) -> bool {
+    TRUSTED_PROXIES.iter().any(|network| network.contains(&ip))
+}
+
+//Removes the mapping when dropped, so it goes however the connection ends
+#[must_use]
+pub struct ProxiedPeer(SocketAddr);
+
+impl Drop for ProxiedPeer {
+    fn drop(&mut self) {
+        PROXIED_PEERS.write().remove(&self.0);
+    }
+}
+
+pub fn register_proxied_peer(local: SocketAddr, client: SocketAddr) -> ProxiedPeer {
+    PROXIED_PEERS.write().insert(local, client);
+    ProxiedPeer(local)
+}
+
+//The address that actually connected, which for a PROXY protocol listener is the one from the header
+fn connected_peer(peer: SocketAddr) -> SocketAddr {
+    PROXIED_PEERS.read().get(&peer).copied().unwrap_or(peer)
+}
+
+//Whether the connection itself came from a trusted proxy, before any forwarding headers are read
+pub fn peer_is_trusted_proxy(req: &HttpRequest) -> bool {
+    req.peer_addr()
+        .is_some_and(|peer| is_trusted_proxy(connected_peer(peer).ip()))
+}
+
+//A single hop from Forwarded or X-Forwarded-For, which may be quoted, bracketed or carry a port
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+
+    if let Ok(ip) = node.parse::<IpAddr>() {
+        return Some(ip);
+    }
+
+    if let Ok(addr) = node.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+
+    //Bracketed IPv6 without a port
+    node.strip_prefix('[')
+        .and_then(|node| node.strip_suffix(']'))
+        .and_then(|node| node.parse().ok())
+}
+
+//Hops in the order they were added, so the closest proxy's entry is last
+fn forwarded_chain(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    let values = |name: &str| -> Vec<String> {
+        headers
+            .get_all(name)
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::to_string)
+            .collect()
+    };
+
+    //Forwarded is the standard, X-Forwarded-For is only read when it is missing
+    let forwarded = values("forwarded");
+    if !forwarded.is_empty() {
+        return forwarded
+            .iter()
+            .map(|element| {
+                element
+                    .split(';')
+                    .filter_map(|pair| pair.split_once('='))
+                    .find(|(key, _)| key.trim().eq_ignore_ascii_case("for"))
+                    .and_then(|(_, value)| parse_node(value))
+            })
+            .collect();
+    }
+
+    values("x-forwarded-for")
+        .iter()
+        .map(|node| parse_node(node))
+        .collect()
+}
+
+//Walks back through the forwarding headers for as long as each hop is a trusted proxy,
+//so a client can not spoof its address by sending the headers itself
+pub fn resolve_client_ip(peer: SocketAddr, headers: &HeaderMap) -> IpAddr {
+    resolve_with(connected_peer(peer).ip(), headers, is_trusted_proxy)
+}
+
+fn resolve_with(peer: IpAddr, headers: &HeaderMap, trusted: impl Fn(IpAddr) -> bool) -> IpAddr {
+    let mut client = peer;
+
+    if !trusted(client) {
+        return client;
+    }
+
+    for hop in forwarded_chain(headers).into_iter().rev() {
+        match hop {
+            Some(ip) => client = ip,
+            //An unreadable hop ends the chain at the last proxy we could vouch for
+            None => break,
+        }
+
+        if !trusted(client) {
+            break;
+        }
+    }
+
+    client
+}
+
+//The IP to use for anything about the client, rate limits, bans, logs and server registration
+pub fn client_ip(req: &HttpRequest) -> Option<IpAddr> {
+    req.peer_addr()
+        .map(|peer| resolve_client_ip(peer, req.headers()))
+}
+
+pub fn client_ip_string(req: &HttpRequest) -> String {
+    client_ip(req).map(|ip| ip.to_string()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        actix_web::http::header::{HeaderName, HeaderValue},
+    };
+
+    fn header_map(entries: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in entries {
+            headers.append(
+                HeaderName::from_static(name),
+                HeaderValue::from_static(value),
+            );
+        }
+        headers
+    }
+
+    fn forwarded(value: &'static str) -> HeaderMap {
+        header_map(&[("forwarded", value)])
+    }
+
+    fn trusted(ip: IpAddr) -> bool {
+        let networks = parse_networks(
+            &[String::from("10.0.0.0/8"), String::from("::1")],
+            "trusted_proxies",
+        );
+        networks.iter().any(|network| network.contains(&ip))
+    }
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn untrusted_peer_can_not_spoof_forwarded_for() {
+        let headers = header_map(&[("x-forwarded-for", "1.2.3.4")]);
+        assert_eq!(
+            resolve_with(ip("203.0.113.9"), &headers, trusted),
+            ip("203.0.113.9")
+        );
+
+        let headers = forwarded("for=1.2.3.4");
+        assert_eq!(
+            resolve_with(ip("203.0.113.9"), &headers, trusted),
+            ip("203.0.113.9")
+        );
+    }
+
+    #[test]
+    fn trusted_peer_without_headers_is_the_client() {
+        assert_eq!(
+            resolve_with(ip("10.0.0.1"), &HeaderMap::new(), trusted),
+            ip("10.0.0.1")
+        );
+    }
+
+    #[test]
+    fn walks_back_through_trusted_hops_only() {
+        //The client claims 6.6.6.6, the untrusted hop before our proxies is where it really came from
+        let headers = header_map(&[("x-forwarded-for", "6.6.6.6, 198.51.100.7, 10.0.0.2")]);
+        assert_eq!(
+            resolve_with(ip("10.0.0.1"), &headers, trusted),
+            ip("198.51.100.7")
+        );
+
+        //Every hop trusted ends at the first one
+        let headers = header_map(&[("x-forwarded-for", "10.1.1.1, 10.0.0.2")]);
+        assert_eq!(
+            resolve_with(ip("10.0.0.1"), &headers, trusted),
+            ip("10.1.1.1")
+        );
+    }
+
+    #[test]
+    fn forwarded_for_across_several_headers() {
+        let headers = header_map(&[
+            ("x-forwarded-for", "6.6.6.6"),
+            ("x-forwarded-for", "198.51.100.7, 10.0.0.2"),
+        ]);
+        assert_eq!(
+            resolve_with(ip("10.0.0.1"), &headers, trusted),
+            ip("198.51.100.7")
+        );
+    }
+
+    #[test]
+    fn unreadable_hop_stops_at_the_last_trusted_proxy() {
+        let headers = header_map(&[("x-forwarded-for", "198.51.100.7, garbage, 10.0.0.2")]);
+        assert_eq!(
+            resolve_with(ip("10.0.0.1"), &headers, trusted),
+            ip("10.0.0.2")
+        );
+    }
+
+    #[test]
+    fn forwarded_takes_precedence_over_x_forwarded_for() {
+        let headers = header_map(&[
+            ("forwarded", "for=198.51.100.7"),
+            ("x-forwarded-for", "6.6.6.6"),
+        ]);
+        assert_eq!(
+            resolve_with(ip("10.0.0.1"), &headers, trusted),
+            ip("198.51.100.7")
+        );
+    }
+
+    #[test]
+    fn forwarded_values_in_every_form() {
+        let cases = [
+            ("for=198.51.100.7", "198.51.100.7"),
+            ("For=\"198.51.100.7\"", "198.51.100.7"),
+            ("for=\"198.51.100.7:4711\"", "198.51.100.7"),
+            ("for=\"[2001:db8::1]\"", "2001:db8::1"),
+            ("for=\"[2001:db8::1]:4711\"", "2001:db8::1"),
+            ("proto=https;for=198.51.100.7;by=10.0.0.1", "198.51.100.7"),
+            ("for=198.51.100.7, for=10.0.0.2;proto=http", "198.51.100.7"),
+        ];
+
+        for (value, expected) in cases {
+            let headers = forwarded(value);
+            assert_eq!(
+                resolve_with(ip("10.0.0.1"), &headers, trusted),
+                ip(expected),
+                "{}",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn obfuscated_forwarded_node_is_not_trusted_past() {
+        let headers = forwarded("for=_hidden, for=10.0.0.2");
+        assert_eq!(
+            resolve_with(ip("10.0.0.1"), &headers, trusted),
+            ip("10.0.0.2")
+        );
+
+        let headers = forwarded("for=unknown");
+        assert_eq!(resolve_with(ip("::1"), &headers, trusted), ip("::1"));
+    }
+}
@@ -0,0 +1,33 @@
+use {
+    once_cell::sync::OnceCell,
+    ring::{
+        digest,
+        rand::{SecureRandom, SystemRandom},
+    },
+};
+
+static SYSTEM_RANDOM: OnceCell<SystemRandom> = OnceCell::new();
+
+pub fn get_system_random() -> &'static SystemRandom {
+    SYSTEM_RANDOM.get_or_init(SystemRandom::new)
+}
+
+pub fn random_bytes(len: usize) -> Option<Vec<u8>> {
+    let mut bytes = vec![0u8; len];
+    get_system_random().fill(&mut bytes).ok()?;
+    Some(bytes)
+}
+
+//Hex encoded random token, used for anything handed out that we later need to check
+pub fn random_token(len: usize) -> Option<String> {
+    random_bytes(len).map(hex::encode)
+}
+
+//Tokens and keys are only ever stored as a hash of themselves
+pub fn sha256_hex(data: &str) -> String {
+    hex::encode(digest::digest(&digest::SHA256, data.as_bytes()))
+}
+
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
@@ -0,0 +1,127 @@
+use {
+    crate::{
+        crypto::{random_token, sha256_hex},
+        get_master_server,
+        permissions::Permission,
+    },
+    anyhow::anyhow,
+    chrono::{DateTime, Utc},
+    serde::Serialize,
+    sqlx::{Pool, Postgres},
+    std::str::FromStr,
+    tracing::error,
+};
+
+//Makes leaked tokens easy to spot in logs and secret scanners
+pub const TOKEN_PREFIX: &str = "ms_";
+
+#[derive(sqlx::FromRow, Serialize)]
+pub struct ApiTokenRow {
+    pub id: i32,
+    pub name: String,
+    pub owner: String,
+    pub permissions: Vec<String>,
+    pub created_on: DateTime<Utc>,
+    pub expires_on: Option<DateTime<Utc>>,
+    pub last_used: Option<DateTime<Utc>>,
+    pub last_used_ip: Option<String>,
+}
+
+#[derive(sqlx::FromRow)]
+struct UsedToken {
+    name: String,
+    owner: String,
+    permissions: Vec<String>,
+}
+
+fn get_pool() -> anyhow::Result<&'static Pool<Postgres>> {
+    match &get_master_server().postgres_pool {
+        Some(pool) => Ok(pool),
+        None => Err(anyhow!("Could not get database pool")),
+    }
+}
+
+//Returns the token, only its hash is stored so this is the one chance to hand it out
+pub async fn create_api_token(
+    name: &str,
+    owner: &str,
+    permissions: &[Permission],
+    expires_on: Option<DateTime<Utc>>,
+) -> anyhow::Result<String> {
+    if name.trim().is_empty() {
+        return Err(anyhow!("Token name can not be empty"));
+    }
+
+    let token = format!(
+        "{}{}",
+        TOKEN_PREFIX,
+        random_token(32).ok_or_else(|| anyhow!("Failed to generate token"))?
+    );
+    let permissions: Vec<&str> = permissions.iter().map(Permission::as_str).collect();
+
+    sqlx::query(
+        "INSERT INTO api_tokens(name, token_hash, owner, permissions, expires_on) VALUES ($1, $2, $3, $4, $5)",
+    )
+    .bind(name.trim())
+    .bind(sha256_hex(&token))
+    .bind(owner)
+    .bind(permissions)
+    .bind(expires_on)
+    .execute(get_pool()?)
+    .await?;
+
+    Ok(token)
+}
+
+//Looks up a presented token and records the use, returns the token name, owner and permissions
+pub async fn use_api_token(
+    token: &str,
+    ip: &str,
+) -> anyhow::Result<Option<(String, String, Vec<Permission>)>> {
+    let row = sqlx::query_as::<_, UsedToken>(
+        "UPDATE api_tokens SET last_used = now(), last_used_ip = $2 WHERE token_hash = $1 AND NOT revoked AND (expires_on IS NULL OR expires_on > now()) RETURNING name, owner, permissions",
+    )
+    .bind(sha256_hex(token))
+    .bind(ip)
+    .fetch_optional(get_pool()?)
+    .await?;
+
+    Ok(row.map(|row| {
+        //Unknown names are dropped rather than failing the token, so removing a permission is safe
+        let permissions = row
+            .permissions
+            .iter()
+            .filter_map(|permission| match Permission::from_str(permission) {
+                Ok(permission) => Some(permission),
+                Err(err) => {
+                    error!("API token '{}': {}", row.name, err);
+                    None
+                }
+            })
+            .collect();
+
+        (row.name, row.owner, permissions)
+    }))
+}
+
+pub async fn get_api_tokens() -> anyhow::Result<Vec<ApiTokenRow>> {
+    let rows = sqlx::query_as::<_, ApiTokenRow>(
+        "SELECT id, name, owner, permissions, created_on, expires_on, last_used, last_used_ip FROM api_tokens WHERE NOT revoked ORDER BY created_on DESC",
+    )
+    .fetch_all(get_pool()?)
+    .await?;
+
+    Ok(rows)
+}
+
+//Returns the name of the revoked token
+pub async fn revoke_api_token(id: i32) -> anyhow::Result<Option<String>> {
+    let name = sqlx::query_scalar::<_, String>(
+        "UPDATE api_tokens SET revoked = true WHERE id = $1 AND NOT revoked RETURNING name",
+    )
+    .bind(id)
+    .fetch_optional(get_pool()?)
+    .await?;
+
+    Ok(name)
+}
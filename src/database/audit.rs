@@ -0,0 +1,143 @@
+use {
+    crate::get_master_server,
+    anyhow::anyhow,
+    chrono::{DateTime, Utc},
+    serde::Serialize,
+    sqlx::{Pool, Postgres, QueryBuilder},
+};
+
+#[derive(sqlx::FromRow, Serialize)]
+pub struct AuditRow {
+    pub id: i64,
+    pub occurred_on: DateTime<Utc>,
+    pub actor: String,
+    pub api_token: Option<String>,
+    pub action: String,
+    pub target: Option<String>,
+    pub details: String,
+    pub ip: String,
+}
+
+//Every field is optional, empty strings are treated the same as missing
+#[derive(Default)]
+pub struct AuditFilter {
+    pub actor: Option<String>,
+    pub action: Option<String>,
+    pub target: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+fn get_pool() -> anyhow::Result<&'static Pool<Postgres>> {
+    match &get_master_server().postgres_pool {
+        Some(pool) => Ok(pool),
+        None => Err(anyhow!("Could not get database pool")),
+    }
+}
+
+fn non_empty(value: &Option<String>) -> Option<&str> {
+    value.as_deref().filter(|value| !value.is_empty())
+}
+
+//Search text is matched literally, LIKE wildcards in it are escaped
+fn escape_like(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '\\' | '%' | '_') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn push_filter(query: &mut QueryBuilder<Postgres>, filter: &AuditFilter) {
+    query.push(" WHERE true");
+
+    if let Some(actor) = non_empty(&filter.actor) {
+        query.push(" AND actor = ").push_bind(actor.to_string());
+    }
+    if let Some(action) = non_empty(&filter.action) {
+        query.push(" AND action = ").push_bind(action.to_string());
+    }
+    if let Some(target) = non_empty(&filter.target) {
+        query
+            .push(" AND target ILIKE ")
+            .push_bind(format!("%{}%", escape_like(target)))
+            .push(" ESCAPE '\\'");
+    }
+    if let Some(since) = filter.since {
+        query.push(" AND occurred_on >= ").push_bind(since);
+    }
+    if let Some(until) = filter.until {
+        query.push(" AND occurred_on < ").push_bind(until);
+    }
+}
+
+pub async fn insert_audit_entry(
+    actor: &str,
+    api_token: Option<&str>,
+    action: &str,
+    target: Option<&str>,
+    details: &serde_json::Value,
+    ip: &str,
+) -> anyhow::Result<()> {
+    sqlx::query(
+        "INSERT INTO audit_log(actor, api_token, action, target, details, ip) VALUES ($1, $2, $3, $4, $5::jsonb, $6)",
+    )
+    .bind(actor)
+    .bind(api_token)
+    .bind(action)
+    .bind(target)
+    .bind(details.to_string())
+    .bind(ip)
+    .execute(get_pool()?)
+    .await?;
+
+    Ok(())
+}
+
+//Newest first
+pub async fn get_audit_entries(
+    filter: &AuditFilter,
+    limit: i64,
+    offset: i64,
+) -> anyhow::Result<Vec<AuditRow>> {
+    let mut query = QueryBuilder::new(
+        "SELECT id, occurred_on, actor, api_token, action, target, details::text AS details, ip FROM audit_log",
+    );
+    push_filter(&mut query, filter);
+    query
+        .push(" ORDER BY id DESC LIMIT ")
+        .push_bind(limit)
+        .push(" OFFSET ")
+        .push_bind(offset);
+
+    let rows = query
+        .build_query_as::<AuditRow>()
+        .fetch_all(get_pool()?)
+        .await?;
+
+    Ok(rows)
+}
+
+pub async fn count_audit_entries(filter: &AuditFilter) -> anyhow::Result<i64> {
+    let mut query = QueryBuilder::new("SELECT count(*) FROM audit_log");
+    push_filter(&mut query, filter);
+
+    let count = query
+        .build_query_scalar::<i64>()
+        .fetch_one(get_pool()?)
+        .await?;
+
+    Ok(count)
+}
+
+pub async fn get_audit_actions() -> anyhow::Result<Vec<String>> {
+    let actions =
+        sqlx::query_scalar::<_, String>("SELECT DISTINCT action FROM audit_log ORDER BY action")
+            .fetch_all(get_pool()?)
+            .await?;
+
+    Ok(actions)
+}
@@ -0,0 +1,56 @@
+use {
+    crate::get_master_server,
+    anyhow::anyhow,
+    chrono::{DateTime, Utc},
+    serde::Serialize,
+    sqlx::{Pool, Postgres},
+};
+
+//Failures older than this are removed whenever a new one is recorded
+const RETENTION_DAYS: i32 = 30;
+
+#[derive(sqlx::FromRow, Serialize)]
+pub struct LoginFailureRow {
+    pub username: String,
+    pub ip: String,
+    pub reason: String,
+    pub attempted_on: DateTime<Utc>,
+}
+
+fn get_pool() -> anyhow::Result<&'static Pool<Postgres>> {
+    match &get_master_server().postgres_pool {
+        Some(pool) => Ok(pool),
+        None => Err(anyhow!("Could not get database pool")),
+    }
+}
+
+pub async fn record_login_failure(username: &str, ip: &str, reason: &str) -> anyhow::Result<()> {
+    let pool = get_pool()?;
+
+    sqlx::query(
+        "DELETE FROM login_failures WHERE attempted_on < now() - make_interval(days => $1)",
+    )
+    .bind(RETENTION_DAYS)
+    .execute(pool)
+    .await?;
+
+    sqlx::query("INSERT INTO login_failures(username, ip, reason) VALUES ($1, $2, $3)")
+        .bind(username)
+        .bind(ip)
+        .bind(reason)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn get_recent_login_failures(limit: i64) -> anyhow::Result<Vec<LoginFailureRow>> {
+    let rows = sqlx::query_as::<_, LoginFailureRow>(
+        "SELECT username, ip, reason, attempted_on FROM login_failures ORDER BY attempted_on DESC LIMIT $1",
+    )
+    .bind(limit)
+    .fetch_all(get_pool()?)
+    .await?;
+
+    Ok(rows)
+}
@@ -1,18 +1,26 @@
+pub mod api_tokens;
+pub mod audit;
+pub mod login_failures;
+pub mod server_history;
+pub mod server_keys;
+pub mod sessions;
+pub mod users;
+
 use {
     crate::get_master_server,
-    argon2::{
-        password_hash::{
-            rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString,
-        },
-        Argon2,
-    },
     chrono::{NaiveDateTime, Utc},
     serde::Serialize,
     shared::{ms_config::get_global_config, responses::BanIdentifiers, utils::format_ip_to_ipv6},
     sqlx::{postgres::PgPoolOptions, types::chrono, Pool, Postgres},
+    std::{
+        sync::atomic::{AtomicBool, Ordering},
+        time::Duration,
+    },
     tracing::{debug, error, info},
 };
 
+static MIGRATED: AtomicBool = AtomicBool::new(false);
+
 pub enum BanInfo {
     Banned(String),
     NotBanned,
@@ -34,97 +42,109 @@ pub struct EULAData {
     pub contents: String,
 }
 
-#[derive(sqlx::FromRow)]
-struct User {
-    pw_hash: String,
-}
-
 pub async fn init_postgres_pool() -> Option<Pool<Postgres>> {
-    if get_global_config().postgres_connection_uri.is_empty() {
+    let cfg = get_global_config();
+
+    if cfg.postgres_connection_uri.is_empty() {
+        if cfg.require_database {
+            panic!("require_database is set but postgres_connection_uri is empty");
+        }
         return None;
     };
 
+    //Created without connecting so the listeners still come up while the database is down,
+    //wait_for_database keeps trying it and /readyz fails until it answers
+    if cfg.require_database {
+        return match PgPoolOptions::new()
+            .max_connections(15)
+            .connect_lazy(&cfg.postgres_connection_uri)
+        {
+            Ok(pool) => Some(pool),
+            Err(err) => panic!("Invalid postgres_connection_uri: {}", err),
+        };
+    }
+
     let pool = PgPoolOptions::new()
         .max_connections(15)
-        .connect(&get_global_config().postgres_connection_uri)
+        .connect(&cfg.postgres_connection_uri)
         .await;
 
-    match pool {
-        Ok(pool) => Some(pool),
+    let pool = match pool {
+        Ok(pool) => pool,
         Err(err) => {
             error!("Failed to init db pool {}", err);
+            return None;
+        }
+    };
+
+    match run_migrations(&pool).await {
+        Ok(()) => Some(pool),
+        Err(err) => {
+            error!("Failed to apply database migrations {}", err);
             None
         }
     }
 }
 
-pub async fn website_auth(username: &String, password: String) -> bool {
-    if username.is_empty() || password.is_empty() {
-        debug!("No ip or id provided as an identifier");
-        return false;
-    };
+//Brings the schema up to date with migrations/, every migration only runs once
+pub async fn run_migrations(pool: &Pool<Postgres>) -> Result<(), String> {
+    sqlx::migrate!()
+        .run(pool)
+        .await
+        .map_err(|err| err.to_string())?;
 
-    let pool = match &get_master_server().postgres_pool {
-        Some(pool) => pool,
-        None => {
-            error!("Could not get database pool");
-            return false;
-        }
-    };
+    MIGRATED.store(true, Ordering::Relaxed);
+    Ok(())
+}
 
-    let res = sqlx::query_as::<_, User>("SELECT * FROM users WHERE username = $1")
-        .bind(username.clone())
-        .fetch_all(pool)
-        .await;
+pub fn is_migrated() -> bool {
+    MIGRATED.load(Ordering::Relaxed)
+}
 
-    let user = match res {
-        Ok(result) => result,
-        Err(err) => {
-            debug!("Error while processing user lookup: {}", err);
-            return false;
-        }
+//Returns once the database answers and is migrated, retrying every database_retry_seconds
+pub async fn wait_for_database() {
+    let pool = match &get_master_server().postgres_pool {
+        Some(pool) => pool,
+        None => return,
     };
 
-    if user.is_empty() {
-        debug!("Tried to auth '{}' but user didnt exist", username);
-        return false;
-    }
-
-    if user[0].pw_hash == "placeholder" {
-        let argon2 = Argon2::default();
-        let salt = SaltString::generate(&mut OsRng);
+    let retry = Duration::from_secs(get_global_config().database_retry_seconds.max(1) as u64);
 
-        let pw_hash = match argon2.hash_password(password.as_bytes(), &salt) {
-            Ok(hash) => hash,
-            Err(_) => return false,
+    loop {
+        let result = match ping_database(retry).await {
+            Ok(()) if is_migrated() => Ok(()),
+            Ok(()) => run_migrations(pool)
+                .await
+                .map_err(|err| format!("migrations failed: {}", err)),
+            Err(err) => Err(err),
         };
 
-        match sqlx::query("UPDATE users SET pw_hash = $1 WHERE username = $2")
-            .bind(pw_hash.to_string())
-            .bind(username)
-            .execute(pool)
-            .await
-        {
-            Ok(updated) => {
-                if updated.rows_affected() != 0 {
-                    return true;
-                }
-            }
+        match result {
+            Ok(()) => return,
             Err(err) => {
-                debug!("Error when updating placeholder pw: {}", err);
-                return false;
+                error!(
+                    "Database is not ready ({}), retrying in {} seconds",
+                    err,
+                    retry.as_secs()
+                );
+                tokio::time::sleep(retry).await;
             }
         }
     }
+}
 
-    let hash = match PasswordHash::new(&user[0].pw_hash) {
-        Ok(hash) => hash,
-        Err(_) => return false,
+//Round trip to the database, for readiness checks
+pub async fn ping_database(timeout: Duration) -> Result<(), String> {
+    let pool = match &get_master_server().postgres_pool {
+        Some(pool) => pool,
+        None => return Err(String::from("Not connected")),
     };
 
-    Argon2::default()
-        .verify_password(password.as_bytes(), &hash)
-        .is_ok()
+    match tokio::time::timeout(timeout, sqlx::query("SELECT 1").execute(pool)).await {
+        Ok(Ok(_)) => Ok(()),
+        Ok(Err(err)) => Err(err.to_string()),
+        Err(_) => Err(String::from("Timed out")),
+    }
 }
 
 pub async fn ban_identifier(identifier: String, reason: String, unban_date: Option<u64>) -> Result<bool, String> {
@@ -162,7 +182,7 @@ pub async fn ban_identifier(identifier: String, reason: String, unban_date: Opti
     match result {
         Ok(res) => Ok(res.rows_affected() != 0),
         Err(err) => {
-            println!("err {}", err);
+            error!("Failed to insert ban: {}", err);
             Err(format!("Database Error: {}", err))
         }
     }
@@ -296,25 +316,26 @@ pub async fn search_for_ban(identifier: String) -> Option<Vec<BanRows>> {
     }
 }
 
-pub async fn unban(key: i32) -> bool {
+//Returns the removed ban so the caller can still say who it was for, None if there was no such ban
+pub async fn unban(key: i32) -> Result<Option<BanRows>, String> {
     let pool = match &get_master_server().postgres_pool {
         Some(pool) => pool,
         None => {
             error!("Could not get database pool");
-            return false;
+            return Err(String::from("Could not get database pool"));
         }
     };
 
-    let response = sqlx::query("DELETE FROM bans WHERE ban_id = $1")
+    let response = sqlx::query_as::<_, BanRows>("DELETE FROM bans WHERE ban_id = $1 RETURNING *")
         .bind(key)
-        .execute(pool)
+        .fetch_optional(pool)
         .await;
 
     match response {
-        Ok(_) => true,
+        Ok(ban) => Ok(ban),
         Err(err) => {
-            error!("Failed to ban player: {}", err);
-            false
+            error!("Failed to unban player: {}", err);
+            Err(format!("Database Error: {}", err))
         }
     }
 }
@@ -0,0 +1,152 @@
+use {
+    crate::get_master_server,
+    anyhow::anyhow,
+    chrono::{DateTime, Utc},
+    serde::Serialize,
+    sqlx::{Pool, Postgres, QueryBuilder},
+};
+
+//Keeps each insert well under the bind parameter limit
+const UPSERT_BATCH: usize = 500;
+
+#[derive(sqlx::FromRow, Serialize, Clone)]
+pub struct ServerSessionRow {
+    pub uid: String,
+    pub server_key: String,
+    pub name: String,
+    pub address: String,
+    pub hidden: bool,
+    pub owner: Option<String>,
+    pub first_seen: DateTime<Utc>,
+    pub last_heartbeat: DateTime<Utc>,
+    pub ended: bool,
+    pub heartbeats: i64,
+    pub player_total: i64,
+    pub peak_players: i32,
+    pub maps: Vec<String>,
+    pub playlists: Vec<String>,
+}
+
+impl ServerSessionRow {
+    pub fn average_players(&self) -> f64 {
+        match self.heartbeats {
+            0 => 0.0,
+            heartbeats => self.player_total as f64 / heartbeats as f64,
+        }
+    }
+}
+
+#[derive(sqlx::FromRow, Serialize)]
+pub struct NetworkSampleRow {
+    pub sampled_on: DateTime<Utc>,
+    pub servers: i64,
+    pub players: i64,
+}
+
+fn get_pool() -> anyhow::Result<&'static Pool<Postgres>> {
+    match &get_master_server().postgres_pool {
+        Some(pool) => Ok(pool),
+        None => Err(anyhow!("Could not get database pool")),
+    }
+}
+
+//Sessions are written with their running totals, so a later write for the same uid replaces the earlier one
+pub async fn upsert_server_sessions(sessions: &[ServerSessionRow]) -> anyhow::Result<()> {
+    for batch in sessions.chunks(UPSERT_BATCH) {
+        let mut query = QueryBuilder::new(
+            "INSERT INTO server_sessions(uid, server_key, name, address, hidden, owner, first_seen, last_heartbeat, ended, heartbeats, player_total, peak_players, maps, playlists) ",
+        );
+
+        query.push_values(batch, |mut row, session| {
+            row.push_bind(session.uid.clone())
+                .push_bind(session.server_key.clone())
+                .push_bind(session.name.clone())
+                .push_bind(session.address.clone())
+                .push_bind(session.hidden)
+                .push_bind(session.owner.clone())
+                .push_bind(session.first_seen)
+                .push_bind(session.last_heartbeat)
+                .push_bind(session.ended)
+                .push_bind(session.heartbeats)
+                .push_bind(session.player_total)
+                .push_bind(session.peak_players)
+                .push_bind(session.maps.clone())
+                .push_bind(session.playlists.clone());
+        });
+
+        query.push(
+            " ON CONFLICT (uid) DO UPDATE SET name = EXCLUDED.name, address = EXCLUDED.address, hidden = EXCLUDED.hidden, owner = EXCLUDED.owner, last_heartbeat = EXCLUDED.last_heartbeat, ended = EXCLUDED.ended, heartbeats = EXCLUDED.heartbeats, player_total = EXCLUDED.player_total, peak_players = EXCLUDED.peak_players, maps = EXCLUDED.maps, playlists = EXCLUDED.playlists",
+        );
+
+        query.build().execute(get_pool()?).await?;
+    }
+
+    Ok(())
+}
+
+pub async fn insert_network_sample(servers: i32, players: i32) -> anyhow::Result<()> {
+    sqlx::query("INSERT INTO network_samples(servers, players) VALUES ($1, $2) ON CONFLICT (sampled_on) DO NOTHING")
+        .bind(servers)
+        .bind(players)
+        .execute(get_pool()?)
+        .await?;
+
+    Ok(())
+}
+
+//Sessions left open by a crash would otherwise look like they are still running
+pub async fn end_open_sessions() -> anyhow::Result<u64> {
+    let result = sqlx::query("UPDATE server_sessions SET ended = true WHERE NOT ended")
+        .execute(get_pool()?)
+        .await?;
+
+    Ok(result.rows_affected())
+}
+
+pub async fn delete_history_before(before: DateTime<Utc>) -> anyhow::Result<()> {
+    sqlx::query("DELETE FROM server_sessions WHERE last_heartbeat < $1")
+        .bind(before)
+        .execute(get_pool()?)
+        .await?;
+
+    sqlx::query("DELETE FROM network_samples WHERE sampled_on < $1")
+        .bind(before)
+        .execute(get_pool()?)
+        .await?;
+
+    Ok(())
+}
+
+//Sessions that were running at any point in the window, newest first
+pub async fn get_server_sessions(
+    server_key: Option<&str>,
+    since: DateTime<Utc>,
+    limit: i64,
+) -> anyhow::Result<Vec<ServerSessionRow>> {
+    let rows = sqlx::query_as::<_, ServerSessionRow>(
+        "SELECT uid, server_key, name, address, hidden, owner, first_seen, last_heartbeat, ended, heartbeats, player_total, peak_players, maps, playlists FROM server_sessions WHERE last_heartbeat >= $1 AND ($2::text IS NULL OR server_key = $2) ORDER BY first_seen DESC LIMIT $3",
+    )
+    .bind(since)
+    .bind(server_key)
+    .bind(limit)
+    .fetch_all(get_pool()?)
+    .await?;
+
+    Ok(rows)
+}
+
+//Samples averaged into buckets of bucket_seconds so long ranges stay a sensible size
+pub async fn get_network_samples(
+    since: DateTime<Utc>,
+    bucket_seconds: i64,
+) -> anyhow::Result<Vec<NetworkSampleRow>> {
+    let rows = sqlx::query_as::<_, NetworkSampleRow>(
+        "SELECT to_timestamp(floor(extract(epoch FROM sampled_on) / $2) * $2) AS sampled_on, round(avg(servers))::bigint AS servers, round(avg(players))::bigint AS players FROM network_samples WHERE sampled_on >= $1 GROUP BY 1 ORDER BY 1",
+    )
+    .bind(since)
+    .bind(bucket_seconds as f64)
+    .fetch_all(get_pool()?)
+    .await?;
+
+    Ok(rows)
+}
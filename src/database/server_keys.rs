@@ -0,0 +1,266 @@
+use {
+    crate::{
+        crypto::{random_token, sha256_hex},
+        get_master_server,
+    },
+    anyhow::anyhow,
+    chrono::{DateTime, Utc},
+    once_cell::sync::Lazy,
+    parking_lot::Mutex,
+    serde::Serialize,
+    sqlx::{Pool, Postgres},
+    std::{
+        collections::HashMap,
+        time::{Duration, Instant},
+    },
+    tracing::{error, info},
+};
+
+//Every heartbeat looks up its key, this keeps that off the database. Revokes on this instance apply at once,
+//ones made on another instance sharing the database within this long
+const KEY_STATUS_CACHE_TIME: Duration = Duration::from_secs(60);
+//Past this many keys expired entries are dropped, if that is not enough nothing new is cached
+const MAX_CACHED_KEYS: usize = 10_000;
+
+static KEY_STATUS_CACHE: Lazy<Mutex<HashMap<String, (Instant, KeyStatus)>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+#[derive(sqlx::FromRow, Serialize)]
+pub struct ServerKeyRow {
+    pub id: i32,
+    pub name: String,
+    pub server_key: String,
+    pub owner: String,
+    pub created_on: DateTime<Utc>,
+    pub revoked_on: Option<DateTime<Utc>>,
+    pub revoked_by: Option<String>,
+    pub revoke_reason: Option<String>,
+    pub verified_on: Option<DateTime<Utc>>,
+}
+
+#[derive(sqlx::FromRow, Serialize)]
+pub struct HostNotificationRow {
+    pub id: i32,
+    pub created_on: DateTime<Utc>,
+    pub message: String,
+    pub read: bool,
+}
+
+//What a posted server key maps to, a key with only unverified claims is Unknown
+#[derive(Clone)]
+pub enum KeyStatus {
+    Owned(String),
+    Revoked,
+    Unknown,
+}
+
+fn get_pool() -> anyhow::Result<&'static Pool<Postgres>> {
+    match &get_master_server().postgres_pool {
+        Some(pool) => Ok(pool),
+        None => Err(anyhow!("Could not get database pool")),
+    }
+}
+
+//Returns the challenge a server using the key has to post before the key is the owner's.
+//Registering a key again while it is unverified hands out a new challenge
+pub async fn register_server_key(
+    name: &str,
+    server_key: &str,
+    owner: &str,
+) -> anyhow::Result<String> {
+    if name.trim().is_empty() || server_key.trim().is_empty() {
+        return Err(anyhow!("Name and key can not be empty"));
+    }
+
+    let challenge = random_token(16).ok_or_else(|| anyhow!("Failed to generate challenge"))?;
+
+    let result = sqlx::query("INSERT INTO server_keys(name, server_key, owner, challenge_hash) SELECT $1, $2, $3, $4 WHERE NOT EXISTS (SELECT 1 FROM server_keys WHERE server_key = $2 AND (verified_on IS NOT NULL OR revoked_on IS NOT NULL)) ON CONFLICT (server_key, owner) WHERE verified_on IS NULL AND revoked_on IS NULL DO UPDATE SET name = EXCLUDED.name, challenge_hash = EXCLUDED.challenge_hash, created_on = now()")
+        .bind(name.trim())
+        .bind(server_key.trim())
+        .bind(owner)
+        .bind(sha256_hex(&challenge))
+        .execute(get_pool()?)
+        .await?;
+
+    match result.rows_affected() {
+        0 => Err(anyhow!("That key is already registered")),
+        _ => Ok(challenge),
+    }
+}
+
+//A server posting the right challenge proves the claiming host runs it, every other claim on the key is dropped
+async fn verify_server_key(server_key: &str, challenge: &str) -> anyhow::Result<Option<String>> {
+    let owner = sqlx::query_scalar::<_, String>(
+        "WITH verified AS (UPDATE server_keys SET verified_on = now(), challenge_hash = NULL WHERE server_key = $1 AND challenge_hash = $2 AND verified_on IS NULL AND revoked_on IS NULL RETURNING id, owner), dropped AS (DELETE FROM server_keys WHERE server_key = $1 AND verified_on IS NULL AND revoked_on IS NULL AND EXISTS (SELECT 1 FROM verified) AND id NOT IN (SELECT id FROM verified)) SELECT owner FROM verified",
+    )
+    .bind(server_key)
+    .bind(sha256_hex(challenge))
+    .fetch_optional(get_pool()?)
+    .await?;
+
+    if let Some(owner) = &owner {
+        info!(
+            target: "security",
+            event = "server_key_verified",
+            owner = %owner,
+            "Server key ownership verified"
+        );
+        notify_owner(
+            Some(owner),
+            &format!("Key '{}' is verified, its servers are now listed as yours", server_key),
+        )
+        .await;
+    }
+
+    Ok(owner)
+}
+
+//challenge is what the server posted as keyChallenge, if any
+pub async fn get_key_status(server_key: &str, challenge: Option<&str>) -> anyhow::Result<KeyStatus> {
+    if let Some(status) = cached_key_status(server_key) {
+        //An unknown key posting a challenge still has to go to the database to be verified
+        if challenge.is_none() || !matches!(status, KeyStatus::Unknown) {
+            return Ok(status);
+        }
+    }
+
+    let status = lookup_key_status(server_key, challenge).await?;
+    cache_key_status(server_key, status.clone());
+    Ok(status)
+}
+
+fn cached_key_status(server_key: &str) -> Option<KeyStatus> {
+    let cache = KEY_STATUS_CACHE.lock();
+    let (cached_at, status) = cache.get(server_key)?;
+
+    match cached_at.elapsed() < KEY_STATUS_CACHE_TIME {
+        true => Some(status.clone()),
+        false => None,
+    }
+}
+
+fn cache_key_status(server_key: &str, status: KeyStatus) {
+    let mut cache = KEY_STATUS_CACHE.lock();
+
+    if cache.len() >= MAX_CACHED_KEYS && !cache.contains_key(server_key) {
+        cache.retain(|_, (cached_at, _)| cached_at.elapsed() < KEY_STATUS_CACHE_TIME);
+        if cache.len() >= MAX_CACHED_KEYS {
+            return;
+        }
+    }
+
+    cache.insert(server_key.to_string(), (Instant::now(), status));
+}
+
+async fn lookup_key_status(server_key: &str, challenge: Option<&str>) -> anyhow::Result<KeyStatus> {
+    let row = sqlx::query_as::<_, (String, bool)>(
+        "SELECT owner, revoked_on IS NOT NULL FROM server_keys WHERE server_key = $1 AND (verified_on IS NOT NULL OR revoked_on IS NOT NULL)",
+    )
+    .bind(server_key)
+    .fetch_optional(get_pool()?)
+    .await?;
+
+    match row {
+        Some((_, true)) => return Ok(KeyStatus::Revoked),
+        Some((owner, false)) => return Ok(KeyStatus::Owned(owner)),
+        None => {}
+    }
+
+    let verified = match challenge {
+        Some(challenge) => verify_server_key(server_key, challenge).await?,
+        None => None,
+    };
+
+    Ok(match verified {
+        Some(owner) => KeyStatus::Owned(owner),
+        None => KeyStatus::Unknown,
+    })
+}
+
+//Every key when owner is None
+pub async fn get_server_keys(owner: Option<&str>) -> anyhow::Result<Vec<ServerKeyRow>> {
+    let rows = sqlx::query_as::<_, ServerKeyRow>(
+        "SELECT id, name, server_key, owner, created_on, revoked_on, revoked_by, revoke_reason, verified_on FROM server_keys WHERE $1::text IS NULL OR owner = $1 ORDER BY revoked_on IS NOT NULL, created_on DESC",
+    )
+    .bind(owner)
+    .fetch_all(get_pool()?)
+    .await?;
+
+    Ok(rows)
+}
+
+pub async fn get_server_key(id: i32) -> anyhow::Result<Option<ServerKeyRow>> {
+    let row = sqlx::query_as::<_, ServerKeyRow>(
+        "SELECT id, name, server_key, owner, created_on, revoked_on, revoked_by, revoke_reason, verified_on FROM server_keys WHERE id = $1",
+    )
+    .bind(id)
+    .fetch_optional(get_pool()?)
+    .await?;
+
+    Ok(row)
+}
+
+//Returns the revoked key, None if it does not exist or was already revoked
+pub async fn revoke_server_key(
+    id: i32,
+    revoked_by: &str,
+    reason: &str,
+) -> anyhow::Result<Option<ServerKeyRow>> {
+    let row = sqlx::query_as::<_, ServerKeyRow>(
+        "UPDATE server_keys SET revoked_on = now(), revoked_by = $2, revoke_reason = $3 WHERE id = $1 AND revoked_on IS NULL RETURNING id, name, server_key, owner, created_on, revoked_on, revoked_by, revoke_reason, verified_on",
+    )
+    .bind(id)
+    .bind(revoked_by)
+    .bind(reason)
+    .fetch_optional(get_pool()?)
+    .await?;
+
+    if let Some(row) = &row {
+        cache_key_status(&row.server_key, KeyStatus::Revoked);
+    }
+
+    Ok(row)
+}
+
+pub async fn notify_host(owner: &str, message: &str) -> anyhow::Result<()> {
+    sqlx::query("INSERT INTO host_notifications(owner, message) VALUES ($1, $2)")
+        .bind(owner)
+        .bind(message)
+        .execute(get_pool()?)
+        .await?;
+
+    Ok(())
+}
+
+//For moderation actions on a server, failing to notify never fails the action
+pub async fn notify_owner(owner: Option<&str>, message: &str) {
+    if let Some(owner) = owner {
+        if let Err(err) = notify_host(owner, message).await {
+            error!("Failed to notify host '{}': {}", owner, err);
+        }
+    }
+}
+
+pub async fn get_host_notifications(
+    owner: &str,
+    limit: i64,
+) -> anyhow::Result<Vec<HostNotificationRow>> {
+    let rows = sqlx::query_as::<_, HostNotificationRow>(
+        "SELECT id, created_on, message, read FROM host_notifications WHERE owner = $1 ORDER BY created_on DESC LIMIT $2",
+    )
+    .bind(owner)
+    .bind(limit)
+    .fetch_all(get_pool()?)
+    .await?;
+
+    Ok(rows)
+}
+
+pub async fn mark_host_notifications_read(owner: &str) -> anyhow::Result<()> {
+    sqlx::query("UPDATE host_notifications SET read = true WHERE owner = $1 AND NOT read")
+        .bind(owner)
+        .execute(get_pool()?)
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,168 @@
+use {
+    crate::{crypto::sha256_hex, get_master_server},
+    anyhow::anyhow,
+    chrono::{DateTime, Utc},
+    serde::Serialize,
+    sqlx::{Pool, Postgres},
+    tracing::error,
+};
+
+#[derive(sqlx::FromRow, Serialize)]
+pub struct SessionRow {
+    pub session_id: i32,
+    pub username: Option<String>,
+    pub created_on: DateTime<Utc>,
+    pub expires_on: DateTime<Utc>,
+}
+
+fn get_pool() -> anyhow::Result<&'static Pool<Postgres>> {
+    match &get_master_server().postgres_pool {
+        Some(pool) => Ok(pool),
+        None => Err(anyhow!("Could not get database pool")),
+    }
+}
+
+fn expiry_from_ttl(ttl_seconds: i64) -> DateTime<Utc> {
+    Utc::now() + chrono::Duration::seconds(ttl_seconds)
+}
+
+pub async fn load_session(key: &str) -> anyhow::Result<Option<String>> {
+    let state = sqlx::query_scalar::<_, String>(
+        "SELECT state FROM sessions WHERE key_hash = $1 AND expires_on > now()",
+    )
+    .bind(sha256_hex(key))
+    .fetch_optional(get_pool()?)
+    .await?;
+
+    Ok(state)
+}
+
+pub async fn insert_session(
+    key: &str,
+    username: Option<String>,
+    state: String,
+    ttl_seconds: i64,
+) -> anyhow::Result<()> {
+    let pool = get_pool()?;
+
+    //Piggyback cleanup of expired sessions on new sessions being created
+    if let Err(err) = sqlx::query("DELETE FROM sessions WHERE expires_on < now()")
+        .execute(pool)
+        .await
+    {
+        error!("Failed to remove expired sessions: {}", err);
+    }
+
+    sqlx::query(
+        "INSERT INTO sessions(key_hash, username, state, expires_on) VALUES ($1, $2, $3, $4)",
+    )
+    .bind(sha256_hex(key))
+    .bind(username)
+    .bind(state)
+    .bind(expiry_from_ttl(ttl_seconds))
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+//Returns false if the session no longer exists, eg it was revoked
+pub async fn update_session(
+    key: &str,
+    username: Option<String>,
+    state: String,
+    ttl_seconds: i64,
+) -> anyhow::Result<bool> {
+    let result = sqlx::query(
+        "UPDATE sessions SET username = $2, state = $3, expires_on = $4 WHERE key_hash = $1",
+    )
+    .bind(sha256_hex(key))
+    .bind(username)
+    .bind(state)
+    .bind(expiry_from_ttl(ttl_seconds))
+    .execute(get_pool()?)
+    .await?;
+
+    Ok(result.rows_affected() != 0)
+}
+
+pub async fn update_session_ttl(key: &str, ttl_seconds: i64) -> anyhow::Result<()> {
+    sqlx::query("UPDATE sessions SET expires_on = $2 WHERE key_hash = $1")
+        .bind(sha256_hex(key))
+        .bind(expiry_from_ttl(ttl_seconds))
+        .execute(get_pool()?)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn delete_session(key: &str) -> anyhow::Result<()> {
+    sqlx::query("DELETE FROM sessions WHERE key_hash = $1")
+        .bind(sha256_hex(key))
+        .execute(get_pool()?)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn get_active_sessions() -> Option<Vec<SessionRow>> {
+    let pool = match get_pool() {
+        Ok(pool) => pool,
+        Err(err) => {
+            error!("{}", err);
+            return None;
+        }
+    };
+
+    let query = sqlx::query_as::<_, SessionRow>(
+        "SELECT session_id, username, created_on, expires_on FROM sessions WHERE expires_on > now() ORDER BY created_on DESC",
+    )
+    .fetch_all(pool)
+    .await;
+
+    match query {
+        Ok(rows) => Some(rows),
+        Err(err) => {
+            error!("Failed to list sessions: {}", err);
+            None
+        }
+    }
+}
+
+pub async fn revoke_session(session_id: i32) -> bool {
+    let pool = match get_pool() {
+        Ok(pool) => pool,
+        Err(err) => {
+            error!("{}", err);
+            return false;
+        }
+    };
+
+    match sqlx::query("DELETE FROM sessions WHERE session_id = $1")
+        .bind(session_id)
+        .execute(pool)
+        .await
+    {
+        Ok(res) => res.rows_affected() != 0,
+        Err(err) => {
+            error!("Failed to revoke session: {}", err);
+            false
+        }
+    }
+}
+
+//Only affects server side sessions, cookie sessions are rejected by the per request user lookup instead
+pub async fn revoke_user_sessions(username: &str) {
+    let pool = match get_pool() {
+        Ok(pool) => pool,
+        Err(_) => return,
+    };
+
+    if let Err(err) = sqlx::query("DELETE FROM sessions WHERE username = $1")
+        .bind(username)
+        .execute(pool)
+        .await
+    {
+        error!("Failed to revoke sessions for '{}': {}", username, err);
+    }
+}
@@ -0,0 +1,538 @@
+use {
+    crate::{
+        crypto::{random_token, sha256_hex},
+        database::sessions::revoke_user_sessions,
+        get_master_server,
+        permissions::{PanelUser, Role},
+        totp,
+    },
+    argon2::{
+        password_hash::{
+            rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString,
+        },
+        Argon2,
+    },
+    chrono::{DateTime, Utc},
+    once_cell::sync::Lazy,
+    serde::Serialize,
+    shared::ms_config::get_global_config,
+    sqlx::{Pool, Postgres},
+    std::str::FromStr,
+    tracing::{debug, error},
+};
+
+pub const MIN_PASSWORD_LENGTH: usize = 8;
+
+//Verified against when there is no real hash so a login takes as long whether or not the account exists
+static DUMMY_HASH: Lazy<String> = Lazy::new(|| {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(b"dummy password", &salt)
+        .map(|hash| hash.to_string())
+        .unwrap_or_default()
+});
+
+#[derive(sqlx::FromRow)]
+struct User {
+    pw_hash: Option<String>,
+    disabled: bool,
+    locked: Option<bool>,
+}
+
+#[derive(sqlx::FromRow)]
+struct PanelUserRow {
+    role: String,
+    totp_enabled: bool,
+}
+
+#[derive(sqlx::FromRow)]
+struct TotpState {
+    totp_secret: Option<String>,
+    totp_last_step: Option<i64>,
+}
+
+#[derive(sqlx::FromRow, Serialize)]
+pub struct UserRow {
+    pub username: String,
+    pub role: String,
+    pub disabled: bool,
+    pub created_on: DateTime<Utc>,
+    pub last_login: Option<DateTime<Utc>>,
+    pub pending_invite: bool,
+    pub totp_enabled: bool,
+    pub locked_until: Option<DateTime<Utc>>,
+}
+
+fn get_pool() -> Result<&'static Pool<Postgres>, String> {
+    match &get_master_server().postgres_pool {
+        Some(pool) => Ok(pool),
+        None => {
+            let err_str = String::from("Could not get DB Pool");
+            error!(err_str);
+            Err(err_str)
+        }
+    }
+}
+
+fn hash_password(password: &str) -> Result<String, String> {
+    if password.len() < MIN_PASSWORD_LENGTH {
+        return Err(format!(
+            "Password must be at least {} characters",
+            MIN_PASSWORD_LENGTH
+        ));
+    }
+
+    let salt = SaltString::generate(&mut OsRng);
+    match Argon2::default().hash_password(password.as_bytes(), &salt) {
+        Ok(hash) => Ok(hash.to_string()),
+        Err(_) => Err(String::from("Failed to hash password")),
+    }
+}
+
+fn verify_password(password: &str, pw_hash: &str) -> bool {
+    let hash = match PasswordHash::new(pw_hash) {
+        Ok(hash) => hash,
+        Err(_) => return false,
+    };
+
+    Argon2::default()
+        .verify_password(password.as_bytes(), &hash)
+        .is_ok()
+}
+
+fn burn_verify(password: &str) {
+    verify_password(password, &DUMMY_HASH);
+}
+
+fn invite_expiry() -> DateTime<Utc> {
+    Utc::now() + chrono::Duration::hours(get_global_config().invite_expiry_hours as i64)
+}
+
+//Why a panel login was rejected, recorded in the login_failures table
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LoginFailure {
+    MissingCredentials,
+    UnknownUser,
+    Disabled,
+    Locked,
+    BadPassword,
+    BadTotp,
+    Error,
+}
+
+impl LoginFailure {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LoginFailure::MissingCredentials => "missing_credentials",
+            LoginFailure::UnknownUser => "unknown_user",
+            LoginFailure::Disabled => "disabled",
+            LoginFailure::Locked => "locked",
+            LoginFailure::BadPassword => "bad_password",
+            LoginFailure::BadTotp => "bad_totp",
+            LoginFailure::Error => "error",
+        }
+    }
+}
+
+//Checks the password without touching any login state
+pub async fn check_password(username: &String, password: &str) -> Result<(), LoginFailure> {
+    if username.is_empty() || password.is_empty() {
+        debug!("No username or password provided");
+        return Err(LoginFailure::MissingCredentials);
+    };
+
+    let pool = get_pool().map_err(|_| LoginFailure::Error)?;
+
+    let res = sqlx::query_as::<_, User>(
+        "SELECT pw_hash, disabled, locked_until > now() AS locked FROM users WHERE username = $1",
+    )
+    .bind(username)
+    .fetch_optional(pool)
+    .await;
+
+    let user = match res {
+        Ok(Some(user)) => user,
+        Ok(None) => {
+            debug!("Tried to auth '{}' but user didnt exist", username);
+            burn_verify(password);
+            return Err(LoginFailure::UnknownUser);
+        }
+        Err(err) => {
+            debug!("Error while processing user lookup: {}", err);
+            return Err(LoginFailure::Error);
+        }
+    };
+
+    if user.disabled {
+        burn_verify(password);
+        return Err(LoginFailure::Disabled);
+    }
+
+    //Checked before the password so a locked account gives nothing away
+    if user.locked.unwrap_or(false) {
+        burn_verify(password);
+        return Err(LoginFailure::Locked);
+    }
+
+    //No hash means the account has not accepted its invite yet
+    let authed = match user.pw_hash {
+        Some(pw_hash) => verify_password(password, &pw_hash),
+        None => {
+            burn_verify(password);
+            false
+        }
+    };
+
+    match authed {
+        true => Ok(()),
+        false => Err(LoginFailure::BadPassword),
+    }
+}
+
+pub async fn website_auth(username: &String, password: String) -> Result<(), LoginFailure> {
+    check_password(username, &password).await?;
+
+    let pool = get_pool().map_err(|_| LoginFailure::Error)?;
+    if let Err(err) = sqlx::query("UPDATE users SET last_login = now() WHERE username = $1")
+        .bind(username)
+        .execute(pool)
+        .await
+    {
+        error!("Failed to update last login for '{}': {}", username, err);
+    }
+
+    Ok(())
+}
+
+pub async fn lock_user(username: &str, minutes: u32) -> Result<(), String> {
+    let pool = get_pool()?;
+
+    match sqlx::query("UPDATE users SET locked_until = $2 WHERE username = $1")
+        .bind(username)
+        .bind(Utc::now() + chrono::Duration::minutes(minutes as i64))
+        .execute(pool)
+        .await
+    {
+        Ok(res) if res.rows_affected() != 0 => Ok(()),
+        Ok(_) => Err(String::from("User not found")),
+        Err(err) => Err(format!("Database Error: {}", err)),
+    }
+}
+
+pub async fn unlock_user(username: &str) -> Result<(), String> {
+    let pool = get_pool()?;
+
+    match sqlx::query("UPDATE users SET locked_until = NULL WHERE username = $1")
+        .bind(username)
+        .execute(pool)
+        .await
+    {
+        Ok(res) if res.rows_affected() != 0 => Ok(()),
+        Ok(_) => Err(String::from("User not found")),
+        Err(err) => Err(format!("Database Error: {}", err)),
+    }
+}
+
+pub fn totp_required_for(role: Role) -> bool {
+    get_global_config()
+        .totp_required_roles
+        .iter()
+        .any(|required| required == role.as_str())
+}
+
+pub async fn get_panel_user(username: &String) -> Option<PanelUser> {
+    let pool = get_pool().ok()?;
+
+    let row = sqlx::query_as::<_, PanelUserRow>(
+        "SELECT role, totp_secret IS NOT NULL AS totp_enabled FROM users WHERE username = $1 AND NOT disabled AND pw_hash IS NOT NULL",
+    )
+    .bind(username)
+    .fetch_optional(pool)
+    .await;
+
+    match row {
+        Ok(Some(row)) => match Role::from_str(&row.role) {
+            Ok(role) if !row.totp_enabled && totp_required_for(role) => {
+                Some(PanelUser::pending_totp_enrolment(username.clone(), role))
+            }
+            Ok(role) => Some(PanelUser::new(username.clone(), role)),
+            Err(err) => {
+                error!("User '{}' has an invalid role: {}", username, err);
+                None
+            }
+        },
+        Ok(None) => None,
+        Err(err) => {
+            error!("Error while looking up panel user: {}", err);
+            None
+        }
+    }
+}
+
+pub async fn get_users() -> Option<Vec<UserRow>> {
+    let pool = get_pool().ok()?;
+
+    let query = sqlx::query_as::<_, UserRow>(
+        "SELECT username, role, disabled, created_on, last_login, invite_hash IS NOT NULL AS pending_invite, totp_secret IS NOT NULL AS totp_enabled, CASE WHEN locked_until > now() THEN locked_until END AS locked_until FROM users ORDER BY username",
+    )
+    .fetch_all(pool)
+    .await;
+
+    match query {
+        Ok(rows) => Some(rows),
+        Err(err) => {
+            error!("Failed to list users: {}", err);
+            None
+        }
+    }
+}
+
+//Returns the invite token, only its hash is stored so this is the one chance to hand it out
+pub async fn create_user(username: &str, role: Role) -> Result<String, String> {
+    if username.trim().is_empty() {
+        return Err(String::from("Username can not be empty"));
+    }
+
+    let pool = get_pool()?;
+    let token = random_token(32).ok_or_else(|| String::from("Failed to generate invite token"))?;
+
+    let result = sqlx::query(
+        "INSERT INTO users(username, role, invite_hash, invite_expires) VALUES ($1, $2, $3, $4) ON CONFLICT DO NOTHING",
+    )
+    .bind(username.trim())
+    .bind(role.as_str())
+    .bind(sha256_hex(&token))
+    .bind(invite_expiry())
+    .execute(pool)
+    .await;
+
+    match result {
+        Ok(res) if res.rows_affected() != 0 => Ok(token),
+        Ok(_) => Err(String::from("User already exists")),
+        Err(err) => Err(format!("Database Error: {}", err)),
+    }
+}
+
+//Clears the password and issues a new one time token, the user can not log in until it is used
+pub async fn reset_password(username: &str) -> Result<String, String> {
+    let pool = get_pool()?;
+    let token = random_token(32).ok_or_else(|| String::from("Failed to generate reset token"))?;
+
+    let result = sqlx::query(
+        "UPDATE users SET pw_hash = NULL, invite_hash = $2, invite_expires = $3 WHERE username = $1",
+    )
+    .bind(username)
+    .bind(sha256_hex(&token))
+    .bind(invite_expiry())
+    .execute(pool)
+    .await;
+
+    match result {
+        Ok(res) if res.rows_affected() != 0 => {
+            revoke_user_sessions(username).await;
+            Ok(token)
+        }
+        Ok(_) => Err(String::from("User not found")),
+        Err(err) => Err(format!("Database Error: {}", err)),
+    }
+}
+
+pub async fn get_invite_username(token: &str) -> Option<String> {
+    let pool = get_pool().ok()?;
+
+    sqlx::query_scalar::<_, String>(
+        "SELECT username FROM users WHERE invite_hash = $1 AND invite_expires > now() AND NOT disabled",
+    )
+    .bind(sha256_hex(token))
+    .fetch_optional(pool)
+    .await
+    .unwrap_or_else(|err| {
+        error!("Failed to look up invite: {}", err);
+        None
+    })
+}
+
+//Sets the password for an invite or reset token and consumes it, returns the username
+pub async fn accept_invite(token: &str, password: &str) -> Result<String, String> {
+    let pool = get_pool()?;
+    let pw_hash = hash_password(password)?;
+
+    let result = sqlx::query_scalar::<_, String>(
+        "UPDATE users SET pw_hash = $2, invite_hash = NULL, invite_expires = NULL WHERE invite_hash = $1 AND invite_expires > now() AND NOT disabled RETURNING username",
+    )
+    .bind(sha256_hex(token))
+    .bind(pw_hash)
+    .fetch_optional(pool)
+    .await;
+
+    match result {
+        Ok(Some(username)) => Ok(username),
+        Ok(None) => Err(String::from("Invite is invalid or has expired")),
+        Err(err) => Err(format!("Database Error: {}", err)),
+    }
+}
+
+//The current password has to be checked with check_password first, every session of the user is revoked
+pub async fn change_password(username: &str, new_password: &str) -> Result<(), String> {
+    let pool = get_pool()?;
+    let pw_hash = hash_password(new_password)?;
+
+    match sqlx::query("UPDATE users SET pw_hash = $2 WHERE username = $1")
+        .bind(username)
+        .bind(pw_hash)
+        .execute(pool)
+        .await
+    {
+        Ok(res) if res.rows_affected() != 0 => {
+            revoke_user_sessions(username).await;
+            Ok(())
+        }
+        Ok(_) => Err(String::from("User not found")),
+        Err(err) => Err(format!("Database Error: {}", err)),
+    }
+}
+
+pub async fn set_user_disabled(username: &str, disabled: bool) -> Result<(), String> {
+    let pool = get_pool()?;
+
+    match sqlx::query("UPDATE users SET disabled = $2 WHERE username = $1")
+        .bind(username)
+        .bind(disabled)
+        .execute(pool)
+        .await
+    {
+        Ok(res) if res.rows_affected() != 0 => {
+            if disabled {
+                revoke_user_sessions(username).await;
+            }
+            Ok(())
+        }
+        Ok(_) => Err(String::from("User not found")),
+        Err(err) => Err(format!("Database Error: {}", err)),
+    }
+}
+
+pub async fn set_user_role(username: &str, role: Role) -> Result<(), String> {
+    let pool = get_pool()?;
+
+    match sqlx::query("UPDATE users SET role = $2 WHERE username = $1")
+        .bind(username)
+        .bind(role.as_str())
+        .execute(pool)
+        .await
+    {
+        Ok(res) if res.rows_affected() != 0 => Ok(()),
+        Ok(_) => Err(String::from("User not found")),
+        Err(err) => Err(format!("Database Error: {}", err)),
+    }
+}
+
+pub async fn has_totp(username: &String) -> bool {
+    let pool = match get_pool() {
+        Ok(pool) => pool,
+        Err(_) => return false,
+    };
+
+    sqlx::query_scalar::<_, bool>("SELECT totp_secret IS NOT NULL FROM users WHERE username = $1")
+        .bind(username)
+        .fetch_optional(pool)
+        .await
+        .ok()
+        .flatten()
+        .unwrap_or(false)
+}
+
+//Accepts either a current TOTP code or one of the unused recovery codes, both are single use
+pub async fn verify_totp(username: &String, code: &str) -> bool {
+    let pool = match get_pool() {
+        Ok(pool) => pool,
+        Err(_) => return false,
+    };
+
+    let state = sqlx::query_as::<_, TotpState>(
+        "SELECT totp_secret, totp_last_step FROM users WHERE username = $1",
+    )
+    .bind(username)
+    .fetch_optional(pool)
+    .await;
+
+    let (secret, last_step) = match state {
+        Ok(Some(TotpState {
+            totp_secret: Some(secret),
+            totp_last_step,
+        })) => (secret, totp_last_step),
+        Ok(_) => return false,
+        Err(err) => {
+            error!("Failed to look up totp state for '{}': {}", username, err);
+            return false;
+        }
+    };
+
+    let result = match totp::verify_code(&secret, code, last_step) {
+        //The step check is repeated in the query so two requests racing with the same code cant both pass
+        Some(step) => sqlx::query(
+            "UPDATE users SET totp_last_step = $2 WHERE username = $1 AND (totp_last_step IS NULL OR totp_last_step < $2)",
+        )
+        .bind(username)
+        .bind(step),
+        None => sqlx::query(
+            "UPDATE users SET totp_recovery_hashes = array_remove(totp_recovery_hashes, $2) WHERE username = $1 AND $2 = ANY(totp_recovery_hashes)",
+        )
+        .bind(username)
+        .bind(sha256_hex(&totp::normalise_recovery_code(code))),
+    };
+
+    match result.execute(pool).await {
+        Ok(res) => res.rows_affected() != 0,
+        Err(err) => {
+            error!("Failed to verify totp for '{}': {}", username, err);
+            false
+        }
+    }
+}
+
+//Returns the recovery codes, like invite tokens they are only stored hashed
+pub async fn enable_totp(
+    username: &String,
+    secret: &str,
+    step: i64,
+) -> Result<Vec<String>, String> {
+    let pool = get_pool()?;
+    let codes = totp::generate_recovery_codes()
+        .ok_or_else(|| String::from("Failed to generate recovery codes"))?;
+    let hashes: Vec<String> = codes
+        .iter()
+        .map(|code| sha256_hex(&totp::normalise_recovery_code(code)))
+        .collect();
+
+    match sqlx::query(
+        "UPDATE users SET totp_secret = $2, totp_last_step = $3, totp_recovery_hashes = $4 WHERE username = $1",
+    )
+    .bind(username)
+    .bind(secret)
+    .bind(step)
+    .bind(hashes)
+    .execute(pool)
+    .await
+    {
+        Ok(res) if res.rows_affected() != 0 => Ok(codes),
+        Ok(_) => Err(String::from("User not found")),
+        Err(err) => Err(format!("Database Error: {}", err)),
+    }
+}
+
+pub async fn disable_totp(username: &str) -> Result<(), String> {
+    let pool = get_pool()?;
+
+    match sqlx::query(
+        "UPDATE users SET totp_secret = NULL, totp_last_step = NULL, totp_recovery_hashes = '{}' WHERE username = $1",
+    )
+    .bind(username)
+    .execute(pool)
+    .await
+    {
+        Ok(res) if res.rows_affected() != 0 => Ok(()),
+        Ok(_) => Err(String::from("User not found")),
+        Err(err) => Err(format!("Database Error: {}", err)),
+    }
+}
@@ -3,13 +3,19 @@ use shared::responses::ms_error_format;
 
 use {
     crate::{
+        client_ip::client_ip_string,
         database::{check_identifier, BanInfo},
         get_master_server,
+        metrics::get_metrics,
+        server_list::{
+            authenticate_server, current_time, kick_cooldown_reason, process_bulk_check,
+            ServerAuthError,
+        },
     },
     actix_web::{
         post, web,
         web::{scope, ServiceConfig},
-        Error, HttpResponse,
+        Error, HttpRequest, HttpResponse,
     },
     serde::Deserialize,
     shared::{
@@ -17,13 +23,21 @@ use {
         responses::{ms_bulk_check_response, BanIdentifiers},
         server::Player,
     },
-    tracing::info_span,
+    std::time::Instant,
+    tracing::{info_span, warn},
 };
 
 #[derive(Deserialize)]
 pub struct BulkCheckRequest {
     pub uid: String,
     pub players: Vec<BanIdentifiers>,
+    //Ids of commands (including kicks) from earlier responses that the server has carried out,
+    //servers built before the command queue send them as acknowledgedKicks
+    #[serde(default, rename = "acknowledgedCommands", alias = "acknowledgedKicks")]
+    pub acknowledged_commands: Vec<u64>,
+    //Same secret as heartbeats, the check sets the server's player list
+    #[serde(default)]
+    pub secret: Option<String>,
 }
 
 pub fn ban_routes(cfg: &mut ServiceConfig) {
@@ -44,12 +58,30 @@ pub async fn is_banned(
     );
     let _span = span.enter();
 
-    if is_banned_request.0.uid.is_none() || get_master_server().server_list.does_server_exist(is_banned_request.0.uid.as_ref().unwrap())
-    {
-        return Err(error::ErrorUnauthorized(ms_error_format("Unlisted Server")))
+    //A player kicked with a cooldown is turned away from that server until it runs out
+    let player_id = is_banned_request.0.id;
+    let cooldown = match &is_banned_request.0.uid {
+        Some(uid) => get_master_server().server_list.with_listed_server(uid, |server| {
+            player_id.and_then(|id| kick_cooldown_reason(server, id))
+        }),
+        None => None,
+    };
+
+    //Only servers on the list may check players
+    let cooldown = match cooldown {
+        Some(cooldown) => cooldown,
+        None => return Err(error::ErrorUnauthorized(ms_error_format("Unlisted Server"))),
+    };
+
+    if let Some(reason) = cooldown {
+        return Ok(HttpResponse::Ok().body(ms_is_banned_response(true, Some(reason))));
     }
 
-    match check_identifier(&is_banned_request.0).await {
+    let started = Instant::now();
+    let ban_info = check_identifier(&is_banned_request.0).await;
+    get_metrics().ban_check("single", started.elapsed().as_secs_f64());
+
+    match ban_info {
         BanInfo::Banned(reason) => {
             Ok(HttpResponse::Ok().body(ms_is_banned_response(true, Some(reason))))
         }
@@ -58,8 +90,13 @@ pub async fn is_banned(
 }
 
 #[post("/bulkCheck")]
-pub async fn bulk_check(request: web::Json<BulkCheckRequest>) -> Result<HttpResponse, Error> {
+pub async fn bulk_check(
+    req: HttpRequest,
+    request: web::Json<BulkCheckRequest>,
+) -> Result<HttpResponse, Error> {
+    let ip = client_ip_string(&req);
     let mut ban_vector: Vec<BanIdentifiers> = Vec::with_capacity(request.0.players.len());
+    let mut commands = Vec::new();
     let lists = get_master_server().server_list.clone();
 
     if !lists.does_server_exist(&request.0.uid)
@@ -83,25 +120,39 @@ pub async fn bulk_check(request: web::Json<BulkCheckRequest>) -> Result<HttpResp
                 })
             }
 
-            list.with_upgraded(|list| {
+            let authenticated: Result<(), ServerAuthError> = list.with_upgraded(|list| {
+                let secret = request.0.secret.as_deref();
+                authenticate_server(&mut list[index], secret, &ip, current_time())?;
+
+                let acknowledged = &request.0.acknowledged_commands;
+                let (kicks, pending) = process_bulk_check(&mut list[index], acknowledged, &players);
+                ban_vector.extend(kicks);
+                commands = pending;
                 list[index].players = players;
-                for kick in list[index].kick_list.iter() {
-                    ban_vector.push(BanIdentifiers {
-                        uid: None,
-                        id: Some(*kick),
-                        ip: None,
-                        reason: Some(String::from("Kicked from server")),
-                    })
-                }
-                list[index].kick_list.clear();
+                Ok(())
             });
 
+            if let Err(reason) = authenticated {
+                warn!(
+                    target: "security",
+                    event = "bulk_check_rejected",
+                    reason = reason.as_str(),
+                    uid = %request.0.uid,
+                    "Rejected bulk check"
+                );
+                return Err(error::ErrorForbidden(ms_error_format(reason.message())));
+            }
+
             break;
         }
     }
 
     for mut player in request.0.players {
-        match check_identifier(&player).await {
+        let started = Instant::now();
+        let ban_info = check_identifier(&player).await;
+        get_metrics().ban_check("bulk", started.elapsed().as_secs_f64());
+
+        match ban_info {
             BanInfo::Banned(reason) => {
                 player.reason = Some(reason);
                 ban_vector.push(player);
@@ -110,5 +161,5 @@ pub async fn bulk_check(request: web::Json<BulkCheckRequest>) -> Result<HttpResp
         }
     }
 
-    Ok(HttpResponse::Ok().body(ms_bulk_check_response(ban_vector)))
+    Ok(HttpResponse::Ok().body(ms_bulk_check_response(ban_vector, commands)))
 }
@@ -0,0 +1,104 @@
+use {
+    crate::{
+        database::{is_migrated, ping_database},
+        get_master_server,
+        server_list::current_time,
+        shutdown::is_shutting_down,
+    },
+    actix_web::{get, HttpResponse},
+    once_cell::sync::Lazy,
+    parking_lot::Mutex,
+    serde_json::json,
+    shared::ms_config::{get_global_config, GLOBAL_CONFIG},
+    std::{
+        sync::atomic::Ordering,
+        time::{Duration, Instant},
+    },
+    tracing::warn,
+};
+
+const DATABASE_TIMEOUT: Duration = Duration::from_secs(2);
+//The endpoint is public, so repeated calls reuse the last database check instead of each hitting the database
+const DATABASE_CHECK_CACHE_TIME: Duration = Duration::from_secs(5);
+//Missing this many scrub wake ups in a row means the task has died or is stuck
+const SCRUB_MISSED_WAKE_UPS: u64 = 6;
+
+static LAST_DATABASE_CHECK: Lazy<Mutex<Option<(Instant, bool)>>> = Lazy::new(|| Mutex::new(None));
+
+//Why the check failed is only logged, the error can name the database host and user
+async fn check_database() -> bool {
+    if let Some((checked_at, ok)) = *LAST_DATABASE_CHECK.lock() {
+        if checked_at.elapsed() < DATABASE_CHECK_CACHE_TIME {
+            return ok;
+        }
+    }
+
+    let result = match get_master_server().postgres_pool {
+        Some(_) => match ping_database(DATABASE_TIMEOUT).await {
+            Ok(()) if is_migrated() => Ok(()),
+            Ok(()) => Err(String::from("not migrated")),
+            Err(err) => Err(err),
+        },
+        None => Err(String::from("not configured")),
+    };
+
+    if let Err(err) = &result {
+        if get_global_config().require_database {
+            warn!("Readiness database check failed: {}", err);
+        }
+    }
+
+    let ok = result.is_ok();
+    *LAST_DATABASE_CHECK.lock() = Some((Instant::now(), ok));
+    ok
+}
+
+//Liveness, answering at all means the process is up
+#[get("/healthz")]
+pub async fn healthz() -> HttpResponse {
+    HttpResponse::Ok().json(json!({ "status": "ok" }))
+}
+
+//Readiness, the database only counts when require_database is set
+#[get("/readyz")]
+pub async fn readyz() -> HttpResponse {
+    let config_ok = GLOBAL_CONFIG.get().is_some();
+    let require_database = get_global_config().require_database;
+
+    let database_ok = check_database().await;
+
+    let scrub_age = current_time().saturating_sub(
+        get_master_server()
+            .server_list
+            .scrub_alive_at
+            .load(Ordering::Relaxed),
+    );
+    let cfg = get_global_config();
+    let scrub_wake_up =
+        cfg.scrub_interval_seconds.max(1) as u64 + cfg.scrub_jitter_ms as u64 / 1000 + 1;
+    let scrub_ok = scrub_age <= scrub_wake_up * SCRUB_MISSED_WAKE_UPS;
+
+    let shutting_down = is_shutting_down();
+    let ready = config_ok && scrub_ok && !shutting_down && (database_ok || !require_database);
+
+    let body = json!({
+        "status": if ready { "ready" } else { "not_ready" },
+        "shutting_down": shutting_down,
+        "checks": {
+            "config": { "ok": config_ok },
+            "database": {
+                "ok": database_ok,
+                "required": require_database,
+            },
+            "scrub": {
+                "ok": scrub_ok,
+                "seconds_since_last_run": scrub_age,
+            },
+        },
+    });
+
+    match ready {
+        true => HttpResponse::Ok().json(body),
+        false => HttpResponse::ServiceUnavailable().json(body),
+    }
+}
@@ -0,0 +1,32 @@
+use {
+    crate::{
+        client_ip::{client_ip, parse_networks},
+        metrics::get_metrics,
+    },
+    actix_web::{get, HttpRequest, HttpResponse},
+    ipnet::IpNet,
+    once_cell::sync::Lazy,
+    shared::ms_config::get_global_config,
+};
+
+static ALLOW_LIST: Lazy<Vec<IpNet>> = Lazy::new(|| {
+    parse_networks(
+        &get_global_config().metrics_allow_list,
+        "metrics_allow_list",
+    )
+});
+
+//Served on the normal listeners unless metrics_listeners is set
+#[get("/metrics")]
+pub async fn metrics(req: HttpRequest) -> HttpResponse {
+    let allowed = ALLOW_LIST.is_empty()
+        || client_ip(&req).is_some_and(|ip| ALLOW_LIST.iter().any(|network| network.contains(&ip)));
+
+    if !allowed {
+        return HttpResponse::Forbidden().finish();
+    }
+
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(get_metrics().render())
+}
@@ -2,3 +2,5 @@ pub mod bans;
 pub mod panel;
 pub mod servers;
 pub mod eula;
+pub mod health;
+pub mod metrics;
@@ -0,0 +1,300 @@
+use {
+    crate::{
+        client_ip::{client_ip, client_ip_string},
+        audit::Auditor,
+        database::users::{
+            accept_invite as db_accept_invite, change_password, check_password, disable_totp,
+            enable_totp, get_invite_username, has_totp, totp_required_for, verify_totp,
+            LoginFailure, MIN_PASSWORD_LENGTH,
+        },
+        endpoints::panel::{
+            get_account_js, get_ms_post_js,
+            login::{handle_failed_login, throttled_response},
+            GENERIC_STYLE,
+        },
+        login_throttle::get_login_throttle,
+        middleware::{
+            auth::SessionOnly,
+            csrf::{CsrfProtection, CsrfToken},
+        },
+        permissions::PanelUser,
+        totp,
+    },
+    actix_session::Session,
+    actix_web::{error, get, post, web, Error, HttpRequest, HttpResponse},
+    maud::{html, Markup, PreEscaped, DOCTYPE},
+    serde::Deserialize,
+    serde_json::json,
+};
+
+//Secret being enrolled, only written to the users table once a code from it has been verified
+const TOTP_ENROL_SECRET: &str = "totp_enrol_secret";
+
+#[derive(Deserialize)]
+pub struct ChangePasswordRequest {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+#[derive(Deserialize)]
+pub struct TotpCodeRequest {
+    pub code: String,
+}
+
+#[derive(Deserialize)]
+pub struct AcceptInviteRequest {
+    pub token: String,
+    pub password: String,
+}
+
+#[get("/account", wrap = "SessionOnly")]
+pub async fn account_page(
+    user: web::ReqData<PanelUser>,
+    csrf: CsrfToken,
+) -> actix_web::Result<Markup> {
+    let totp_enabled = has_totp(&user.username).await;
+
+    Ok(html! {
+        (DOCTYPE)
+        meta name="viewport" content="width=device-width, initial-scale=1.0";
+        html lang = "en" {
+            (GENERIC_STYLE)
+            (csrf)
+            (PreEscaped(get_ms_post_js()))
+            (PreEscaped(get_account_js()))
+            title {"Account"}
+
+            body {
+                h1 {(format!("Account: {}", user.username))}
+                p {(format!("Role: {}", user.role))}
+
+                h2 {"Change password"}
+                table {
+                    tr {
+                        td {label for = "current_password" {"Current password"}}
+                        td {input type = "password" id = "current_password";}
+                    }
+                    tr {
+                        td {label for = "new_password" {(format!("New password (min {} characters)", MIN_PASSWORD_LENGTH))}}
+                        td {input type = "password" id = "new_password";}
+                    }
+                    tr {
+                        td {label for = "confirm_password" {"Confirm new password"}}
+                        td {input type = "password" id = "confirm_password";}
+                    }
+                }
+                button type = "button" onclick = "change_password()" {"Change password"}
+                p id = "password_message";
+
+                h2 {"Two factor authentication"}
+                @if totp_enabled {
+                    p {"Two factor authentication is enabled"}
+                    @if !totp_required_for(user.role) {
+                        label for = "disable_totp_code" {"Current code "}
+                        input type = "text" id = "disable_totp_code" autocomplete = "one-time-code";
+                        button type = "button" onclick = "disable_totp()" {"Disable"}
+                        p id = "totp_message";
+                    }
+                } @else {
+                    @if user.totp_enrolment_required {
+                        p {"Two factor authentication is required for your role, set it up to continue using the panel"}
+                    }
+                    a href = "/panel/account/totp" {"Set up two factor authentication"}
+                }
+            }
+        }
+    })
+}
+
+#[get("/account/totp", wrap = "SessionOnly")]
+pub async fn totp_enrolment(
+    user: web::ReqData<PanelUser>,
+    session: Session,
+    csrf: CsrfToken,
+) -> actix_web::Result<Markup> {
+    if has_totp(&user.username).await {
+        return Err(error::ErrorBadRequest(
+            "Two factor authentication is already enabled",
+        ));
+    }
+
+    let secret = match totp::generate_secret() {
+        Some(secret) => secret,
+        None => return Err(error::ErrorInternalServerError("Failed to generate secret")),
+    };
+    session.insert(TOTP_ENROL_SECRET, &secret)?;
+
+    let uri = totp::provisioning_uri(&user.username, &secret);
+
+    Ok(html! {
+        (DOCTYPE)
+        meta name="viewport" content="width=device-width, initial-scale=1.0";
+        html lang = "en" {
+            (GENERIC_STYLE)
+            (csrf)
+            (PreEscaped(get_ms_post_js()))
+            (PreEscaped(get_account_js()))
+            title {"Two factor authentication"}
+
+            body {
+                h1 {"Set up two factor authentication"}
+                p {"Scan the code with your authenticator app, or enter the secret manually"}
+                @if let Some(svg) = totp::qr_code_svg(&uri) {
+                    (PreEscaped(svg))
+                }
+                p {"Secret: " code {(secret)}}
+
+                label for = "enable_totp_code" {"Code from app "}
+                input type = "text" id = "enable_totp_code" autocomplete = "one-time-code";
+                button type = "button" onclick = "enable_totp()" {"Enable"}
+                p id = "totp_message";
+                pre id = "recovery_codes";
+            }
+        }
+    })
+}
+
+#[post("/account/totp/enable", wrap = "SessionOnly")]
+pub async fn enable_own_totp(
+    request: web::Json<TotpCodeRequest>,
+    user: web::ReqData<PanelUser>,
+    session: Session,
+    auditor: Auditor,
+) -> Result<HttpResponse, Error> {
+    let secret = match session.get::<String>(TOTP_ENROL_SECRET)? {
+        Some(secret) => secret,
+        None => return Err(error::ErrorBadRequest("No enrolment in progress")),
+    };
+
+    let step = match totp::verify_code(&secret, &request.0.code, None) {
+        Some(step) => step,
+        None => return Err(error::ErrorBadRequest("Invalid code")),
+    };
+
+    match enable_totp(&user.username, &secret, step).await {
+        Ok(codes) => {
+            session.remove(TOTP_ENROL_SECRET);
+            auditor.record("totp_enable", None, json!({})).await;
+            Ok(HttpResponse::Ok().json(json!({ "recovery_codes": codes })))
+        }
+        Err(err) => Err(error::ErrorInternalServerError(err)),
+    }
+}
+
+#[post("/account/totp/disable", wrap = "SessionOnly")]
+pub async fn disable_own_totp(
+    request: web::Json<TotpCodeRequest>,
+    user: web::ReqData<PanelUser>,
+    auditor: Auditor,
+) -> Result<HttpResponse, Error> {
+    if totp_required_for(user.role) {
+        return Err(error::ErrorForbidden(
+            "Two factor authentication is required for your role",
+        ));
+    }
+
+    if !verify_totp(&user.username, &request.0.code).await {
+        return Err(error::ErrorBadRequest("Invalid code"));
+    }
+
+    match disable_totp(&user.username).await {
+        Ok(_) => {
+            auditor.record("totp_disable", None, json!({})).await;
+            Ok(HttpResponse::Ok().finish())
+        }
+        Err(err) => Err(error::ErrorInternalServerError(err)),
+    }
+}
+
+//Throttled like a login so a stolen session can not be used to guess the current password
+#[post("/account/password", wrap = "SessionOnly")]
+pub async fn change_own_password(
+    req: HttpRequest,
+    request: web::Json<ChangePasswordRequest>,
+    user: web::ReqData<PanelUser>,
+    session: Session,
+    auditor: Auditor,
+) -> Result<HttpResponse, Error> {
+    let ip = client_ip(&req);
+
+    if let Some(wait) = get_login_throttle().retry_after(ip, &user.username) {
+        return Ok(throttled_response(ip, &user.username, wait));
+    }
+
+    match check_password(&user.username, &request.0.current_password).await {
+        Ok(()) => get_login_throttle().record_success(ip, &user.username),
+        Err(LoginFailure::Error) => {
+            return Err(error::ErrorInternalServerError("Failed to check password"))
+        }
+        Err(reason) => {
+            handle_failed_login(ip, &user.username, reason).await;
+            return Err(error::ErrorBadRequest("Current password is incorrect"));
+        }
+    }
+
+    match change_password(&user.username, &request.0.new_password).await {
+        Ok(_) => {
+            //Every session was revoked with the old password, this one carries on under a new key
+            session.renew();
+            auditor.record("password_change", None, json!({})).await;
+            Ok(HttpResponse::Ok().finish())
+        }
+        Err(err) => Err(error::ErrorBadRequest(err)),
+    }
+}
+
+//Used both for new accounts and for password resets
+#[get("/invite/{token}")]
+pub async fn invite_page(token: web::Path<String>) -> actix_web::Result<Markup> {
+    let token = token.into_inner();
+
+    let username = match get_invite_username(&token).await {
+        Some(username) => username,
+        None => return Err(error::ErrorNotFound("Invite is invalid or has expired")),
+    };
+
+    Ok(html! {
+        (DOCTYPE)
+        meta name="viewport" content="width=device-width, initial-scale=1.0";
+        html lang = "en" {
+            (GENERIC_STYLE)
+            (PreEscaped(get_ms_post_js()))
+            (PreEscaped(get_account_js()))
+            title {"Set password"}
+
+            body {
+                h1 {(format!("Set a password for {}", username))}
+                table {
+                    tr {
+                        td {label for = "new_password" {(format!("Password (min {} characters)", MIN_PASSWORD_LENGTH))}}
+                        td {input type = "password" id = "new_password";}
+                    }
+                    tr {
+                        td {label for = "confirm_password" {"Confirm password"}}
+                        td {input type = "password" id = "confirm_password";}
+                    }
+                }
+                button type = "button" value = (token) onclick = "accept_invite(this)" {"Set password"}
+                p id = "password_message";
+            }
+        }
+    })
+}
+
+#[post("/invite", wrap = "CsrfProtection::origin_only()")]
+pub async fn accept_invite(
+    req: HttpRequest,
+    request: web::Json<AcceptInviteRequest>,
+) -> Result<HttpResponse, Error> {
+    let ip = client_ip_string(&req);
+
+    match db_accept_invite(&request.0.token, &request.0.password).await {
+        Ok(username) => {
+            Auditor::new(&username, &ip)
+                .record("invite_accept", None, json!({}))
+                .await;
+            Ok(HttpResponse::Ok().finish())
+        }
+        Err(err) => Err(error::ErrorBadRequest(err)),
+    }
+}
@@ -0,0 +1,261 @@
+use {
+    crate::{
+        announcements::{get_announcements, NewAnnouncement},
+        audit::Auditor,
+        endpoints::panel::{get_announcements_js, get_ms_post_js, GENERIC_STYLE},
+        middleware::{auth::RequirePermission, csrf::CsrfToken},
+        permissions::{PanelUser, Permission},
+        server_list::current_time,
+    },
+    actix_web::{error, get, post, web, Error, HttpResponse},
+    chrono::DateTime,
+    maud::{html, Markup, PreEscaped, DOCTYPE},
+    serde::Deserialize,
+    serde_json::json,
+    shared::server::Severity,
+};
+
+//Used when no end time is given
+const DEFAULT_DURATION_SECONDS: u64 = 60 * 60;
+//How far ahead an announcement can start or end
+const MAX_SCHEDULE_SECONDS: u64 = 365 * 24 * 60 * 60;
+
+#[derive(Deserialize)]
+pub struct CreateAnnouncementRequest {
+    pub message: String,
+    pub severity: Severity,
+    //Unix seconds, None starts now
+    pub starts_at: Option<u64>,
+    pub ends_at: Option<u64>,
+    #[serde(default)]
+    pub versions: Vec<String>,
+    #[serde(default)]
+    pub playlists: Vec<String>,
+    #[serde(default)]
+    pub regions: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct CancelAnnouncementRequest {
+    pub id: u64,
+}
+
+fn format_time(time: u64) -> String {
+    DateTime::from_timestamp(time as i64, 0)
+        .map(|time| time.to_rfc3339())
+        .unwrap_or_default()
+}
+
+fn format_filter(filter: &[String]) -> String {
+    match filter.is_empty() {
+        true => String::from("Any"),
+        false => filter.join(", "),
+    }
+}
+
+fn clean_filter(filter: Vec<String>) -> Vec<String> {
+    filter
+        .into_iter()
+        .map(|item| item.trim().to_string())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+#[get("/announcements", wrap = "RequirePermission(Permission::ViewServers)")]
+pub async fn announcement_list(
+    user: web::ReqData<PanelUser>,
+    csrf: CsrfToken,
+) -> actix_web::Result<Markup> {
+    let announcements = get_announcements().all();
+    let time = current_time();
+    let can_manage = user.has(Permission::ManageServers);
+
+    Ok(html! {
+        (DOCTYPE)
+        meta name="viewport" content="width=device-width, initial-scale=1.0";
+        html lang = "en" {
+            (GENERIC_STYLE)
+            (csrf)
+            (PreEscaped(get_ms_post_js()))
+            (PreEscaped(get_announcements_js()))
+            title {"Announcements"}
+
+            body {
+                h1 {"Announcements"}
+                p {"Announcements are handed to every matching server the next time it posts or bulk checks"}
+
+                @if can_manage {
+                    h2 {"Create announcement"}
+                    label for = "announcement_message" {"Message "}
+                    input type = "text" id = "announcement_message" size = "60";
+                    br;
+                    label for = "announcement_severity" {"Severity "}
+                    select id = "announcement_severity" {
+                        option value = "info" {"Info"}
+                        option value = "warning" {"Warning"}
+                        option value = "critical" {"Critical"}
+                    }
+                    br;
+                    label for = "announcement_start" {"Start (empty for now) "}
+                    input type = "datetime-local" id = "announcement_start";
+                    br;
+                    label for = "announcement_end" {"End (empty for an hour after start) "}
+                    input type = "datetime-local" id = "announcement_end";
+                    br;
+                    p {"Filters are comma separated, leave empty to match every server"}
+                    label for = "announcement_versions" {"Versions "}
+                    input type = "text" id = "announcement_versions";
+                    br;
+                    label for = "announcement_playlists" {"Playlists "}
+                    input type = "text" id = "announcement_playlists";
+                    br;
+                    button type = "button" onclick = "create_announcement()" {"Create"}
+                    p id = "announcement_message_status";
+                }
+
+                h2 {"Announcements"}
+                @if announcements.is_empty() {
+                    p {"No announcements"}
+                } @else {
+                    table {
+                        tr {
+                            th {"Id"}
+                            th {"Severity"}
+                            th {"Message"}
+                            th {"Start"}
+                            th {"End"}
+                            th {"Versions"}
+                            th {"Playlists"}
+                            th {"Created by"}
+                            th {"Status"}
+                            th {"Delivered"}
+                            th {"Acknowledged"}
+                            @if can_manage {
+                                th;
+                            }
+                        }
+
+                        @for announcement in announcements.iter() {
+                            tr {
+                                td {(announcement.id)}
+                                td {(announcement.severity.as_str())}
+                                td {(&announcement.message)}
+                                td {(format_time(announcement.starts_at))}
+                                td {(format_time(announcement.ends_at))}
+                                td {(format_filter(&announcement.versions))}
+                                td {(format_filter(&announcement.playlists))}
+                                td {(&announcement.created_by)}
+                                td {(announcement.status(time))}
+                                td {(announcement.delivered_count())}
+                                td {(announcement.acknowledged_count())}
+                                @if can_manage {
+                                    td {
+                                        @if !announcement.cancelled && time < announcement.ends_at {
+                                            button type = "button" value = (announcement.id) onclick = "cancel_announcement_pressed(this)" {"Cancel"}
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    })
+}
+
+#[post(
+    "/announcements/create",
+    wrap = "RequirePermission(Permission::ManageServers)"
+)]
+pub async fn create_announcement(
+    request: web::Json<CreateAnnouncementRequest>,
+    user: web::ReqData<PanelUser>,
+    auditor: Auditor,
+) -> Result<HttpResponse, Error> {
+    let request = request.into_inner();
+    let message = request.message.trim().to_string();
+
+    if message.is_empty() {
+        return Err(error::ErrorBadRequest("Message can not be empty"));
+    }
+
+    //Servers do not report a region yet, a region filter would silently match none of them
+    let regions = clean_filter(request.regions);
+    if !regions.is_empty() {
+        return Err(error::ErrorBadRequest(
+            "Region filters are not supported until servers report a region",
+        ));
+    }
+
+    let time = current_time();
+    let starts_at = request.starts_at.unwrap_or(time);
+    let ends_at = request
+        .ends_at
+        .unwrap_or(starts_at.saturating_add(DEFAULT_DURATION_SECONDS));
+
+    if starts_at.max(ends_at) > time.saturating_add(MAX_SCHEDULE_SECONDS) {
+        return Err(error::ErrorBadRequest(
+            "Announcements can be scheduled at most a year ahead",
+        ));
+    }
+
+    if ends_at <= starts_at {
+        return Err(error::ErrorBadRequest("End has to be after the start"));
+    }
+
+    if ends_at <= time {
+        return Err(error::ErrorBadRequest("End is already in the past"));
+    }
+
+    let announcement = NewAnnouncement {
+        message,
+        severity: request.severity,
+        starts_at,
+        ends_at,
+        versions: clean_filter(request.versions),
+        playlists: clean_filter(request.playlists),
+        regions,
+    };
+
+    let details = json!({
+        "message": announcement.message,
+        "severity": announcement.severity,
+        "starts_at": announcement.starts_at,
+        "ends_at": announcement.ends_at,
+        "versions": announcement.versions,
+        "playlists": announcement.playlists,
+        "regions": announcement.regions,
+    });
+
+    let id = get_announcements().create(announcement, &user.username);
+
+    auditor
+        .record("announcement_create", Some(&id.to_string()), details)
+        .await;
+
+    Ok(HttpResponse::Ok().json(json!({ "id": id })))
+}
+
+#[post(
+    "/announcements/cancel",
+    wrap = "RequirePermission(Permission::ManageServers)"
+)]
+pub async fn cancel_announcement(
+    request: web::Json<CancelAnnouncementRequest>,
+    auditor: Auditor,
+) -> Result<HttpResponse, Error> {
+    match get_announcements().cancel(request.0.id) {
+        Some(message) => {
+            auditor
+                .record(
+                    "announcement_cancel",
+                    Some(&request.0.id.to_string()),
+                    json!({ "message": message }),
+                )
+                .await;
+            Ok(HttpResponse::Ok().finish())
+        }
+        None => Err(error::ErrorNotFound("Announcement not found")),
+    }
+}
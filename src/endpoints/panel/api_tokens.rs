@@ -0,0 +1,177 @@
+use {
+    crate::{
+        audit::Auditor,
+        database::api_tokens::{create_api_token, get_api_tokens, revoke_api_token},
+        endpoints::panel::{get_api_tokens_js, get_ms_post_js, GENERIC_STYLE},
+        middleware::{
+            auth::{RequirePermission, SessionOnly},
+            csrf::CsrfToken,
+        },
+        permissions::{PanelUser, Permission},
+    },
+    actix_web::{error, get, post, web, Error, HttpResponse},
+    chrono::Utc,
+    maud::{html, Markup, PreEscaped, DOCTYPE},
+    serde::Deserialize,
+    serde_json::json,
+    tracing::error,
+};
+
+//Longer expiries are cut down to this, a token that should outlive it can be created without one
+const MAX_EXPIRY_DAYS: u32 = 10 * 365;
+
+#[derive(Deserialize)]
+pub struct CreateApiTokenRequest {
+    pub name: String,
+    pub permissions: Vec<Permission>,
+    //None for a token that does not expire, at most MAX_EXPIRY_DAYS
+    pub expires_in_days: Option<u32>,
+}
+
+#[derive(Deserialize)]
+pub struct RevokeApiTokenRequest {
+    pub id: i32,
+}
+
+#[get("/api_tokens", wrap = "RequirePermission(Permission::ManageApiTokens)")]
+pub async fn api_token_list(
+    user: web::ReqData<PanelUser>,
+    csrf: CsrfToken,
+) -> actix_web::Result<Markup> {
+    let tokens = get_api_tokens()
+        .await
+        .map_err(|err| error!("Failed to load API tokens: {}", err))
+        .ok();
+
+    Ok(html! {
+        (DOCTYPE)
+        meta name="viewport" content="width=device-width, initial-scale=1.0";
+        html lang = "en" {
+            (GENERIC_STYLE)
+            (csrf)
+            (PreEscaped(get_ms_post_js()))
+            (PreEscaped(get_api_tokens_js()))
+            title {"API Tokens"}
+
+            body {
+                h1 {"API Tokens"}
+                p {"Send tokens as an \"Authorization: Bearer <token>\" header. A token acts as the user that created it, limited to the permissions picked here"}
+
+                h2 {"Create token"}
+                label for = "token_name" {"Name "}
+                input type = "text" id = "token_name";
+                br;
+                label for = "token_expiry" {"Expires after days (empty for never) "}
+                input type = "number" id = "token_expiry" min = "1" max = (MAX_EXPIRY_DAYS);
+                br;
+                @for permission in user.permissions.iter() {
+                    input type = "checkbox" class = "token_permission" id = (permission.as_str()) value = (permission.as_str());
+                    label for = (permission.as_str()) {(permission.as_str())}
+                    br;
+                }
+                button type = "button" onclick = "create_token()" {"Create"}
+                p id = "token_message";
+
+                h2 {"Active tokens"}
+                @if let Some(tokens) = tokens {
+                    table {
+                        tr {
+                            th {"Name"}
+                            th {"Owner"}
+                            th {"Permissions"}
+                            th {"Created"}
+                            th {"Expires"}
+                            th {"Last used"}
+                            th;
+                        }
+
+                        @for token in tokens.iter() {
+                            tr {
+                                td {(&token.name)}
+                                td {(&token.owner)}
+                                td {(token.permissions.join(", "))}
+                                td {(token.created_on.to_rfc3339())}
+                                td {(token.expires_on.map(|time| time.to_rfc3339()).unwrap_or_else(|| String::from("Never")))}
+                                td {
+                                    @if let Some(last_used) = token.last_used {
+                                        (format!("{} from {}", last_used.to_rfc3339(), token.last_used_ip.clone().unwrap_or_default()))
+                                    } @else {
+                                        "Never"
+                                    }
+                                }
+                                td {button type = "button" value = (token.id) onclick = "revoke_token_pressed(this)" {"Revoke"}}
+                            }
+                        }
+                    }
+                } @else {
+                    p {"Failed to load API tokens"}
+                }
+            }
+        }
+    })
+}
+
+//Tokens can only be created from a browser session so a leaked token can not mint more
+#[post(
+    "/api_tokens/create",
+    wrap = "RequirePermission(Permission::ManageApiTokens)",
+    wrap = "SessionOnly"
+)]
+pub async fn create_token(
+    request: web::Json<CreateApiTokenRequest>,
+    user: web::ReqData<PanelUser>,
+    auditor: Auditor,
+) -> Result<HttpResponse, Error> {
+    if let Some(permission) = request.0.permissions.iter().find(|p| !user.has(**p)) {
+        return Err(error::ErrorForbidden(format!(
+            "You do not have the {} permission",
+            permission.as_str()
+        )));
+    }
+
+    let expires_on = request
+        .0
+        .expires_in_days
+        .map(|days| Utc::now() + chrono::Duration::days(days.min(MAX_EXPIRY_DAYS) as i64));
+
+    match create_api_token(
+        &request.0.name,
+        &user.username,
+        &request.0.permissions,
+        expires_on,
+    )
+    .await
+    {
+        Ok(token) => {
+            auditor
+                .record(
+                    "api_token_create",
+                    Some(&request.0.name),
+                    json!({ "permissions": request.0.permissions, "expires_on": expires_on }),
+                )
+                .await;
+            Ok(HttpResponse::Ok().json(json!({ "token": token })))
+        }
+        Err(err) => Err(error::ErrorBadRequest(err.to_string())),
+    }
+}
+
+#[post(
+    "/api_tokens/revoke",
+    wrap = "RequirePermission(Permission::ManageApiTokens)"
+)]
+pub async fn revoke_token(
+    request: web::Json<RevokeApiTokenRequest>,
+    auditor: Auditor,
+) -> Result<HttpResponse, Error> {
+    match revoke_api_token(request.0.id).await {
+        Ok(Some(name)) => {
+            auditor
+                .record("api_token_revoke", Some(&name), json!({}))
+                .await;
+            Ok(HttpResponse::Ok().finish())
+        }
+        Ok(None) => Err(error::ErrorNotFound("Token not found")),
+        Err(err) => Err(error::ErrorInternalServerError(err.to_string())),
+    }
+}
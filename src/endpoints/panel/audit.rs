@@ -0,0 +1,230 @@
+use {
+    crate::{
+        database::audit::{
+            count_audit_entries, get_audit_actions, get_audit_entries, AuditFilter, AuditRow,
+        },
+        endpoints::panel::GENERIC_STYLE,
+        middleware::auth::RequirePermission,
+        permissions::Permission,
+    },
+    actix_web::{error, get, http, web, HttpResponse},
+    chrono::{NaiveDate, TimeZone, Utc},
+    maud::{html, Markup, DOCTYPE},
+    serde::{Deserialize, Serialize},
+    tracing::error,
+};
+
+const PAGE_SIZE: i64 = 50;
+//Upper bound on a single export so one request can not pull the whole table into memory
+const EXPORT_LIMIT: i64 = 100_000;
+
+//Straight from the filter form, so every value can be an empty string
+#[derive(Deserialize, Serialize, Default, Clone)]
+pub struct AuditQuery {
+    #[serde(default)]
+    pub actor: String,
+    #[serde(default)]
+    pub action: String,
+    #[serde(default)]
+    pub target: String,
+    //Dates as yyyy-mm-dd, until is inclusive
+    #[serde(default)]
+    pub since: String,
+    #[serde(default)]
+    pub until: String,
+    #[serde(default)]
+    pub page: Option<u32>,
+    #[serde(default)]
+    pub format: Option<String>,
+}
+
+impl AuditQuery {
+    fn filter(&self) -> Result<AuditFilter, actix_web::Error> {
+        let parse_date = |date: &str, days_after: u64| -> Result<_, actix_web::Error> {
+            if date.is_empty() {
+                return Ok(None);
+            }
+
+            let date = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                .map_err(|_| error::ErrorBadRequest(format!("Invalid date '{}'", date)))?;
+            let start = (date + chrono::Days::new(days_after))
+                .and_hms_opt(0, 0, 0)
+                .map(|time| Utc.from_utc_datetime(&time));
+
+            Ok(start)
+        };
+
+        Ok(AuditFilter {
+            actor: Some(self.actor.clone()),
+            action: Some(self.action.clone()),
+            target: Some(self.target.clone()),
+            since: parse_date(&self.since, 0)?,
+            until: parse_date(&self.until, 1)?,
+        })
+    }
+
+    //Query string for the same filter on another page
+    fn with_page(&self, page: u32) -> String {
+        let query = AuditQuery {
+            page: Some(page),
+            ..self.clone()
+        };
+
+        serde_urlencoded::to_string(query).unwrap_or_default()
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    format!("\"{}\"", value.replace('"', "\"\""))
+}
+
+fn to_csv(rows: &[AuditRow]) -> String {
+    let mut csv = String::from("id,occurred_on,actor,api_token,action,target,details,ip\n");
+
+    for row in rows {
+        let fields = [
+            row.id.to_string(),
+            row.occurred_on.to_rfc3339(),
+            csv_field(&row.actor),
+            csv_field(row.api_token.as_deref().unwrap_or_default()),
+            csv_field(&row.action),
+            csv_field(row.target.as_deref().unwrap_or_default()),
+            csv_field(&row.details),
+            csv_field(&row.ip),
+        ];
+        csv.push_str(&fields.join(","));
+        csv.push('\n');
+    }
+
+    csv
+}
+
+#[get("/audit", wrap = "RequirePermission(Permission::ViewAuditLog)")]
+pub async fn audit_log(query: web::Query<AuditQuery>) -> actix_web::Result<Markup> {
+    let query = query.into_inner();
+    let filter = query.filter()?;
+    let page = query.page.unwrap_or(0);
+
+    let entries = get_audit_entries(&filter, PAGE_SIZE, page as i64 * PAGE_SIZE).await;
+    let total = count_audit_entries(&filter).await;
+    let actions = get_audit_actions().await.unwrap_or_default();
+
+    let (entries, total) = match (entries, total) {
+        (Ok(entries), Ok(total)) => (Some(entries), total),
+        (Err(err), _) | (_, Err(err)) => {
+            error!("Failed to load audit log: {}", err);
+            (None, 0)
+        }
+    };
+    let last_page = ((total - 1).max(0) / PAGE_SIZE) as u32;
+
+    Ok(html! {
+        (DOCTYPE)
+        meta name="viewport" content="width=device-width, initial-scale=1.0";
+        html lang = "en" {
+            (GENERIC_STYLE)
+            title {"Audit Log"}
+
+            body {
+                h1 {"Audit Log"}
+
+                form method = "get" action = "/panel/audit" {
+                    label for = "actor" {"Actor "}
+                    input type = "text" id = "actor" name = "actor" value = (query.actor);
+                    label for = "action" {" Action "}
+                    select id = "action" name = "action" {
+                        option value = "" {"Any"}
+                        @for action in actions.iter() {
+                            option value = (action) selected[*action == query.action] {(action)}
+                        }
+                    }
+                    label for = "target" {" Target "}
+                    input type = "text" id = "target" name = "target" value = (query.target);
+                    br;
+                    label for = "since" {"From "}
+                    input type = "date" id = "since" name = "since" value = (query.since);
+                    label for = "until" {" To "}
+                    input type = "date" id = "until" name = "until" value = (query.until);
+                    br;
+                    button type = "submit" {"Filter"}
+                    " "
+                    button type = "submit" formaction = "/panel/audit/export" name = "format" value = "csv" {"Export CSV"}
+                    " "
+                    button type = "submit" formaction = "/panel/audit/export" name = "format" value = "json" {"Export JSON"}
+                }
+
+                @if let Some(entries) = entries {
+                    p {(format!("{} entries, page {} of {}", total, page + 1, last_page + 1))}
+
+                    table {
+                        tr {
+                            th {"Time"}
+                            th {"Actor"}
+                            th {"Action"}
+                            th {"Target"}
+                            th {"Details"}
+                            th {"IP"}
+                        }
+
+                        @for entry in entries.iter() {
+                            tr {
+                                td {(entry.occurred_on.to_rfc3339())}
+                                td {
+                                    (&entry.actor)
+                                    @if let Some(token) = &entry.api_token {
+                                        (format!(" (token {})", token))
+                                    }
+                                }
+                                td {(&entry.action)}
+                                td {(entry.target.as_deref().unwrap_or_default())}
+                                td {code {(&entry.details)}}
+                                td {(&entry.ip)}
+                            }
+                        }
+                    }
+
+                    @if page > 0 {
+                        a href = (format!("/panel/audit?{}", query.with_page(page - 1))) {"Previous"}
+                        " "
+                    }
+                    @if page < last_page {
+                        a href = (format!("/panel/audit?{}", query.with_page(page + 1))) {"Next"}
+                    }
+                } @else {
+                    p {"Failed to load audit log"}
+                }
+            }
+        }
+    })
+}
+
+#[get("/audit/export", wrap = "RequirePermission(Permission::ViewAuditLog)")]
+pub async fn export_audit_log(query: web::Query<AuditQuery>) -> actix_web::Result<HttpResponse> {
+    let filter = query.filter()?;
+
+    let entries = get_audit_entries(&filter, EXPORT_LIMIT, 0)
+        .await
+        .map_err(|err| error::ErrorInternalServerError(err.to_string()))?;
+
+    let (content_type, extension, body) = match query.format.as_deref() {
+        Some("json") => (
+            "application/json",
+            "json",
+            serde_json::to_string(&entries)
+                .map_err(|err| error::ErrorInternalServerError(err.to_string()))?,
+        ),
+        _ => ("text/csv", "csv", to_csv(&entries)),
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(content_type)
+        .insert_header((
+            http::header::CONTENT_DISPOSITION,
+            format!(
+                "attachment; filename=\"audit_log_{}.{}\"",
+                Utc::now().format("%Y%m%d%H%M%S"),
+                extension
+            ),
+        ))
+        .body(body))
+}
@@ -0,0 +1,340 @@
+use {
+    crate::{
+        database::server_history::{get_network_samples, get_server_sessions, ServerSessionRow},
+        endpoints::panel::GENERIC_STYLE,
+        middleware::auth::RequirePermission,
+        permissions::Permission,
+    },
+    actix_web::{error, get, web},
+    chrono::{DateTime, Utc},
+    maud::{html, Markup, DOCTYPE},
+    serde::Deserialize,
+    shared::ms_config::get_global_config,
+    tracing::error,
+};
+
+const DEFAULT_HOURS: i64 = 24;
+const DEFAULT_DAYS: i64 = 7;
+const MAX_DAYS: i64 = 365;
+const SESSION_LIMIT: i64 = 200;
+const SERVER_SESSION_LIMIT: i64 = 1000;
+//Points drawn per graph at most, samples are averaged down to fit
+const GRAPH_POINTS: i64 = 300;
+const GRAPH_WIDTH: f64 = 800.0;
+const GRAPH_HEIGHT: f64 = 200.0;
+const DAY_SECONDS: i64 = 24 * 60 * 60;
+
+#[derive(Deserialize)]
+pub struct NetworkHistoryQuery {
+    pub hours: Option<i64>,
+    pub key: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ServerHistoryQuery {
+    pub key: String,
+    pub days: Option<i64>,
+}
+
+fn format_duration(seconds: i64) -> String {
+    let seconds = seconds.max(0);
+    let (days, hours, minutes) = (
+        seconds / DAY_SECONDS,
+        seconds % DAY_SECONDS / 3600,
+        seconds % 3600 / 60,
+    );
+
+    match (days, hours) {
+        (0, 0) => format!("{}m", minutes),
+        (0, _) => format!("{}h {}m", hours, minutes),
+        _ => format!("{}d {}h {}m", days, hours, minutes),
+    }
+}
+
+//A running session counts up to now, its last heartbeat may be up to a flush behind
+fn session_end(session: &ServerSessionRow, now: DateTime<Utc>) -> DateTime<Utc> {
+    match session.ended {
+        true => session.last_heartbeat,
+        false => now,
+    }
+}
+
+//Seconds within [from, until) covered by at least one session, overlapping sessions on one key are only counted once
+fn covered_seconds(intervals: &[(i64, i64)], from: i64, until: i64) -> i64 {
+    let mut clipped: Vec<(i64, i64)> = intervals
+        .iter()
+        .map(|(start, end)| ((*start).max(from), (*end).min(until)))
+        .filter(|(start, end)| start < end)
+        .collect();
+    clipped.sort_unstable();
+
+    let mut covered = 0;
+    let mut reached = from;
+    for (start, end) in clipped {
+        let start = start.max(reached);
+        if end > start {
+            covered += end - start;
+            reached = end;
+        }
+    }
+
+    covered
+}
+
+fn percent(part: i64, whole: i64) -> String {
+    format!("{:.1}%", part as f64 / whole.max(1) as f64 * 100.0)
+}
+
+fn line_graph(title: &str, points: &[(i64, i64)], from: i64, until: i64) -> Markup {
+    let peak = points.iter().map(|(_, value)| *value).max().unwrap_or(0);
+    let span = (until - from).max(1) as f64;
+    let scale = peak.max(1) as f64;
+
+    let coordinates = points
+        .iter()
+        .map(|(time, value)| {
+            format!(
+                "{:.1},{:.1}",
+                (time - from) as f64 / span * GRAPH_WIDTH,
+                GRAPH_HEIGHT - *value as f64 / scale * GRAPH_HEIGHT
+            )
+        })
+        .collect::<Vec<String>>()
+        .join(" ");
+
+    html! {
+        h3 {(format!("{} (peak {})", title, peak))}
+        svg width = (GRAPH_WIDTH) height = (GRAPH_HEIGHT) style = "border: 1px solid black" {
+            polyline points = (coordinates) fill = "none" stroke = "steelblue" stroke-width = "2";
+        }
+    }
+}
+
+fn timeline(intervals: &[(i64, i64)], from: i64, until: i64) -> Markup {
+    let span = (until - from).max(1) as f64;
+
+    html! {
+        svg width = (GRAPH_WIDTH) height = "30" style = "border: 1px solid black" {
+            @for (start, end) in intervals.iter() {
+                @let start = (*start).max(from);
+                @let end = (*end).min(until);
+                @if start < end {
+                    rect
+                        x = (format!("{:.1}", (start - from) as f64 / span * GRAPH_WIDTH))
+                        y = "0"
+                        width = (format!("{:.1}", ((end - start) as f64 / span * GRAPH_WIDTH).max(1.0)))
+                        height = "30"
+                        fill = "seagreen";
+                }
+            }
+        }
+    }
+}
+
+fn session_table(sessions: &[ServerSessionRow], now: DateTime<Utc>, link_keys: bool) -> Markup {
+    html! {
+        table {
+            tr {
+                th {"Name"}
+                @if link_keys {
+                    th {"Key"}
+                }
+                th {"Address"}
+                th {"First seen"}
+                th {"Last heartbeat"}
+                th {"Uptime"}
+                th {"Avg players"}
+                th {"Peak players"}
+                th {"Maps"}
+                th {"Playlists"}
+                th {"Status"}
+            }
+
+            @for session in sessions.iter() {
+                tr {
+                    td {
+                        (&session.name)
+                        @if session.hidden {
+                            " (hidden)"
+                        }
+                    }
+                    @if link_keys {
+                        td {
+                            a href = (format!("/panel/history/server?{}", serde_urlencoded::to_string([("key", &session.server_key)]).unwrap_or_default())) {
+                                (&session.server_key)
+                            }
+                        }
+                    }
+                    td {(&session.address)}
+                    td {(session.first_seen.to_rfc3339())}
+                    td {(session.last_heartbeat.to_rfc3339())}
+                    td {(format_duration((session_end(session, now) - session.first_seen).num_seconds()))}
+                    td {(format!("{:.1}", session.average_players()))}
+                    td {(session.peak_players)}
+                    td {(session.maps.join(", "))}
+                    td {(session.playlists.join(", "))}
+                    td {(if session.ended { "Ended" } else { "Running" })}
+                }
+            }
+        }
+    }
+}
+
+//Network wide server and player counts over time, with the sessions that ran in the window
+#[get("/history", wrap = "RequirePermission(Permission::ViewServers)")]
+pub async fn network_history(query: web::Query<NetworkHistoryQuery>) -> actix_web::Result<Markup> {
+    let hours = query.hours.unwrap_or(DEFAULT_HOURS).clamp(1, MAX_DAYS * 24);
+    let key = query.key.as_deref().map(str::trim).filter(|key| !key.is_empty());
+
+    let now = Utc::now();
+    let since = now - chrono::Duration::hours(hours);
+    let bucket = (hours * 3600 / GRAPH_POINTS).max(get_global_config().history_flush_seconds as i64);
+
+    let samples = get_network_samples(since, bucket).await;
+    let sessions = get_server_sessions(key, since, SESSION_LIMIT).await;
+
+    let (samples, sessions) = match (samples, sessions) {
+        (Ok(samples), Ok(sessions)) => (Some(samples), sessions),
+        (Err(err), _) | (_, Err(err)) => {
+            error!("Failed to load server history: {}", err);
+            (None, Vec::new())
+        }
+    };
+
+    let (from, until) = (since.timestamp(), now.timestamp());
+
+    Ok(html! {
+        (DOCTYPE)
+        meta name="viewport" content="width=device-width, initial-scale=1.0";
+        html lang = "en" {
+            (GENERIC_STYLE)
+            title {"Server History"}
+
+            body {
+                h1 {"Server History"}
+
+                form method = "get" action = "/panel/history" {
+                    label for = "hours" {"Last "}
+                    input type = "number" id = "hours" name = "hours" min = "1" value = (hours);
+                    label for = "key" {" hours, key "}
+                    input type = "text" id = "key" name = "key" value = (key.unwrap_or_default());
+                    " "
+                    button type = "submit" {"Show"}
+                }
+
+                @if let Some(samples) = samples {
+                    p {(format!("{} to {}", since.to_rfc3339(), now.to_rfc3339()))}
+
+                    @let servers: Vec<(i64, i64)> = samples.iter().map(|sample| (sample.sampled_on.timestamp(), sample.servers)).collect();
+                    @let players: Vec<(i64, i64)> = samples.iter().map(|sample| (sample.sampled_on.timestamp(), sample.players)).collect();
+                    (line_graph("Servers", &servers, from, until))
+                    (line_graph("Players", &players, from, until))
+
+                    h2 {"Sessions"}
+                    @if sessions.is_empty() {
+                        p {"No sessions in this period"}
+                    } @else {
+                        (session_table(&sessions, now, true))
+                    }
+                } @else {
+                    p {"Failed to load server history"}
+                }
+            }
+        }
+    })
+}
+
+//Uptime of every server that posted with one key, per day and as a timeline
+#[get("/history/server", wrap = "RequirePermission(Permission::ViewServers)")]
+pub async fn server_history(query: web::Query<ServerHistoryQuery>) -> actix_web::Result<Markup> {
+    let key = query.key.trim();
+    if key.is_empty() {
+        return Err(error::ErrorBadRequest("Key can not be empty"));
+    }
+
+    let days = query.days.unwrap_or(DEFAULT_DAYS).clamp(1, MAX_DAYS);
+    let now = Utc::now();
+    let since = now - chrono::Duration::days(days);
+
+    let sessions = get_server_sessions(Some(key), since, SERVER_SESSION_LIMIT)
+        .await
+        .map_err(|err| error!("Failed to load server history: {}", err))
+        .ok();
+
+    let (from, until) = (since.timestamp(), now.timestamp());
+    let intervals: Vec<(i64, i64)> = sessions
+        .iter()
+        .flatten()
+        .map(|session| {
+            (
+                session.first_seen.timestamp(),
+                session_end(session, now).timestamp(),
+            )
+        })
+        .collect();
+
+    //Newest day first, the first one is cut short by the window
+    let daily: Vec<(i64, i64, i64)> = (0..days)
+        .map(|day| {
+            let day_until = until - day * DAY_SECONDS;
+            let day_from = (day_until - DAY_SECONDS).max(from);
+            (day_from, day_until, covered_seconds(&intervals, day_from, day_until))
+        })
+        .collect();
+
+    let total = covered_seconds(&intervals, from, until);
+
+    Ok(html! {
+        (DOCTYPE)
+        meta name="viewport" content="width=device-width, initial-scale=1.0";
+        html lang = "en" {
+            (GENERIC_STYLE)
+            title {"Server History"}
+
+            body {
+                h1 {(format!("Server History: {}", key))}
+                a href = "/panel/history" {"Network history"}
+
+                form method = "get" action = "/panel/history/server" {
+                    input type = "hidden" name = "key" value = (key);
+                    label for = "days" {"Last "}
+                    input type = "number" id = "days" name = "days" min = "1" max = (MAX_DAYS) value = (days);
+                    " days "
+                    button type = "submit" {"Show"}
+                }
+
+                @if let Some(sessions) = sessions {
+                    p {(format!("Up {} of the last {} days ({})", format_duration(total), days, percent(total, until - from)))}
+                    (timeline(&intervals, from, until))
+                    p {(format!("{} to {}", since.to_rfc3339(), now.to_rfc3339()))}
+
+                    h2 {"Daily uptime"}
+                    table {
+                        tr {
+                            th {"From"}
+                            th {"Uptime"}
+                            th {"Percent"}
+                        }
+
+                        @for (day_from, day_until, covered) in daily.iter() {
+                            tr {
+                                td {(DateTime::from_timestamp(*day_from, 0).map(|time| time.to_rfc3339()).unwrap_or_default())}
+                                td {(format_duration(*covered))}
+                                td {(percent(*covered, day_until - day_from))}
+                            }
+                        }
+                    }
+
+                    h2 {"Sessions"}
+                    @if sessions.is_empty() {
+                        p {"No sessions in this period"}
+                    } @else {
+                        (session_table(&sessions, now, false))
+                    }
+                } @else {
+                    p {"Failed to load server history"}
+                }
+            }
+        }
+    })
+}
@@ -1,6 +1,11 @@
 use {
-    crate::{endpoints::panel::GENERIC_STYLE, get_master_server},
-    actix_web::get,
+    crate::{
+        endpoints::panel::{get_ms_post_js, get_server_management_js, GENERIC_STYLE},
+        get_master_server,
+        middleware::{auth::RequirePermission, csrf::CsrfToken},
+        permissions::{PanelUser, Permission},
+    },
+    actix_web::{get, web},
     chrono::{DateTime, NaiveDateTime, Utc},
     maud::{html, Markup, PreEscaped, DOCTYPE},
     serde::Serialize,
@@ -51,8 +56,13 @@ pub async fn public_list() -> actix_web::Result<Markup> {
 }
 
 //This is the private list that will show hidden servers as well as public ones
-#[get("/list")]
-pub async fn private_list() -> actix_web::Result<Markup> {
+#[get("/list", wrap = "RequirePermission(Permission::ViewServers)")]
+pub async fn private_list(
+    user: web::ReqData<PanelUser>,
+    csrf: CsrfToken,
+) -> actix_web::Result<Markup> {
+    let relist_blocks = get_master_server().server_list.active_relist_blocks();
+    let pending = get_master_server().server_list.get_pending_servers();
     let pub_list = get_master_server().server_list.get_public_servers().read();
     let hidden_list = get_master_server().server_list.get_hidden_servers().read();
     let current_time = SystemTime::now()
@@ -71,6 +81,9 @@ pub async fn private_list() -> actix_web::Result<Markup> {
                 }
                 </script>"#))
             (GENERIC_STYLE)
+            (csrf)
+            (PreEscaped(get_ms_post_js()))
+            (PreEscaped(get_server_management_js()))
             title {"R5R Server List"}
 
             body {
@@ -144,6 +157,71 @@ pub async fn private_list() -> actix_web::Result<Markup> {
                         }
                     }
                 }
+
+                @if !pending.is_empty() {
+                    h2 {"Awaiting approval"}
+                    p {"Registered while the flood detector was tripped, these are not listed until approved"}
+                    table {
+                        tr {
+                            th {"Name"}
+                            th {"Map"}
+                            th {"Playlist"}
+                            th {"IP:Port"}
+                            th {"Key"}
+                            th {"Version"}
+                            th {"Hidden"}
+                            @if user.has(Permission::ManageServers) {
+                                th;
+                            }
+                        }
+
+                        @for server in pending.iter() {
+                            tr {
+                                td {(&server.server.name)}
+                                td {(&server.server.map)}
+                                td {(&server.server.playlist)}
+                                td {(format!("{}:{}", server.server.ip, server.server.port))}
+                                td {(&server.server.key)}
+                                td {(&server.server.version)}
+                                td {(server.server.hidden)}
+                                @if user.has(Permission::ManageServers) {
+                                    td {
+                                        button type = "button" value = (server.internal.uid) onclick = "approve_pending_pressed(this)" {"Approve"}
+                                        " "
+                                        button type = "button" value = (server.internal.uid) onclick = "reject_pending_pressed(this)" {"Reject"}
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+
+                @if !relist_blocks.is_empty() {
+                    h2 {"Blocked from relisting"}
+                    table {
+                        tr {
+                            th {"IP:Port"}
+                            th {"Reason"}
+                            th {"Blocked by"}
+                            th {"Until"}
+                            @if user.has(Permission::ManageServers) {
+                                th;
+                            }
+                        }
+
+                        @for (address, block) in relist_blocks.iter() {
+                            tr {
+                                td {(address)}
+                                td {(&block.reason)}
+                                td {(&block.issued_by)}
+                                td {(DateTime::from_timestamp(block.until as i64, 0).map(|time| time.to_rfc3339()).unwrap_or_default())}
+                                @if user.has(Permission::ManageServers) {
+                                    td {button type = "button" value = (address) onclick = "lift_block_pressed(this)" {"Lift"}}
+                                }
+                            }
+                        }
+                    }
+                }
             }
         }
     })
@@ -1,23 +1,136 @@
 use {
     crate::{
-        database::website_auth,
+        client_ip::client_ip,
+        audit::Auditor,
+        database::{
+            login_failures::record_login_failure,
+            users::{has_totp, lock_user, verify_totp, website_auth, LoginFailure},
+        },
         endpoints::panel::{get_login_js, get_ms_post_js},
+        login_throttle::get_login_throttle,
+        middleware::csrf::CsrfProtection,
     },
     actix_session::Session,
     actix_web::{
-        error, Error, get, post,
+        error, get, http, post,
         web::{self, Redirect},
+        Error, HttpRequest, HttpResponse,
     },
     maud::{html, Markup, PreEscaped, DOCTYPE},
     serde::Deserialize,
+    serde_json::json,
+    shared::ms_config::get_global_config,
+    std::{
+        net::IpAddr,
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    },
+    tracing::{error, warn},
 };
 
+//Set once the password has been accepted, the user key is only set after the second factor
+const TOTP_PENDING_USER: &str = "totp_pending_user";
+const TOTP_PENDING_SINCE: &str = "totp_pending_since";
+
+//How long the user has after entering their password to enter a code
+const TOTP_LOGIN_TIMEOUT: u64 = 300;
+
 #[derive(Deserialize)]
 pub struct LoginInfo {
     username: String,
     password: String,
 }
 
+#[derive(Deserialize)]
+pub struct TotpLoginInfo {
+    code: String,
+}
+
+//Usernames are attacker controlled, this keeps the logs and login_failures table sane
+const MAX_LOGGED_USERNAME_LENGTH: usize = 64;
+
+fn current_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or(0)
+}
+
+fn ip_str(ip: Option<IpAddr>) -> String {
+    ip.map(|ip| ip.to_string()).unwrap_or_default()
+}
+
+//Throttled attempts are only logged, storing them would let a flood fill the login_failures table
+pub fn throttled_response(ip: Option<IpAddr>, username: &str, wait: Duration) -> HttpResponse {
+    let seconds = wait.as_secs().max(1);
+    let username: String = username.chars().take(MAX_LOGGED_USERNAME_LENGTH).collect();
+
+    warn!(
+        target: "security",
+        event = "login_throttled",
+        username = %username,
+        ip = %ip_str(ip),
+        retry_after = seconds,
+        "Throttled panel login"
+    );
+
+    HttpResponse::TooManyRequests()
+        .insert_header((http::header::RETRY_AFTER, seconds.to_string()))
+        .json(json!({ "retry_after": seconds }))
+}
+
+//Counts the failure towards backoff and lockout and records it for the panel
+pub async fn handle_failed_login(ip: Option<IpAddr>, username: &str, reason: LoginFailure) {
+    let username: String = username.chars().take(MAX_LOGGED_USERNAME_LENGTH).collect();
+    let ip_string = ip_str(ip);
+
+    let failures = get_login_throttle().record_failure(ip, &username);
+
+    warn!(
+        target: "security",
+        event = "login_failure",
+        username = %username,
+        ip = %ip_string,
+        reason = reason.as_str(),
+        failures,
+        "Failed panel login"
+    );
+
+    if let Err(err) = record_login_failure(&username, &ip_string, reason.as_str()).await {
+        error!("Failed to record login failure: {}", err);
+    }
+
+    let cfg = get_global_config();
+    let counts_to_lockout = matches!(reason, LoginFailure::BadPassword | LoginFailure::BadTotp);
+
+    if counts_to_lockout
+        && cfg.login_lockout_threshold != 0
+        && failures >= cfg.login_lockout_threshold
+    {
+        match lock_user(&username, cfg.login_lockout_minutes).await {
+            Ok(_) => {
+                //The lockout now does the limiting, so the user gets a fresh set of attempts afterwards
+                get_login_throttle().clear_username(&username);
+                Auditor::new("system", &ip_string)
+                    .record(
+                        "account_lock",
+                        Some(&username),
+                        json!({ "failures": failures, "minutes": cfg.login_lockout_minutes }),
+                    )
+                    .await;
+                warn!(
+                    target: "security",
+                    event = "account_locked",
+                    username = %username,
+                    ip = %ip_string,
+                    minutes = cfg.login_lockout_minutes,
+                    "Panel account locked after repeated failed logins"
+                );
+            }
+            Err(err) => error!("Failed to lock '{}': {}", username, err),
+        }
+    }
+}
+
 #[get("/login")]
 pub async fn login_page() -> actix_web::Result<Markup> {
     Ok(html! {
@@ -58,7 +171,16 @@ pub async fn login_page() -> actix_web::Result<Markup> {
                     input type = "password" id = "password";
                 }
                 br;
-                button onclick = "post_login()" {"Login"}
+                button id = "login_button" onclick = "post_login()" {"Login"}
+
+                div id = "totp_form" hidden {
+                    label for = "totp_code" {"Authentication or recovery code:"}
+                    br;
+                    input type = "text" id = "totp_code" autocomplete = "one-time-code";
+                    br;
+                    button onclick = "post_totp()" {"Verify"}
+                }
+
                 p id = "login_message";
 
             }
@@ -66,17 +188,79 @@ pub async fn login_page() -> actix_web::Result<Markup> {
     })
 }
 
-#[post("/panel/auth")]
-pub async fn panel_auth(form: web::Json<LoginInfo>, session: Session) -> Result<Redirect, Error> {
-    let authed: bool = website_auth(&form.0.username, form.0.password).await;
-    
-    if authed {
-        session.renew();
-        session.insert("user", form.0.username)?;
-        return Ok(Redirect::to("/panel/"))
+//Origin only, there is no session to hold a CSRF token before login
+#[post("/panel/auth", wrap = "CsrfProtection::origin_only()")]
+pub async fn panel_auth(
+    req: HttpRequest,
+    form: web::Json<LoginInfo>,
+    session: Session,
+) -> Result<HttpResponse, Error> {
+    let ip = client_ip(&req);
+
+    if let Some(wait) = get_login_throttle().retry_after(ip, &form.0.username) {
+        return Ok(throttled_response(ip, &form.0.username, wait));
+    }
+
+    if let Err(reason) = website_auth(&form.0.username, form.0.password).await {
+        handle_failed_login(ip, &form.0.username, reason).await;
+        return Err(error::ErrorUnauthorized(""));
+    }
+
+    session.renew();
+
+    if has_totp(&form.0.username).await {
+        session.insert(TOTP_PENDING_USER, form.0.username)?;
+        session.insert(TOTP_PENDING_SINCE, current_time())?;
+        return Ok(HttpResponse::Ok().json(json!({ "totp_required": true })));
+    }
+
+    get_login_throttle().record_success(ip, &form.0.username);
+    Auditor::new(&form.0.username, &ip_str(ip))
+        .record("login", None, json!({ "totp": false }))
+        .await;
+    session.insert("user", form.0.username)?;
+    Ok(HttpResponse::Ok().json(json!({ "totp_required": false })))
+}
+
+#[post("/panel/auth/totp", wrap = "CsrfProtection::origin_only()")]
+pub async fn panel_auth_totp(
+    req: HttpRequest,
+    form: web::Json<TotpLoginInfo>,
+    session: Session,
+) -> Result<HttpResponse, Error> {
+    let username = match session.get::<String>(TOTP_PENDING_USER)? {
+        Some(username) => username,
+        None => return Err(error::ErrorUnauthorized("")),
+    };
+
+    let since = session.get::<u64>(TOTP_PENDING_SINCE)?.unwrap_or(0);
+    if current_time().saturating_sub(since) > TOTP_LOGIN_TIMEOUT {
+        session.purge();
+        return Err(error::ErrorUnauthorized("Login timed out"));
+    }
+
+    let ip = client_ip(&req);
+
+    if let Some(wait) = get_login_throttle().retry_after(ip, &username) {
+        return Ok(throttled_response(ip, &username, wait));
+    }
+
+    if !verify_totp(&username, &form.0.code).await {
+        handle_failed_login(ip, &username, LoginFailure::BadTotp).await;
+        return Err(error::ErrorUnauthorized(""));
     }
 
-    Err(error::ErrorUnauthorized(""))
+    get_login_throttle().record_success(ip, &username);
+    Auditor::new(&username, &ip_str(ip))
+        .record("login", None, json!({ "totp": true }))
+        .await;
+
+    session.remove(TOTP_PENDING_USER);
+    session.remove(TOTP_PENDING_SINCE);
+    session.renew();
+    session.insert("user", username)?;
+
+    Ok(HttpResponse::Ok().finish())
 }
 
 #[get("/logout")]
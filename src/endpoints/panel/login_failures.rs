@@ -0,0 +1,55 @@
+use {
+    crate::{
+        database::login_failures::get_recent_login_failures, endpoints::panel::GENERIC_STYLE,
+        middleware::auth::RequirePermission, permissions::Permission,
+    },
+    actix_web::get,
+    maud::{html, Markup, DOCTYPE},
+    tracing::error,
+};
+
+const SHOWN_FAILURES: i64 = 200;
+
+#[get("/login_failures", wrap = "RequirePermission(Permission::ManageUsers)")]
+pub async fn login_failure_list() -> actix_web::Result<Markup> {
+    let failures = get_recent_login_failures(SHOWN_FAILURES)
+        .await
+        .map_err(|err| error!("Failed to load login failures: {}", err))
+        .ok();
+
+    Ok(html! {
+        (DOCTYPE)
+        meta name="viewport" content="width=device-width, initial-scale=1.0";
+        html lang = "en" {
+            (GENERIC_STYLE)
+            title {"Login Failures"}
+
+            body {
+                h1 {"Recent Login Failures"}
+                p {"Locked accounts can be unlocked from the users page"}
+
+                @if let Some(failures) = failures {
+                    table {
+                        tr {
+                            th {"Time"}
+                            th {"Username"}
+                            th {"IP"}
+                            th {"Reason"}
+                        }
+
+                        @for failure in failures.iter() {
+                            tr {
+                                td {(failure.attempted_on.to_rfc3339())}
+                                td {(&failure.username)}
+                                td {(&failure.ip)}
+                                td {(&failure.reason)}
+                            }
+                        }
+                    }
+                } @else {
+                    p {"Failed to load login failures"}
+                }
+            }
+        }
+    })
+}
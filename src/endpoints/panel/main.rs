@@ -1,20 +1,67 @@
 use {
-    actix_web::get,
+    crate::permissions::{PanelUser, Permission},
+    actix_web::{get, web},
     maud::{html, Markup, DOCTYPE},
 };
 
 #[get("/")]
-pub async fn panel_main_menu() -> actix_web::Result<Markup> {
+pub async fn panel_main_menu(user: web::ReqData<PanelUser>) -> actix_web::Result<Markup> {
     Ok(html! {
         (DOCTYPE)
         meta name="viewport" content="width=device-width, initial-scale=1.0";
         html lang = "en" {
             h1 {"Management"}
+            p {(format!("Logged in as {} ({})", user.username, user.role))}
 
-            a href = "/panel/moderation/player" {"Player Moderation"}
-            br;
+            @if user.totp_enrolment_required {
+                p {"Two factor authentication is required for your role, set it up on the account page"}
+            }
+
+            @if user.has(Permission::ViewBans) {
+                a href = "/panel/moderation/player" {"Player Moderation"}
+                br;
+            }
+
+            @if user.has(Permission::ViewServers) {
+                a href = "/panel/list" {"Server List"}
+                br;
+
+                a href = "/panel/announcements" {"Announcements"}
+                br;
+
+                a href = "/panel/history" {"Server History"}
+                br;
+            }
+
+            @if user.has(Permission::HostServers) || user.has(Permission::ManageServers) {
+                a href = "/panel/server_keys" {"Server Keys"}
+                br;
+            }
+
+            @if user.has(Permission::ManageSessions) {
+                a href = "/panel/sessions" {"Sessions"}
+                br;
+            }
+
+            @if user.has(Permission::ManageUsers) {
+                a href = "/panel/users" {"Users"}
+                br;
+
+                a href = "/panel/login_failures" {"Login Failures"}
+                br;
+            }
+
+            @if user.has(Permission::ManageApiTokens) {
+                a href = "/panel/api_tokens" {"API Tokens"}
+                br;
+            }
+
+            @if user.has(Permission::ViewAuditLog) {
+                a href = "/panel/audit" {"Audit Log"}
+                br;
+            }
 
-            a href = "/panel/list" {"Server List"}
+            a href = "/panel/account" {"Account"}
             br;
 
             //a href = "/panel/config" {"Configuration"}
@@ -24,4 +71,4 @@ pub async fn panel_main_menu() -> actix_web::Result<Markup> {
             br;
         }
     })
-}
\ No newline at end of file
+}
@@ -1,9 +1,18 @@
+mod account;
+mod announcements;
+mod api_tokens;
+mod audit;
 mod config;
+mod history;
 mod list;
 mod login;
+mod login_failures;
 mod main;
 mod player_moderation;
+mod server_keys;
 mod server_management;
+mod sessions;
+mod users;
 use maud::PreEscaped;
 
 pub static GENERIC_STYLE: PreEscaped<&'static str> = PreEscaped(
@@ -40,6 +49,21 @@ static LOGIN_JS: &'static str = include_str!("../../javascript/login.js");
 #[cfg(not(debug_assertions))]
 static MS_POST_JS: &'static str = include_str!("../../javascript/ms_post.js");
 
+#[cfg(not(debug_assertions))]
+static USER_MANAGEMENT_JS: &'static str = include_str!("../../javascript/user_management.js");
+
+#[cfg(not(debug_assertions))]
+static ACCOUNT_JS: &'static str = include_str!("../../javascript/account.js");
+
+#[cfg(not(debug_assertions))]
+static API_TOKENS_JS: &'static str = include_str!("../../javascript/api_tokens.js");
+
+#[cfg(not(debug_assertions))]
+static ANNOUNCEMENTS_JS: &'static str = include_str!("../../javascript/announcements.js");
+
+#[cfg(not(debug_assertions))]
+static SERVER_KEYS_JS: &'static str = include_str!("../../javascript/server_keys.js");
+
 #[cfg(not(debug_assertions))]
 fn get_mod_panel_js() -> &'static str {
     ID_MANAGEMENT_JS
@@ -60,6 +84,31 @@ fn get_ms_post_js() -> &'static str {
     MS_POST_JS
 }
 
+#[cfg(not(debug_assertions))]
+fn get_user_management_js() -> &'static str {
+    USER_MANAGEMENT_JS
+}
+
+#[cfg(not(debug_assertions))]
+fn get_account_js() -> &'static str {
+    ACCOUNT_JS
+}
+
+#[cfg(not(debug_assertions))]
+fn get_api_tokens_js() -> &'static str {
+    API_TOKENS_JS
+}
+
+#[cfg(not(debug_assertions))]
+fn get_announcements_js() -> &'static str {
+    ANNOUNCEMENTS_JS
+}
+
+#[cfg(not(debug_assertions))]
+fn get_server_keys_js() -> &'static str {
+    SERVER_KEYS_JS
+}
+
 #[cfg(debug_assertions)]
 fn get_mod_panel_js() -> String {
     std::fs::read_to_string("r5r_ms_rs\\src\\javascript\\identifier_management.js").unwrap()
@@ -80,14 +129,45 @@ fn get_ms_post_js() -> String {
     std::fs::read_to_string("r5r_ms_rs\\src\\javascript\\ms_post.js").unwrap()
 }
 
-use actix_web::{
-    self,
-    web::{scope, ServiceConfig},
+#[cfg(debug_assertions)]
+fn get_user_management_js() -> String {
+    std::fs::read_to_string("r5r_ms_rs\\src\\javascript\\user_management.js").unwrap()
+}
+
+#[cfg(debug_assertions)]
+fn get_account_js() -> String {
+    std::fs::read_to_string("r5r_ms_rs\\src\\javascript\\account.js").unwrap()
+}
+
+#[cfg(debug_assertions)]
+fn get_api_tokens_js() -> String {
+    std::fs::read_to_string("r5r_ms_rs\\src\\javascript\\api_tokens.js").unwrap()
+}
+
+#[cfg(debug_assertions)]
+fn get_announcements_js() -> String {
+    std::fs::read_to_string("r5r_ms_rs\\src\\javascript\\announcements.js").unwrap()
+}
+
+#[cfg(debug_assertions)]
+fn get_server_keys_js() -> String {
+    std::fs::read_to_string("r5r_ms_rs\\src\\javascript\\server_keys.js").unwrap()
+}
+
+use {
+    crate::middleware::{auth::ProtectedEndpoint, csrf::CsrfProtection},
+    actix_web::{
+        self,
+        web::{scope, ServiceConfig},
+    },
 };
 pub fn panel_routes(cfg: &mut ServiceConfig) {
     cfg.service(login::panel_auth)
+        .service(login::panel_auth_totp)
         .service(list::public_list)
         .service(login::login_page)
+        .service(account::invite_page)
+        .service(account::accept_invite)
         .service(
             scope("/panel")
                 //.service(config::get_config)
@@ -101,7 +181,42 @@ pub fn panel_routes(cfg: &mut ServiceConfig) {
                 .service(main::panel_main_menu)
                 .service(list::private_list)
                 .service(server_management::server_management)
+                .service(server_management::queue_server_command)
+                .service(server_management::delist_server)
+                .service(server_management::lift_relist_block)
+                .service(server_management::approve_pending_server)
+                .service(server_management::reject_pending_server)
+                .service(announcements::announcement_list)
+                .service(announcements::create_announcement)
+                .service(announcements::cancel_announcement)
+                .service(history::network_history)
+                .service(history::server_history)
+                .service(server_keys::server_key_list)
+                .service(server_keys::register_key)
+                .service(server_keys::revoke_key)
                 .service(player_moderation::moderation_panel)
-                .wrap(crate::middleware::auth::ProtectedEndpoint)
+                .service(sessions::session_list)
+                .service(sessions::session_revoke)
+                .service(login_failures::login_failure_list)
+                .service(users::user_list)
+                .service(users::create_user)
+                .service(users::reset_user_password)
+                .service(users::disable_user)
+                .service(users::change_user_role)
+                .service(account::account_page)
+                .service(account::change_own_password)
+                .service(account::totp_enrolment)
+                .service(account::enable_own_totp)
+                .service(account::disable_own_totp)
+                .service(users::reset_user_totp)
+                .service(users::unlock_user_account)
+                .service(api_tokens::api_token_list)
+                .service(api_tokens::create_token)
+                .service(api_tokens::revoke_token)
+                .service(audit::audit_log)
+                .service(audit::export_audit_log)
+                //Runs after ProtectedEndpoint so API token requests can be told apart
+                .wrap(CsrfProtection::new())
+                .wrap(ProtectedEndpoint),
         );
 }
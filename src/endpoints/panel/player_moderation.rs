@@ -1,12 +1,16 @@
 use {
     crate::{
-        database::{ban_identifier, search_for_ban},
+        audit::Auditor,
+        database::{ban_identifier, search_for_ban, server_keys::notify_owner},
         endpoints::panel::{get_mod_panel_js, get_ms_post_js},
         get_master_server,
+        middleware::{auth::RequirePermission, csrf::CsrfToken},
+        permissions::{PanelUser, Permission},
     },
     actix_web::{error, get, post, web, Error, HttpResponse},
     maud::{html, Markup, PreEscaped, DOCTYPE},
     serde::Deserialize,
+    serde_json::json,
     shared::utils::format_identifier,
 };
 
@@ -31,10 +35,16 @@ pub struct UnbanRequest {
 pub struct KickFromServer {
     pub server_uid: String,
     pub player_uids: Vec<u64>,
+    pub reason: String,
+    //How long the players are kept off the server for, none lets them rejoin straight away
+    pub cooldown_seconds: Option<u64>,
 }
 
-#[post("/ban")]
-async fn ban(ban_info: web::Json<BanRequest>) -> Result<HttpResponse, Error> {
+#[post("/ban", wrap = "RequirePermission(Permission::BanPlayers)")]
+pub async fn ban(
+    ban_info: web::Json<BanRequest>,
+    auditor: Auditor,
+) -> Result<HttpResponse, Error> {
     if ban_info.0.identifier.is_empty() {
         return Err(error::ErrorBadRequest("No identifier specified"));
     }
@@ -44,9 +54,15 @@ async fn ban(ban_info: web::Json<BanRequest>) -> Result<HttpResponse, Error> {
         None => return Err(error::ErrorBadRequest("Invalid Identifier")),
     };
 
-    match ban_identifier(identifier, ban_info.0.reason, ban_info.0.unban_timestamp).await {
+    let details = json!({
+        "reason": ban_info.0.reason,
+        "unban_timestamp": ban_info.0.unban_timestamp,
+    });
+
+    match ban_identifier(identifier.clone(), ban_info.0.reason, ban_info.0.unban_timestamp).await {
         Ok(result) => {
             if result {
+                auditor.record("ban", Some(&identifier), details).await;
                 Ok(HttpResponse::Ok().finish())
             } else {
                 Err(error::ErrorInternalServerError("Failed to ban identifier"))
@@ -56,8 +72,8 @@ async fn ban(ban_info: web::Json<BanRequest>) -> Result<HttpResponse, Error> {
     }
 }
 
-#[post("/ban_search")]
-async fn ban_search(ban_info: web::Json<BanSearchRequest>) -> Result<HttpResponse, Error> {
+#[post("/ban_search", wrap = "RequirePermission(Permission::ViewBans)")]
+pub async fn ban_search(ban_info: web::Json<BanSearchRequest>) -> Result<HttpResponse, Error> {
     let cleaned_identifier = match format_identifier(ban_info.0.identifier) {
         Some(identifier) => identifier,
         None => return Err(error::ErrorBadRequest("Invalid Identifier")),
@@ -66,32 +82,85 @@ async fn ban_search(ban_info: web::Json<BanSearchRequest>) -> Result<HttpRespons
     Ok(HttpResponse::Ok().json(bans))
 }
 
-#[post("/unban")]
-async fn unban_request(request: web::Json<UnbanRequest>) -> Result<HttpResponse, Error> {
+#[post("/unban", wrap = "RequirePermission(Permission::BanPlayers)")]
+pub async fn unban_request(
+    request: web::Json<UnbanRequest>,
+    auditor: Auditor,
+) -> Result<HttpResponse, Error> {
     match crate::database::unban(request.0.key).await {
-        true => Ok(HttpResponse::Ok().finish()),
-        false => Err(error::ErrorInternalServerError("")),
+        Ok(Some(removed)) => {
+            let details = json!({
+                "ban_id": removed.ban_id,
+                "reason": removed.reason,
+                "banned_on": removed.banned_on,
+                "unban_date": removed.unban_date,
+            });
+            auditor
+                .record("unban", removed.identifier.as_deref(), details)
+                .await;
+            Ok(HttpResponse::Ok().finish())
+        },
+        Ok(None) => Err(error::ErrorNotFound("Ban not found")),
+        Err(_) => Err(error::ErrorInternalServerError("")),
     }
 }
 
-#[post("/kick")]
-async fn kick_from_server(request: web::Json<KickFromServer>) -> Result<HttpResponse, Error> {
-    if get_master_server()
-        .server_list
-        .update_kick_list(request.0.server_uid, request.0.player_uids)
-    {
-        Ok(HttpResponse::Ok().finish())
-    } else {
-        Err(error::ErrorInternalServerError("Could not find server"))
+#[post("/kick", wrap = "RequirePermission(Permission::KickPlayers)")]
+pub async fn kick_from_server(
+    request: web::Json<KickFromServer>,
+    user: web::ReqData<PanelUser>,
+    auditor: Auditor,
+) -> Result<HttpResponse, Error> {
+    if request.0.player_uids.is_empty() {
+        return Err(error::ErrorBadRequest("No players selected"));
+    }
+
+    let reason = match request.0.reason.trim() {
+        "" => String::from("Kicked from server"),
+        reason => reason.to_string(),
+    };
+
+    let details = json!({
+        "player_uids": request.0.player_uids,
+        "reason": reason,
+        "cooldown_seconds": request.0.cooldown_seconds,
+    });
+    let notification = format!(
+        "{} player(s) kicked: {}",
+        request.0.player_uids.len(),
+        reason
+    );
+
+    let server_list = &get_master_server().server_list;
+    match server_list.queue_kicks(
+        request.0.server_uid.clone(),
+        request.0.player_uids,
+        reason,
+        request.0.cooldown_seconds,
+        user.username.clone(),
+    ) {
+        Ok(_) => {
+            auditor.record("kick", Some(&request.0.server_uid), details).await;
+            if let Some(server) = server_list.find_server_from_uid(request.0.server_uid) {
+                let notification = format!("On '{}', {}", server.server.name, notification);
+                notify_owner(server.internal.owner.as_deref(), &notification).await;
+            }
+            Ok(HttpResponse::Ok().finish())
+        }
+        Err(err) => Err(error::ErrorBadRequest(err)),
     }
 }
 
-#[get("/moderation/player")]
-async fn moderation_panel() -> actix_web::Result<Markup> {
+#[get("/moderation/player", wrap = "RequirePermission(Permission::ViewBans)")]
+pub async fn moderation_panel(
+    user: web::ReqData<PanelUser>,
+    csrf: CsrfToken,
+) -> actix_web::Result<Markup> {
     Ok(html! {
         (DOCTYPE)
         meta name="viewport" content="width=device-width, initial-scale=1.0";
         html lang = "en" {
+            (csrf)
             (PreEscaped(get_ms_post_js()))
             (PreEscaped(get_mod_panel_js()))
             (PreEscaped(r#"<style>
@@ -128,30 +197,32 @@ async fn moderation_panel() -> actix_web::Result<Markup> {
             body {
                 h1 {"Identifier Management"}
 
-                h2 {"Ban identifier"}
+                @if user.has(Permission::BanPlayers) {
+                    h2 {"Ban identifier"}
 
-                form {
+                    form {
 
-                    p {
-                        label for = "ban_identifier" {"Identifier"}
-                        input type = "text" id = "ban_identifier";
-                    }
+                        p {
+                            label for = "ban_identifier" {"Identifier"}
+                            input type = "text" id = "ban_identifier";
+                        }
 
-                    p {
-                        label for = "ban_unbandate" {"Unban date (blank for permanent)"}
-                        input type = "date" id = "ban_unbandate";
-                    }
+                        p {
+                            label for = "ban_unbandate" {"Unban date (blank for permanent)"}
+                            input type = "date" id = "ban_unbandate";
+                        }
 
-                    p {
-                        label for = "ban_reason" {"Reason"};
-                        input type = "text" id = "ban_reason";
-                    }
+                        p {
+                            label for = "ban_reason" {"Reason"};
+                            input type = "text" id = "ban_reason";
+                        }
 
-                    p {
-                        input type = "button" id = "ban_button" value = "Ban" onclick = "ban(this)" ;
-                        p id = "ban_result";
-                    }
+                        p {
+                            input type = "button" id = "ban_button" value = "Ban" onclick = "ban(this)" ;
+                            p id = "ban_result";
+                        }
 
+                    }
                 }
 
                 h2 {"Ban search"}
@@ -0,0 +1,292 @@
+use {
+    crate::{
+        audit::Auditor,
+        database::server_keys::{
+            get_host_notifications, get_server_key, get_server_keys, mark_host_notifications_read,
+            notify_owner, register_server_key, revoke_server_key,
+        },
+        endpoints::panel::{get_ms_post_js, get_server_keys_js, GENERIC_STYLE},
+        get_master_server,
+        middleware::{auth::RequirePermission, csrf::CsrfToken},
+        permissions::{PanelUser, Permission},
+    },
+    actix_web::{error, get, post, web, Error, HttpResponse},
+    maud::{html, Markup, PreEscaped, DOCTYPE},
+    serde::Deserialize,
+    serde_json::json,
+    tracing::error,
+};
+
+const NOTIFICATION_LIMIT: i64 = 50;
+
+#[derive(Deserialize)]
+pub struct RegisterServerKeyRequest {
+    pub name: String,
+    pub server_key: String,
+}
+
+#[derive(Deserialize)]
+pub struct RevokeServerKeyRequest {
+    pub id: i32,
+    pub reason: String,
+}
+
+//Hosts manage their own keys, anyone who can manage servers sees and can revoke every key
+#[get("/server_keys")]
+pub async fn server_key_list(
+    user: web::ReqData<PanelUser>,
+    csrf: CsrfToken,
+) -> actix_web::Result<Markup> {
+    let is_host = user.has(Permission::HostServers);
+    let manages_servers = user.has(Permission::ManageServers);
+
+    if !is_host && !manages_servers {
+        return Err(error::ErrorForbidden("Missing permission"));
+    }
+
+    let owner = match manages_servers {
+        true => None,
+        false => Some(user.username.as_str()),
+    };
+
+    let keys = get_server_keys(owner)
+        .await
+        .map_err(|err| error!("Failed to load server keys: {}", err))
+        .ok();
+
+    let (servers, notifications) = match is_host {
+        true => (
+            get_master_server()
+                .server_list
+                .servers_owned_by(&user.username),
+            get_host_notifications(&user.username, NOTIFICATION_LIMIT)
+                .await
+                .map_err(|err| error!("Failed to load host notifications: {}", err))
+                .unwrap_or_default(),
+        ),
+        false => (Vec::new(), Vec::new()),
+    };
+
+    if notifications.iter().any(|notification| !notification.read) {
+        if let Err(err) = mark_host_notifications_read(&user.username).await {
+            error!("Failed to mark host notifications read: {}", err);
+        }
+    }
+
+    Ok(html! {
+        (DOCTYPE)
+        meta name="viewport" content="width=device-width, initial-scale=1.0";
+        html lang = "en" {
+            (GENERIC_STYLE)
+            (csrf)
+            (PreEscaped(get_ms_post_js()))
+            (PreEscaped(get_server_keys_js()))
+            title {"Server Keys"}
+
+            body {
+                h1 {"Server Keys"}
+
+                @if is_host {
+                    h2 {"Register key"}
+                    p {"Servers posting with a registered key are listed as yours once one of them has posted the key's challenge as keyChallenge"}
+                    label for = "key_name" {"Name "}
+                    input type = "text" id = "key_name";
+                    br;
+                    label for = "server_key" {"Key "}
+                    input type = "text" id = "server_key";
+                    br;
+                    button type = "button" onclick = "register_key()" {"Register"}
+                    p id = "key_message";
+
+                    h2 {"Your servers"}
+                    @if servers.is_empty() {
+                        p {"None of your servers are listed"}
+                    } @else {
+                        table {
+                            tr {
+                                th {"Name"}
+                                th {"Map"}
+                                th {"Playlist"}
+                                th {"IP:Port"}
+                                th {"Players"}
+                                th {"Key"}
+                            }
+
+                            @for server in servers.iter() {
+                                tr {
+                                    td {(&server.server.name)}
+                                    td {(&server.server.map)}
+                                    td {(&server.server.playlist)}
+                                    td {(format!("{}:{}", server.server.ip, server.server.port))}
+                                    td {(format!("{}/{}", server.server.player_count, server.server.max_players))}
+                                    td {(&server.server.key)}
+                                }
+                            }
+                        }
+                    }
+
+                    h2 {"Notifications"}
+                    @if notifications.is_empty() {
+                        p {"No notifications"}
+                    } @else {
+                        table {
+                            tr {
+                                th {"Time"}
+                                th {"Message"}
+                            }
+
+                            @for notification in notifications.iter() {
+                                tr {
+                                    td {
+                                        (notification.created_on.to_rfc3339())
+                                        @if !notification.read {
+                                            " (new)"
+                                        }
+                                    }
+                                    td {(&notification.message)}
+                                }
+                            }
+                        }
+                    }
+                }
+
+                h2 {(if manages_servers { "All keys" } else { "Your keys" })}
+                @if let Some(keys) = keys {
+                    table {
+                        tr {
+                            th {"Name"}
+                            th {"Key"}
+                            @if manages_servers {
+                                th {"Owner"}
+                            }
+                            th {"Registered"}
+                            th {"Status"}
+                            th;
+                        }
+
+                        @for key in keys.iter() {
+                            tr {
+                                td {(&key.name)}
+                                td {(&key.server_key)}
+                                @if manages_servers {
+                                    td {(&key.owner)}
+                                }
+                                td {(key.created_on.to_rfc3339())}
+                                td {
+                                    @if let Some(revoked_on) = key.revoked_on {
+                                        (format!(
+                                            "Revoked {} by {}: {}",
+                                            revoked_on.to_rfc3339(),
+                                            key.revoked_by.clone().unwrap_or_default(),
+                                            key.revoke_reason.clone().unwrap_or_default()
+                                        ))
+                                    } @else if let Some(verified_on) = key.verified_on {
+                                        (format!("Verified {}", verified_on.to_rfc3339()))
+                                    } @else {
+                                        "Awaiting a post with the challenge"
+                                    }
+                                }
+                                td {
+                                    //Hosts can only revoke keys proven to be theirs, otherwise anyone could blacklist a key they do not run
+                                    @if key.revoked_on.is_none() && (manages_servers || key.verified_on.is_some()) {
+                                        button type = "button" value = (key.id) onclick = "revoke_key_pressed(this)" {"Revoke"}
+                                    }
+                                }
+                            }
+                        }
+                    }
+                } @else {
+                    p {"Failed to load server keys"}
+                }
+            }
+        }
+    })
+}
+
+#[post(
+    "/server_keys/register",
+    wrap = "RequirePermission(Permission::HostServers)"
+)]
+pub async fn register_key(
+    request: web::Json<RegisterServerKeyRequest>,
+    user: web::ReqData<PanelUser>,
+    auditor: Auditor,
+) -> Result<HttpResponse, Error> {
+    match register_server_key(&request.0.name, &request.0.server_key, &user.username).await {
+        Ok(challenge) => {
+            auditor
+                .record(
+                    "server_key_register",
+                    Some(request.0.server_key.trim()),
+                    json!({ "name": request.0.name.trim() }),
+                )
+                .await;
+            Ok(HttpResponse::Ok().json(json!({ "challenge": challenge })))
+        }
+        Err(err) => Err(error::ErrorBadRequest(err.to_string())),
+    }
+}
+
+//Revoking delists every server using the key, the key can not be registered again afterwards
+#[post("/server_keys/revoke")]
+pub async fn revoke_key(
+    request: web::Json<RevokeServerKeyRequest>,
+    user: web::ReqData<PanelUser>,
+    auditor: Auditor,
+) -> Result<HttpResponse, Error> {
+    let reason = request.0.reason.trim();
+
+    if reason.is_empty() {
+        return Err(error::ErrorBadRequest("Reason can not be empty"));
+    }
+
+    let key = get_server_key(request.0.id)
+        .await
+        .map_err(|err| error::ErrorInternalServerError(err.to_string()))?
+        .ok_or_else(|| error::ErrorNotFound("Key not found"))?;
+
+    let own_key = user.has(Permission::HostServers) && key.owner == user.username;
+    if !own_key && !user.has(Permission::ManageServers) {
+        return Err(error::ErrorForbidden("Missing permission"));
+    }
+
+    if own_key && key.verified_on.is_none() && !user.has(Permission::ManageServers) {
+        return Err(error::ErrorForbidden(
+            "Only verified keys can be revoked, none of your servers has posted this key's challenge",
+        ));
+    }
+
+    let key = revoke_server_key(key.id, &user.username, reason)
+        .await
+        .map_err(|err| error::ErrorInternalServerError(err.to_string()))?
+        .ok_or_else(|| error::ErrorNotFound("Key already revoked"))?;
+
+    let delisted = get_master_server()
+        .server_list
+        .remove_servers_with_key(&key.server_key);
+
+    auditor
+        .record(
+            "server_key_revoke",
+            Some(&key.server_key),
+            json!({
+                "name": key.name,
+                "owner": key.owner,
+                "reason": reason,
+                "delisted": delisted.len(),
+            }),
+        )
+        .await;
+
+    if !own_key {
+        let notification = format!(
+            "Key '{}' was revoked: {}, {} server(s) delisted",
+            key.name,
+            reason,
+            delisted.len()
+        );
+        notify_owner(Some(&key.owner), &notification).await;
+    }
+
+    Ok(HttpResponse::Ok().json(json!({ "delisted": delisted.len() })))
+}
@@ -1,14 +1,108 @@
 use {
     crate::{
+        audit::Auditor,
+        database::server_keys::notify_owner,
         endpoints::panel::{get_server_management_js, get_ms_post_js, GENERIC_STYLE},
         get_master_server,
+        middleware::{auth::RequirePermission, csrf::CsrfToken},
+        permissions::{PanelUser, Permission},
+        server_list::{current_time, RelistBlock, MAX_KICK_COOLDOWN_SECONDS},
     },
-    actix_web::{error, get, web},
+    actix_web::{error, get, post, web, Error, HttpResponse},
+    chrono::DateTime,
     maud::{html, Markup, PreEscaped, DOCTYPE},
+    serde::Deserialize,
+    serde_json::json,
+    shared::server::CommandKind,
 };
 
-#[get("/management/server/{server_id}")]
-pub async fn server_management(server_id: web::Path<String>) -> actix_web::Result<Markup> {
+//The commands that can be sent by hand, kicks go through /kick
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PanelCommand {
+    Broadcast {
+        message: String,
+    },
+    ChangeMap {
+        map: String,
+        playlist: Option<String>,
+    },
+    DelistNotice {
+        reason: String,
+    },
+}
+
+#[derive(Deserialize)]
+pub struct QueueCommandRequest {
+    pub server_uid: String,
+    #[serde(flatten)]
+    pub command: PanelCommand,
+}
+
+#[derive(Deserialize)]
+pub struct DelistServerRequest {
+    pub server_uid: String,
+    pub reason: String,
+    //None lets the server list itself again on its next post
+    pub block_minutes: Option<u64>,
+}
+
+#[derive(Deserialize)]
+pub struct PendingServerRequest {
+    pub server_uid: String,
+}
+
+#[derive(Deserialize)]
+pub struct RejectPendingRequest {
+    pub server_uid: String,
+    pub reason: String,
+    pub block_minutes: Option<u64>,
+}
+
+#[derive(Deserialize)]
+pub struct LiftRelistBlockRequest {
+    pub address: String,
+}
+
+//Longest a delisted or rejected server can be kept from listing again
+const MAX_BLOCK_MINUTES: u64 = 365 * 24 * 60;
+
+//When a relist block given in minutes runs out, checked before the server is taken off the list
+fn block_until(block_minutes: Option<u64>) -> Result<Option<u64>, Error> {
+    let minutes = match block_minutes {
+        Some(minutes) => minutes,
+        None => return Ok(None),
+    };
+
+    if minutes > MAX_BLOCK_MINUTES {
+        return Err(error::ErrorBadRequest(format!(
+            "Relisting can be blocked for at most {} minutes",
+            MAX_BLOCK_MINUTES
+        )));
+    }
+
+    minutes
+        .checked_mul(60)
+        .and_then(|seconds| current_time().checked_add(seconds))
+        .map(Some)
+        .ok_or_else(|| error::ErrorBadRequest("Block is too long"))
+}
+
+fn format_time(time: u64) -> String {
+    DateTime::from_timestamp(time as i64, 0)
+        .map(|time| time.to_rfc3339())
+        .unwrap_or_default()
+}
+
+#[get(
+    "/management/server/{server_id}",
+    wrap = "RequirePermission(Permission::ViewServers)"
+)]
+pub async fn server_management(
+    server_id: web::Path<String>,
+    user: web::ReqData<PanelUser>,
+    csrf: CsrfToken,
+) -> actix_web::Result<Markup> {
     let server_id = server_id.into_inner();
 
     if server_id.is_empty() {
@@ -24,6 +118,7 @@ pub async fn server_management(server_id: web::Path<String>) -> actix_web::Resul
             meta name="viewport" content="width=device-width, initial-scale=1.0";
             html lang = "en" {
                 (GENERIC_STYLE)
+                (csrf)
                 (PreEscaped(get_ms_post_js()))
                 (PreEscaped(get_server_management_js()))
                 title {"Server Management"}
@@ -37,7 +132,9 @@ pub async fn server_management(server_id: web::Path<String>) -> actix_web::Resul
                             tr {
                                 th {"IP"}
                                 th {"UID"}
-                                th {"Kick player"}
+                                @if user.has(Permission::KickPlayers) {
+                                    th {"Kick player"}
+                                }
                             }
 
                             @for player in server.players.iter() {
@@ -46,14 +143,89 @@ pub async fn server_management(server_id: web::Path<String>) -> actix_web::Resul
                                     td { (player.ip.clone().unwrap_or_else(String::new)) }
                                     td { (player.uid.unwrap_or(0)) }
 
-                                    @if player.uid.is_some() {
+                                    @if player.uid.is_some() && user.has(Permission::KickPlayers) {
                                         td { input type = "checkbox" name = "players_to_kick" value = (player.uid.unwrap()); }
                                     }
                                 }
                             }
                         }
-                        button type = "button" value = (&server.internal.uid) onclick = "kick_button_pressed(this)" {"Kick Player(s)"}
-                        p id = "kick_message" style = "margin-left: 5px;";
+                        @if user.has(Permission::KickPlayers) {
+                            br;
+                            label for = "kick_reason" {"Reason "}
+                            input type = "text" id = "kick_reason" placeholder = "Kicked from server";
+                            br;
+                            label for = "kick_cooldown" {"Rejoin cooldown (minutes, empty for none) "}
+                            input type = "number" id = "kick_cooldown" min = "1" max = (MAX_KICK_COOLDOWN_SECONDS / 60);
+                            br;
+                            button type = "button" value = (&server.internal.uid) onclick = "kick_button_pressed(this)" {"Kick Player(s)"}
+                            p id = "kick_message" style = "margin-left: 5px;";
+                        }
+                    }
+
+                    @if user.has(Permission::ManageServers) {
+                        h2 {"Send command"}
+                        label for = "broadcast_message" {"Broadcast message "}
+                        input type = "text" id = "broadcast_message";
+                        button type = "button" value = (&server.internal.uid) onclick = "broadcast_pressed(this)" {"Broadcast"}
+                        br;
+                        label for = "change_map" {"Change map "}
+                        input type = "text" id = "change_map" placeholder = (&server.server.map);
+                        label for = "change_playlist" {" Playlist "}
+                        input type = "text" id = "change_playlist" placeholder = (&server.server.playlist);
+                        button type = "button" value = (&server.internal.uid) onclick = "change_map_pressed(this)" {"Change map"}
+                        br;
+                        label for = "delist_reason" {"Delisting notice "}
+                        input type = "text" id = "delist_reason";
+                        button type = "button" value = (&server.internal.uid) onclick = "delist_notice_pressed(this)" {"Send notice"}
+                        p id = "command_message";
+
+                        h2 {"Delist"}
+                        label for = "delist_server_reason" {"Reason "}
+                        input type = "text" id = "delist_server_reason";
+                        br;
+                        label for = "delist_block_minutes" {"Block relisting for minutes (empty to allow it straight away) "}
+                        input type = "number" id = "delist_block_minutes" min = "1" max = (MAX_BLOCK_MINUTES);
+                        br;
+                        button type = "button" value = (&server.internal.uid) onclick = "delist_pressed(this)" {"Delist server"}
+                        p id = "delist_message";
+                    }
+
+                    @if !server.commands.is_empty() {
+                        h2 {"Commands"}
+                        table {
+                            tr {
+                                th {"Id"}
+                                th {"Type"}
+                                th {"Details"}
+                                th {"Issued by"}
+                                th {"Issued"}
+                                th {"Status"}
+                            }
+
+                            @for command in server.commands.iter() {
+                                tr {
+                                    td {(command.id)}
+                                    td {(command.kind.name())}
+                                    td {
+                                        (command.kind.describe())
+                                        @if let Some(until) = command.cooldown_until() {
+                                            (format!(" (cooldown until {})", format_time(until)))
+                                        }
+                                    }
+                                    td {(&command.issued_by)}
+                                    td {(format_time(command.issued_at))}
+                                    td {
+                                        @if let Some(time) = command.acknowledged_at {
+                                            (format!("Acknowledged {}", format_time(time)))
+                                        } @else if let Some(time) = command.delivered_at {
+                                            (format!("Delivered {}, awaiting acknowledgement", format_time(time)))
+                                        } @else {
+                                            "Pending"
+                                        }
+                                    }
+                                }
+                            }
+                        }
                     }
                 }
             }
@@ -62,3 +234,218 @@ pub async fn server_management(server_id: web::Path<String>) -> actix_web::Resul
         Err(error::ErrorNotFound("Server not found"))
     }
 }
+
+#[post(
+    "/management/server/command",
+    wrap = "RequirePermission(Permission::ManageServers)"
+)]
+pub async fn queue_server_command(
+    request: web::Json<QueueCommandRequest>,
+    user: web::ReqData<PanelUser>,
+    auditor: Auditor,
+) -> Result<HttpResponse, Error> {
+    let non_empty = |value: String, field: &str| match value.trim() {
+        "" => Err(error::ErrorBadRequest(format!("{} can not be empty", field))),
+        value => Ok(value.to_string()),
+    };
+
+    let kind = match request.0.command {
+        PanelCommand::Broadcast { message } => CommandKind::Broadcast {
+            message: non_empty(message, "Message")?,
+        },
+        PanelCommand::ChangeMap { map, playlist } => CommandKind::ChangeMap {
+            map: non_empty(map, "Map")?,
+            playlist: playlist.filter(|playlist| !playlist.trim().is_empty()),
+        },
+        PanelCommand::DelistNotice { reason } => CommandKind::DelistNotice {
+            reason: non_empty(reason, "Reason")?,
+        },
+    };
+
+    let details = json!({ "command": kind });
+    let notification = format!("{}: {}", kind.name(), kind.describe());
+
+    let server_list = &get_master_server().server_list;
+    match server_list.queue_command(&request.0.server_uid, kind, &user.username) {
+        Some(id) => {
+            auditor
+                .record("server_command", Some(&request.0.server_uid), details)
+                .await;
+            if let Some(server) = server_list.find_server_from_uid(request.0.server_uid.clone()) {
+                notify_owner(
+                    server.internal.owner.as_deref(),
+                    &format!("Command sent to '{}', {}", server.server.name, notification),
+                )
+                .await;
+            }
+            Ok(HttpResponse::Ok().json(json!({ "id": id })))
+        }
+        None => Err(error::ErrorNotFound("Could not find server")),
+    }
+}
+
+#[post(
+    "/management/server/delist",
+    wrap = "RequirePermission(Permission::ManageServers)"
+)]
+pub async fn delist_server(
+    request: web::Json<DelistServerRequest>,
+    user: web::ReqData<PanelUser>,
+    auditor: Auditor,
+) -> Result<HttpResponse, Error> {
+    let request = request.into_inner();
+    let reason = request.reason.trim().to_string();
+
+    if reason.is_empty() {
+        return Err(error::ErrorBadRequest("Reason can not be empty"));
+    }
+
+    let blocked_until = block_until(request.block_minutes)?;
+
+    let server_list = &get_master_server().server_list;
+    let server = server_list
+        .remove_server(&request.server_uid)
+        .ok_or_else(|| error::ErrorNotFound("Could not find server"))?;
+
+    if let Some(until) = blocked_until {
+        server_list.block_relist(
+            &server.server.ip,
+            server.server.port,
+            RelistBlock {
+                reason: reason.clone(),
+                until,
+                issued_by: user.username.clone(),
+            },
+        );
+    }
+
+    auditor
+        .record(
+            "server_delist",
+            Some(&request.server_uid),
+            json!({
+                "name": server.server.name,
+                "address": format!("{}:{}", server.server.ip, server.server.port),
+                "reason": reason,
+                "blocked_until": blocked_until,
+            }),
+        )
+        .await;
+
+    let mut notification = format!("'{}' was delisted: {}", server.server.name, reason);
+    if let Some(until) = blocked_until {
+        notification.push_str(&format!(", relisting is blocked until {}", format_time(until)));
+    }
+    notify_owner(server.internal.owner.as_deref(), &notification).await;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[post(
+    "/management/relist_block/lift",
+    wrap = "RequirePermission(Permission::ManageServers)"
+)]
+pub async fn lift_relist_block(
+    request: web::Json<LiftRelistBlockRequest>,
+    auditor: Auditor,
+) -> Result<HttpResponse, Error> {
+    match get_master_server()
+        .server_list
+        .lift_relist_block(&request.0.address)
+    {
+        Some(block) => {
+            auditor
+                .record(
+                    "relist_block_lift",
+                    Some(&request.0.address),
+                    json!({ "reason": block.reason }),
+                )
+                .await;
+            Ok(HttpResponse::Ok().finish())
+        }
+        None => Err(error::ErrorNotFound("No block for that address")),
+    }
+}
+
+#[post(
+    "/management/pending/approve",
+    wrap = "RequirePermission(Permission::ManageServers)"
+)]
+pub async fn approve_pending_server(
+    request: web::Json<PendingServerRequest>,
+    auditor: Auditor,
+) -> Result<HttpResponse, Error> {
+    let server = get_master_server()
+        .server_list
+        .approve_pending(&request.0.server_uid)
+        .ok_or_else(|| error::ErrorNotFound("Could not find pending server"))?;
+
+    auditor
+        .record(
+            "server_approve",
+            Some(&request.0.server_uid),
+            json!({
+                "name": server.server.name,
+                "address": format!("{}:{}", server.server.ip, server.server.port),
+            }),
+        )
+        .await;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[post(
+    "/management/pending/reject",
+    wrap = "RequirePermission(Permission::ManageServers)"
+)]
+pub async fn reject_pending_server(
+    request: web::Json<RejectPendingRequest>,
+    user: web::ReqData<PanelUser>,
+    auditor: Auditor,
+) -> Result<HttpResponse, Error> {
+    let request = request.into_inner();
+    let reason = match request.reason.trim() {
+        "" => String::from("Registration rejected"),
+        reason => reason.to_string(),
+    };
+
+    let blocked_until = block_until(request.block_minutes)?;
+
+    let server_list = &get_master_server().server_list;
+    let server = server_list
+        .remove_pending(&request.server_uid)
+        .ok_or_else(|| error::ErrorNotFound("Could not find pending server"))?;
+
+    if let Some(until) = blocked_until {
+        server_list.block_relist(
+            &server.server.ip,
+            server.server.port,
+            RelistBlock {
+                reason: reason.clone(),
+                until,
+                issued_by: user.username.clone(),
+            },
+        );
+    }
+
+    auditor
+        .record(
+            "server_reject",
+            Some(&request.server_uid),
+            json!({
+                "name": server.server.name,
+                "address": format!("{}:{}", server.server.ip, server.server.port),
+                "reason": reason,
+                "blocked_until": blocked_until,
+            }),
+        )
+        .await;
+
+    notify_owner(
+        server.internal.owner.as_deref(),
+        &format!("Registration of '{}' was rejected: {}", server.server.name, reason),
+    )
+    .await;
+
+    Ok(HttpResponse::Ok().finish())
+}
@@ -0,0 +1,97 @@
+use {
+    crate::{
+        audit::Auditor,
+        database::sessions::{get_active_sessions, revoke_session},
+        endpoints::panel::{get_ms_post_js, GENERIC_STYLE},
+        middleware::{auth::RequirePermission, csrf::CsrfToken},
+        permissions::Permission,
+    },
+    actix_web::{error, get, post, web, Error, HttpResponse},
+    maud::{html, Markup, PreEscaped, DOCTYPE},
+    serde::Deserialize,
+    serde_json::json,
+    shared::ms_config::get_global_config,
+};
+
+#[derive(Deserialize)]
+pub struct RevokeSessionRequest {
+    pub session_id: i32,
+}
+
+#[get("/sessions", wrap = "RequirePermission(Permission::ManageSessions)")]
+pub async fn session_list(csrf: CsrfToken) -> actix_web::Result<Markup> {
+    let sessions = match get_global_config().server_side_sessions {
+        true => get_active_sessions().await,
+        false => None,
+    };
+
+    Ok(html! {
+        (DOCTYPE)
+        meta name="viewport" content="width=device-width, initial-scale=1.0";
+        html lang = "en" {
+            (GENERIC_STYLE)
+            (csrf)
+            (PreEscaped(get_ms_post_js()))
+            (PreEscaped(r#"<script>
+                async function revoke_session_pressed(e) {
+                    const response = await ms_post("/panel/sessions/revoke", { session_id: Number(e.value) });
+                    if (response.status == 200) {
+                        window.location.reload();
+                    }
+                }
+                </script>"#))
+            title {"Sessions"}
+
+            body {
+                h1 {"Active Sessions"}
+
+                @if !get_global_config().server_side_sessions {
+                    p {"Server side sessions are disabled, sessions can only be listed and revoked when they are stored in the database"}
+                } @else if let Some(sessions) = sessions {
+                    table {
+                        tr {
+                            th {"User"}
+                            th {"Logged in"}
+                            th {"Expires"}
+                            th;
+                        }
+
+                        @for session in sessions.iter() {
+                            tr {
+                                td {(session.username.clone().unwrap_or_else(String::new))}
+                                td {(session.created_on.to_rfc3339())}
+                                td {(session.expires_on.to_rfc3339())}
+                                td {button type = "button" value = (session.session_id) onclick = "revoke_session_pressed(this)" {"Revoke"}}
+                            }
+                        }
+                    }
+                } @else {
+                    p {"Failed to load sessions"}
+                }
+            }
+        }
+    })
+}
+
+#[post(
+    "/sessions/revoke",
+    wrap = "RequirePermission(Permission::ManageSessions)"
+)]
+pub async fn session_revoke(
+    request: web::Json<RevokeSessionRequest>,
+    auditor: Auditor,
+) -> Result<HttpResponse, Error> {
+    match revoke_session(request.0.session_id).await {
+        true => {
+            auditor
+                .record(
+                    "session_revoke",
+                    Some(&request.0.session_id.to_string()),
+                    json!({}),
+                )
+                .await;
+            Ok(HttpResponse::Ok().finish())
+        }
+        false => Err(error::ErrorNotFound("Session not found")),
+    }
+}
@@ -0,0 +1,262 @@
+use {
+    crate::{
+        audit::Auditor,
+        database::users::{
+            create_user as db_create_user, disable_totp, get_users, reset_password,
+            set_user_disabled, set_user_role, unlock_user,
+        },
+        endpoints::panel::{get_ms_post_js, get_user_management_js, GENERIC_STYLE},
+        middleware::{auth::RequirePermission, csrf::CsrfToken},
+        permissions::{PanelUser, Permission, Role},
+    },
+    actix_web::{error, get, post, web, Error, HttpResponse},
+    maud::{html, Markup, PreEscaped, DOCTYPE},
+    serde::Deserialize,
+    serde_json::json,
+};
+
+#[derive(Deserialize)]
+pub struct CreateUserRequest {
+    pub username: String,
+    pub role: Role,
+}
+
+#[derive(Deserialize)]
+pub struct UsernameRequest {
+    pub username: String,
+}
+
+#[derive(Deserialize)]
+pub struct DisableUserRequest {
+    pub username: String,
+    pub disabled: bool,
+}
+
+#[derive(Deserialize)]
+pub struct ChangeRoleRequest {
+    pub username: String,
+    pub role: Role,
+}
+
+#[get("/users", wrap = "RequirePermission(Permission::ManageUsers)")]
+pub async fn user_list(
+    user: web::ReqData<PanelUser>,
+    csrf: CsrfToken,
+) -> actix_web::Result<Markup> {
+    let users = get_users().await;
+
+    Ok(html! {
+        (DOCTYPE)
+        meta name="viewport" content="width=device-width, initial-scale=1.0";
+        html lang = "en" {
+            (GENERIC_STYLE)
+            (csrf)
+            (PreEscaped(get_ms_post_js()))
+            (PreEscaped(get_user_management_js()))
+            title {"Users"}
+
+            body {
+                h1 {"Users"}
+
+                h2 {"Create user"}
+                label for = "new_username" {"Username "}
+                input type = "text" id = "new_username";
+                select id = "new_role" {
+                    @for role in Role::ALL {
+                        option value = (role.as_str()) {(role.as_str())}
+                    }
+                }
+                button type = "button" onclick = "create_user()" {"Create"}
+                p id = "user_message";
+
+                h2 {"Accounts"}
+                @if let Some(users) = users {
+                    table {
+                        tr {
+                            th {"Username"}
+                            th {"Role"}
+                            th {"Status"}
+                            th {"Created"}
+                            th {"Last login"}
+                            th {"2FA"}
+                            th;
+                            th;
+                        }
+
+                        @for row in users.iter() {
+                            @let is_self = row.username == user.username;
+                            tr {
+                                td {(&row.username)}
+                                td {
+                                    select name = (&row.username) onchange = "role_changed(this)" disabled[is_self] {
+                                        @for role in Role::ALL {
+                                            option value = (role.as_str()) selected[role.as_str() == row.role] {(role.as_str())}
+                                        }
+                                    }
+                                }
+                                td {
+                                    @if row.disabled {
+                                        "Disabled"
+                                    } @else if let Some(locked_until) = row.locked_until {
+                                        (format!("Locked until {} ", locked_until.to_rfc3339()))
+                                        button type = "button" value = (&row.username) onclick = "unlock_pressed(this)" {"Unlock"}
+                                    } @else if row.pending_invite {
+                                        "Invite pending"
+                                    } @else {
+                                        "Active"
+                                    }
+                                }
+                                td {(row.created_on.to_rfc3339())}
+                                td {(row.last_login.map(|time| time.to_rfc3339()).unwrap_or_else(|| String::from("Never")))}
+                                td {
+                                    @if row.totp_enabled {
+                                        "Enabled "
+                                        button type = "button" value = (&row.username) onclick = "reset_totp_pressed(this)" {"Reset"}
+                                    } @else {
+                                        "Disabled"
+                                    }
+                                }
+                                td {button type = "button" value = (&row.username) onclick = "reset_password_pressed(this)" {"Reset password"}}
+                                td {
+                                    @if !is_self {
+                                        @if row.disabled {
+                                            button type = "button" value = (&row.username) onclick = "set_disabled_pressed(this, false)" {"Enable"}
+                                        } @else {
+                                            button type = "button" value = (&row.username) onclick = "set_disabled_pressed(this, true)" {"Disable"}
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                } @else {
+                    p {"Failed to load users"}
+                }
+            }
+        }
+    })
+}
+
+#[post("/users/create", wrap = "RequirePermission(Permission::ManageUsers)")]
+pub async fn create_user(
+    request: web::Json<CreateUserRequest>,
+    auditor: Auditor,
+) -> Result<HttpResponse, Error> {
+    match db_create_user(&request.0.username, request.0.role).await {
+        Ok(token) => {
+            auditor
+                .record(
+                    "user_create",
+                    Some(&request.0.username),
+                    json!({ "role": request.0.role }),
+                )
+                .await;
+            Ok(HttpResponse::Ok().json(json!({ "token": token })))
+        }
+        Err(err) => Err(error::ErrorBadRequest(err)),
+    }
+}
+
+#[post("/users/reset", wrap = "RequirePermission(Permission::ManageUsers)")]
+pub async fn reset_user_password(
+    request: web::Json<UsernameRequest>,
+    auditor: Auditor,
+) -> Result<HttpResponse, Error> {
+    match reset_password(&request.0.username).await {
+        Ok(token) => {
+            auditor
+                .record("user_password_reset", Some(&request.0.username), json!({}))
+                .await;
+            Ok(HttpResponse::Ok().json(json!({ "token": token })))
+        }
+        Err(err) => Err(error::ErrorBadRequest(err)),
+    }
+}
+
+#[post("/users/disable", wrap = "RequirePermission(Permission::ManageUsers)")]
+pub async fn disable_user(
+    request: web::Json<DisableUserRequest>,
+    user: web::ReqData<PanelUser>,
+    auditor: Auditor,
+) -> Result<HttpResponse, Error> {
+    if request.0.username == user.username {
+        return Err(error::ErrorBadRequest(
+            "You can not disable your own account",
+        ));
+    }
+
+    match set_user_disabled(&request.0.username, request.0.disabled).await {
+        Ok(_) => {
+            let action = match request.0.disabled {
+                true => "user_disable",
+                false => "user_enable",
+            };
+            auditor
+                .record(action, Some(&request.0.username), json!({}))
+                .await;
+            Ok(HttpResponse::Ok().finish())
+        }
+        Err(err) => Err(error::ErrorBadRequest(err)),
+    }
+}
+
+#[post("/users/role", wrap = "RequirePermission(Permission::ManageUsers)")]
+pub async fn change_user_role(
+    request: web::Json<ChangeRoleRequest>,
+    user: web::ReqData<PanelUser>,
+    auditor: Auditor,
+) -> Result<HttpResponse, Error> {
+    if request.0.username == user.username {
+        return Err(error::ErrorBadRequest("You can not change your own role"));
+    }
+
+    match set_user_role(&request.0.username, request.0.role).await {
+        Ok(_) => {
+            auditor
+                .record(
+                    "user_role_change",
+                    Some(&request.0.username),
+                    json!({ "role": request.0.role }),
+                )
+                .await;
+            Ok(HttpResponse::Ok().finish())
+        }
+        Err(err) => Err(error::ErrorBadRequest(err)),
+    }
+}
+
+//For users that have lost their authenticator and recovery codes
+#[post(
+    "/users/reset_totp",
+    wrap = "RequirePermission(Permission::ManageUsers)"
+)]
+pub async fn reset_user_totp(
+    request: web::Json<UsernameRequest>,
+    auditor: Auditor,
+) -> Result<HttpResponse, Error> {
+    match disable_totp(&request.0.username).await {
+        Ok(_) => {
+            auditor
+                .record("user_totp_reset", Some(&request.0.username), json!({}))
+                .await;
+            Ok(HttpResponse::Ok().finish())
+        }
+        Err(err) => Err(error::ErrorBadRequest(err)),
+    }
+}
+
+#[post("/users/unlock", wrap = "RequirePermission(Permission::ManageUsers)")]
+pub async fn unlock_user_account(
+    request: web::Json<UsernameRequest>,
+    auditor: Auditor,
+) -> Result<HttpResponse, Error> {
+    match unlock_user(&request.0.username).await {
+        Ok(_) => {
+            auditor
+                .record("user_unlock", Some(&request.0.username), json!({}))
+                .await;
+            Ok(HttpResponse::Ok().finish())
+        }
+        Err(err) => Err(error::ErrorBadRequest(err)),
+    }
+}
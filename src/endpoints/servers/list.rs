@@ -11,6 +11,7 @@ use {
         sync::atomic::Ordering,
         time::{Duration, SystemTime, UNIX_EPOCH},
     },
+    tracing::error,
     uuid::Uuid,
 };
 
@@ -61,7 +62,7 @@ pub async fn list_servers() -> Result<HttpResponse, Error> {
 
     let json = match serde_json::to_string(&response) {
         Err(err) => {
-            println!("Failed to serialise server list request json {}", err);
+            error!("Failed to serialise server list request json {}", err);
             return Err(error::ErrorInternalServerError(ms_error_format(
                 "Failed to build response json",
             )));
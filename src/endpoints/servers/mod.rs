@@ -1,5 +1,6 @@
 mod list;
 mod post;
+mod remove;
 
 use actix_web::{
     self,
@@ -10,6 +11,7 @@ pub fn servers_routes(cfg: &mut ServiceConfig) {
     cfg.service(
         scope("/servers")
             .service(post::post)
+            .service(remove::remove)
             .service(list::list_servers)
             .service(list::get_server_by_token)
     );
@@ -1,13 +1,26 @@
 use {
-    crate::{get_master_server, wrappers},
+    crate::{
+        client_ip::client_ip,
+        database::server_keys::{get_key_status, KeyStatus},
+        get_master_server,
+        metrics::get_metrics,
+        middleware::rate_limit::too_many_requests,
+        registration_limits::get_registration_limits,
+        server_list::{AddServerError, PostKind},
+        shutdown::is_shutting_down,
+        wrappers,
+    },
     actix_web::{error, post, web, Error, HttpRequest, HttpResponse},
     shared::{
         ms_config::get_global_config,
         responses::{ms_error_format, ms_return_host_info},
         server::ServerWithUID,
     },
-    std::time::Duration,
-    tracing::{debug, error, span, Level},
+    std::{
+        net::{IpAddr, SocketAddr},
+        time::Duration,
+    },
+    tracing::{debug, error, span, warn, Level},
 };
 
 #[post("/add")]
@@ -24,17 +37,20 @@ pub async fn post(
     );
 
     let _enter = span.enter();
+    let metrics = get_metrics();
 
     match wrappers::validate_server_values(&server.server) {
         Ok(_) => {}
         Err(error) => {
             debug!("Server field validation error: {}", error);
+            metrics.validation_failure("values");
             return Err(error::ErrorBadRequest(ms_error_format(error)));
         }
     }
 
-    let mut sock_adr = match req.peer_addr() {
-        Some(adr) => adr,
+    //Behind a trusted proxy this is the address the proxy says the server connected from
+    let sock_adr = match client_ip(&req) {
+        Some(ip) => SocketAddr::new(ip, server.server.port),
         None => {
             error!("Actix peer_addr was None");
             return Err(error::ErrorInternalServerError(ms_error_format(
@@ -43,7 +59,69 @@ pub async fn post(
         }
     };
 
-    sock_adr.set_port(server.server.port);
+    if let Some(block) = get_master_server()
+        .server_list
+        .relist_block(&sock_adr.ip().to_string(), server.server.port)
+    {
+        debug!("Server is blocked from relisting until {}", block.until);
+        metrics.validation_failure("relist_blocked");
+        return Err(error::ErrorForbidden(ms_error_format(format!(
+            "This server was delisted: {}",
+            block.reason
+        ))));
+    }
+
+    let server_list = &get_master_server().server_list;
+    let limits = get_registration_limits();
+    let ip = sock_adr.ip();
+
+    let kind = match server_list.post_kind(&server.uid, server.server.hidden) {
+        Ok(kind) => kind,
+        Err(err) => return Ok(rejected(err, &server.uid, ip)),
+    };
+
+    match kind {
+        PostKind::Registration => {
+            if let Some(response) = registration_refused(ip, &server.server.key) {
+                return Ok(response);
+            }
+        }
+        PostKind::Heartbeat => {
+            if let Err(retry_after) = limits.take_heartbeat(ip) {
+                metrics.validation_failure("heartbeat_rate");
+                return Ok(too_many_requests("Too many updates", retry_after));
+            }
+        }
+    }
+
+    let require_known_key = get_global_config().require_known_server_key;
+    let owner = match get_key_status(&server.server.key, server.key_challenge.as_deref()).await {
+        Ok(KeyStatus::Owned(owner)) => Some(owner),
+        Ok(KeyStatus::Revoked) => {
+            metrics.validation_failure("revoked_key");
+            return Err(error::ErrorForbidden(ms_error_format(
+                "This server key has been revoked",
+            )));
+        }
+        Ok(KeyStatus::Unknown) if require_known_key => {
+            metrics.validation_failure("unknown_key");
+            return Err(error::ErrorForbidden(ms_error_format(
+                "Unknown server key, it has to be registered by a host account first",
+            )));
+        }
+        Ok(KeyStatus::Unknown) => None,
+        Err(err) if require_known_key => {
+            error!("Failed to look up server key: {}", err);
+            return Err(error::ErrorServiceUnavailable(ms_error_format(
+                "Could not check server key",
+            )));
+        }
+        //Without a database there are no registered keys to check against, a listed server keeps the owner
+        //it has so a failed lookup does not move it out of its host's list
+        Err(_) => server_list
+            .with_listed_server(&server.uid, |listed| listed.internal.owner.clone())
+            .flatten(),
+    };
 
     let duration =
         Duration::from_millis(get_global_config().server_conn_validation_listen_timeout as u64);
@@ -53,19 +131,147 @@ pub async fn post(
             .unwrap_or(false);
 
     if !connection_valid {
+        metrics.validation_failure("connection");
         return Err(error::ErrorNotAcceptable(ms_error_format(
             "Unable to communicate, please forward your ports and check if the server is publicly accessible",
         )));
     };
 
-    match get_master_server()
-        .server_list
-        .add_server(server.0, sock_adr)
-        .await
+    let uid = server.0.uid.clone();
+    //Only checked once the server has proven it is reachable, so junk requests do not set it off
+    let quarantine = kind == PostKind::Registration && limits.record_registration();
+
+    let mut result = server_list
+        .add_server(server.0.clone(), sock_adr, owner.clone(), kind, quarantine)
+        .await;
+
+    //The server expired while it was being checked, so this post lists it again and has to pass as a registration
+    let mut kind = kind;
+    if let Err(AddServerError::NotListed) = result {
+        if let Some(response) = registration_refused(ip, &server.server.key) {
+            return Ok(response);
+        }
+
+        kind = PostKind::Registration;
+        result = server_list
+            .add_server(
+                server.0,
+                sock_adr,
+                owner,
+                kind,
+                limits.record_registration(),
+            )
+            .await;
+    }
+
+    match result {
+        Ok(server) => {
+            match kind {
+                PostKind::Registration => metrics.registrations.inc(),
+                PostKind::Heartbeat => metrics.heartbeats.inc(),
+            }
+            Ok(HttpResponse::Ok().body(ms_return_host_info(server)))
+        }
+        Err(err) => Ok(rejected(err, &uid, ip)),
+    }
+}
+
+//Limits that only apply to new servers, a heartbeat from a listed server is never refused by these
+fn registration_refused(ip: IpAddr, key: &str) -> Option<HttpResponse> {
+    let metrics = get_metrics();
+
+    //Listed servers keep heartbeating until the end so they are not dropped by the next instance
+    if is_shutting_down() {
+        metrics.validation_failure("shutting_down");
+        return Some(
+            error::ErrorServiceUnavailable(ms_error_format("Master server is shutting down"))
+                .error_response(),
+        );
+    }
+
+    if let Err(retry_after) = get_registration_limits().take_registration(ip) {
+        metrics.validation_failure("registration_rate");
+        return Some(too_many_requests("Too many registrations", retry_after));
+    }
+
+    let cfg = get_global_config();
+    let server_list = &get_master_server().server_list;
+    let ip_str = ip.to_string();
+    if cfg.max_servers_per_ip != 0
+        && server_list.count_servers(|listed| listed.server.ip == ip_str)
+            >= cfg.max_servers_per_ip as usize
     {
-        Some(server) => Ok(HttpResponse::Ok().body(ms_return_host_info(server))),
-        None => Err(error::ErrorInternalServerError(ms_error_format(
-            "Failed to add server to server list",
-        ))),
+        metrics.validation_failure("servers_per_ip");
+        return Some(
+            error::ErrorForbidden(ms_error_format("Too many servers listed from this address"))
+                .error_response(),
+        );
+    }
+
+    if cfg.max_servers_per_key != 0
+        && server_list.count_servers(|listed| listed.server.key == key)
+            >= cfg.max_servers_per_key as usize
+    {
+        metrics.validation_failure("servers_per_key");
+        return Some(
+            error::ErrorForbidden(ms_error_format("Too many servers listed with this key"))
+                .error_response(),
+        );
+    }
+
+    None
+}
+
+fn rejected(err: AddServerError, uid: &str, ip: IpAddr) -> HttpResponse {
+    let metrics = get_metrics();
+
+    match err {
+        AddServerError::Unauthorized(reason) => {
+            metrics.validation_failure(reason.as_str());
+            warn!(
+                target: "security",
+                event = "server_update_rejected",
+                reason = reason.as_str(),
+                uid = %uid,
+                ip = %ip,
+                "Rejected server update"
+            );
+            error::ErrorForbidden(ms_error_format(reason.message())).error_response()
+        }
+        AddServerError::VisibilityMismatch => {
+            metrics.validation_failure("visibility_mismatch");
+            warn!(
+                target: "security",
+                event = "server_update_rejected",
+                reason = "visibility_mismatch",
+                uid = %uid,
+                ip = %ip,
+                "Rejected server update"
+            );
+            error::ErrorForbidden(ms_error_format(
+                "This uid belongs to a server listed with a different visibility, register again without a uid",
+            ))
+            .error_response()
+        }
+        AddServerError::KeyMismatch => {
+            metrics.validation_failure("key_mismatch");
+            warn!(
+                target: "security",
+                event = "server_update_rejected",
+                reason = "key_mismatch",
+                uid = %uid,
+                ip = %ip,
+                "Rejected server update"
+            );
+            error::ErrorForbidden(ms_error_format(
+                "A listed server's key can not change, register again without a uid",
+            ))
+            .error_response()
+        }
+        //Only returned for heartbeats, which are retried as a registration before getting here
+        AddServerError::NotListed | AddServerError::Internal => {
+            error::ErrorInternalServerError(ms_error_format("Failed to add server to server list"))
+                .error_response()
+        }
     }
 }
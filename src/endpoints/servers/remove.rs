@@ -0,0 +1,71 @@
+use {
+    crate::{
+        client_ip::client_ip_string,
+        get_master_server,
+        server_list::{authenticate_server, current_time},
+    },
+    actix_web::{error, post, web, Error, HttpRequest, HttpResponse},
+    serde::Deserialize,
+    shared::responses::{ms_error_format, ms_success_response},
+    tracing::{info, warn},
+    uuid::Uuid,
+};
+
+#[derive(Deserialize)]
+pub struct RemoveServerRequest {
+    pub uid: String,
+    //Only hidden servers have one, and they have to send it
+    pub token: Option<Uuid>,
+    //Always required, the option is only so a missing secret gets a clear error
+    pub secret: Option<String>,
+}
+
+//Called by a server as it shuts down so it does not linger on the list until it times out
+#[post("/remove")]
+pub async fn remove(
+    req: HttpRequest,
+    request: web::Json<RemoveServerRequest>,
+) -> Result<HttpResponse, Error> {
+    let server_list = &get_master_server().server_list;
+    let ip = client_ip_string(&req);
+
+    let mut server = match server_list.find_server_from_uid(request.uid.clone()) {
+        Some(server) => server,
+        None => return Err(error::ErrorNotFound(ms_error_format("Server not found"))),
+    };
+
+    //Has to come from the address the server is listed under as well as carry its secret, even when heartbeats
+    //without one are still accepted. Builds that do not send it can not remove themselves and time out instead
+    let secret = match request.secret.as_deref() {
+        Some(secret) => secret,
+        None => {
+            return Err(error::ErrorForbidden(ms_error_format(
+                "The server's secret is required to remove it, servers without one are removed once they time out",
+            )))
+        }
+    };
+
+    if server.server.ip != ip
+        || server.internal.token != request.token
+        || authenticate_server(&mut server, Some(secret), &ip, current_time()).is_err()
+    {
+        warn!(
+            target: "security",
+            event = "server_remove_rejected",
+            uid = %request.uid,
+            ip = %ip,
+            "Rejected server removal"
+        );
+        return Err(error::ErrorForbidden(ms_error_format(
+            "Not allowed to remove this server",
+        )));
+    }
+
+    server_list.remove_server(&request.uid);
+    info!(
+        "Server '{}' ({}) removed itself",
+        server.server.name, request.uid
+    );
+
+    Ok(HttpResponse::Ok().body(ms_success_response()))
+}
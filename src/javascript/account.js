@@ -0,0 +1,75 @@
+<script>
+
+async function change_password() {
+    const current_password = document.getElementById("current_password").value;
+    const new_password = document.getElementById("new_password").value;
+    const confirm_password = document.getElementById("confirm_password").value;
+    const message = document.getElementById("password_message");
+
+    if (new_password != confirm_password) {
+        message.innerText = "Passwords do not match";
+        return;
+    }
+
+    const response = await ms_post("/panel/account/password", {
+        current_password: current_password,
+        new_password: new_password,
+    });
+
+    if (response.status != 200) {
+        message.innerText = "Failed to change password: " + await response.text();
+    } else {
+        message.innerText = "Password changed";
+    }
+}
+
+async function enable_totp() {
+    const code = document.getElementById("enable_totp_code").value;
+    const message = document.getElementById("totp_message");
+
+    const response = await ms_post("/panel/account/totp/enable", { code: code });
+
+    if (response.status != 200) {
+        message.innerText = "Failed to enable: " + await response.text();
+        return;
+    }
+
+    const codes = (await response.json())["recovery_codes"];
+    message.innerText = "Two factor authentication enabled. Store these recovery codes somewhere safe, each can be used once and they will not be shown again:";
+    document.getElementById("recovery_codes").innerText = codes.join("\n");
+}
+
+async function disable_totp() {
+    const code = document.getElementById("disable_totp_code").value;
+    const message = document.getElementById("totp_message");
+
+    const response = await ms_post("/panel/account/totp/disable", { code: code });
+
+    if (response.status != 200) {
+        message.innerText = "Failed to disable: " + await response.text();
+        return;
+    }
+
+    window.location.reload();
+}
+
+async function accept_invite(button) {
+    const password = document.getElementById("new_password").value;
+    const confirm_password = document.getElementById("confirm_password").value;
+    const message = document.getElementById("password_message");
+
+    if (password != confirm_password) {
+        message.innerText = "Passwords do not match";
+        return;
+    }
+
+    const response = await ms_post("/invite", { token: button.value, password: password });
+
+    if (response.status != 200) {
+        message.innerText = "Failed to set password: " + await response.text();
+    } else {
+        window.location.href = "/login";
+    }
+}
+
+</script>
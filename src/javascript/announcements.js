@@ -0,0 +1,58 @@
+<script>
+
+function show_announcement_message(msg) {
+    document.getElementById("announcement_message_status").innerText = msg;
+}
+
+//datetime-local inputs are in the browser's timezone, the server wants unix seconds
+function input_time(id) {
+    const value = document.getElementById(id).value;
+    return value == "" ? null : Math.floor(new Date(value).getTime() / 1000);
+}
+
+function input_list(id) {
+    return document.getElementById(id).value
+        .split(",")
+        .map(item => item.trim())
+        .filter(item => item != "");
+}
+
+async function create_announcement() {
+    const message = document.getElementById("announcement_message").value;
+
+    if (message == "") {
+        return;
+    }
+
+    const response = await ms_post("/panel/announcements/create", {
+        message: message,
+        severity: document.getElementById("announcement_severity").value,
+        starts_at: input_time("announcement_start"),
+        ends_at: input_time("announcement_end"),
+        versions: input_list("announcement_versions"),
+        playlists: input_list("announcement_playlists"),
+    });
+
+    if (response.status != 200) {
+        show_announcement_message("Failed to create announcement: " + await response.text());
+        return;
+    }
+
+    window.location.reload();
+}
+
+async function cancel_announcement_pressed(button) {
+    if (!confirm("Cancel this announcement? Servers that already have it will not be told")) {
+        return;
+    }
+
+    const response = await ms_post("/panel/announcements/cancel", { id: Number(button.value) });
+
+    if (response.status != 200) {
+        alert("Failed to cancel announcement: " + await response.text());
+        return;
+    }
+
+    window.location.reload();
+}
+</script>
@@ -0,0 +1,48 @@
+<script>
+
+function show_token_message(msg) {
+    document.getElementById("token_message").innerText = msg;
+}
+
+async function create_token() {
+    const name = document.getElementById("token_name").value;
+    const expiry = document.getElementById("token_expiry").value;
+
+    if (name == "") {
+        return;
+    }
+
+    const permissions = Array.from(document.getElementsByClassName("token_permission"))
+        .filter(checkbox => checkbox.checked)
+        .map(checkbox => checkbox.value);
+
+    const response = await ms_post("/panel/api_tokens/create", {
+        name: name,
+        permissions: permissions,
+        expires_in_days: expiry == "" ? null : Number(expiry),
+    });
+
+    if (response.status != 200) {
+        show_token_message("Failed to create token: " + await response.text());
+        return;
+    }
+
+    const token = (await response.json())["token"];
+    show_token_message("Token created, copy it now as it will not be shown again: " + token);
+}
+
+async function revoke_token_pressed(button) {
+    if (!confirm("Revoke this token? Anything using it will stop working")) {
+        return;
+    }
+
+    const response = await ms_post("/panel/api_tokens/revoke", { id: Number(button.value) });
+
+    if (response.status != 200) {
+        show_token_message("Failed to revoke token: " + await response.text());
+        return;
+    }
+
+    window.location.reload();
+}
+</script>
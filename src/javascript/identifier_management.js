@@ -73,11 +73,14 @@ async function check_identifier() {
 
         row.insertCell(3).innerText = response[i]["reason"]
 
-        let button = document.createElement("button");
-        button.innerText = "Unban";
-        button.id = response[i]["ban_id"];
-        button.addEventListener("click", unban);
-        row.insertCell(4).append(button);
+        //The ban form is only rendered for users that are allowed to ban and unban
+        if (document.getElementById("ban_button") != null) {
+            let button = document.createElement("button");
+            button.innerText = "Unban";
+            button.id = response[i]["ban_id"];
+            button.addEventListener("click", unban);
+            row.insertCell(4).append(button);
+        }
     }
 }
 
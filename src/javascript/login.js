@@ -1,4 +1,14 @@
 <script>
+function show_login_failure(status_box, response, message) {
+    if (response.status == 429) {
+        const retry_after = response.headers.get("Retry-After");
+        status_box.innerText = "Too many failed attempts, try again in " + retry_after + " seconds";
+        return;
+    }
+
+    status_box.innerText = message;
+}
+
 async function post_login() {
     const username = document.getElementById("username").value;
     const password = document.getElementById("password").value;
@@ -9,11 +19,36 @@ async function post_login() {
     };
 
     const response = await ms_post("/panel/auth", login_info);
+    const status_box = document.getElementById("login_message");
+
+    if (response.status != 200) {
+        show_login_failure(status_box, response, "Login Failure");
+        return;
+    }
+
+    const result = await response.json();
+
+    if (result["totp_required"]) {
+        document.getElementById("login_button").hidden = true;
+        document.getElementById("totp_form").hidden = false;
+        status_box.innerText = "";
+        return;
+    }
+
+    window.location.href = "/panel/";
+}
+
+async function post_totp() {
+    const code = document.getElementById("totp_code").value;
+
+    const response = await ms_post("/panel/auth/totp", { code: code });
 
     if (response.status != 200) {
         const status_box = document.getElementById("login_message");
-        status_box.innerText = "Login Failure"
+        show_login_failure(status_box, response, "Invalid code");
+        return;
     }
 
+    window.location.href = "/panel/";
 }
-</script>
\ No newline at end of file
+</script>
@@ -1,12 +1,18 @@
 <script>
 async function ms_post(endpoint, body) {
-    let url = "https://localhost" + endpoint;
+    const headers = {
+        "Content-Type": "application/json"
+    };
 
-    const response = await fetch(url, {
+    //Panel pages embed the session's CSRF token, the login and invite pages dont have one
+    const csrf_token = document.querySelector("meta[name='csrf-token']");
+    if (csrf_token != null) {
+        headers["X-CSRF-Token"] = csrf_token.content;
+    }
+
+    const response = await fetch(endpoint, {
         method: "POST",
-        headers: {
-            "Content-Type": "application/json"
-        },
+        headers: headers,
         credentials: "same-origin",
         body: JSON.stringify(body),
     });
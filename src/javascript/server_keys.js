@@ -0,0 +1,39 @@
+<script>
+
+async function register_key() {
+    const name = document.getElementById("key_name").value;
+    const key = document.getElementById("server_key").value;
+
+    if (name == "" || key == "") {
+        return;
+    }
+
+    const response = await ms_post("/panel/server_keys/register", { name: name, server_key: key });
+
+    if (response.status != 200) {
+        document.getElementById("key_message").innerText = "Failed to register key: " + await response.text();
+        return;
+    }
+
+    //Only shown once, the master server keeps a hash of it
+    const result = await response.json();
+    document.getElementById("key_message").innerText = "Registered. Set keyChallenge to " + result.challenge + " in your server's posts to verify the key, it is only shown once.";
+}
+
+async function revoke_key_pressed(button) {
+    const reason = prompt("Revoke this key? Every server using it will be delisted and the key can not be used again.\nReason:");
+
+    if (reason == null || reason == "") {
+        return;
+    }
+
+    const response = await ms_post("/panel/server_keys/revoke", { id: Number(button.value), reason: reason });
+
+    if (response.status != 200) {
+        alert("Failed to revoke key: " + await response.text());
+        return;
+    }
+
+    window.location.reload();
+}
+</script>
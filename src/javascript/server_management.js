@@ -4,24 +4,125 @@ async function kick_button_pressed(button) {
     let uids = []
     
     for (let i = 0; i < players.length; i++) {
-        uids.push(Number(players[i].value))
-    } 
+        if (players[i].checked) {
+            uids.push(Number(players[i].value))
+        }
+    }
+
+    const cooldown = document.getElementById("kick_cooldown").value;
 
     const request = {
         server_uid: button.value,
-        player_uids: uids
+        player_uids: uids,
+        reason: document.getElementById("kick_reason").value,
+        cooldown_seconds: cooldown == "" ? null : Number(cooldown) * 60,
     };
 
-    console.log(request)
-
     const response = await ms_post("/panel/kick", request);
 
     let kick_message = document.getElementById("kick_message")
 
     if (response.status == 200 ) {
-        kick_message.innerText = "Selcted player(s) will be kicked on next bulk check"
+        window.location.reload();
     } else {
         kick_message.innerText = "Kick request failed"
     }
 }
+
+async function send_command(server_uid, command) {
+    const response = await ms_post("/panel/management/server/command", { server_uid: server_uid, ...command });
+
+    if (response.status == 200) {
+        window.location.reload();
+    } else {
+        document.getElementById("command_message").innerText = "Command failed: " + await response.text();
+    }
+}
+
+async function broadcast_pressed(button) {
+    await send_command(button.value, {
+        type: "broadcast",
+        message: document.getElementById("broadcast_message").value,
+    });
+}
+
+async function change_map_pressed(button) {
+    await send_command(button.value, {
+        type: "change_map",
+        map: document.getElementById("change_map").value,
+        playlist: document.getElementById("change_playlist").value,
+    });
+}
+
+async function delist_notice_pressed(button) {
+    await send_command(button.value, {
+        type: "delist_notice",
+        reason: document.getElementById("delist_reason").value,
+    });
+}
+
+async function delist_pressed(button) {
+    const reason = document.getElementById("delist_server_reason").value;
+    const block = document.getElementById("delist_block_minutes").value;
+
+    if (reason == "" || !confirm("Remove this server from the list?")) {
+        return;
+    }
+
+    const response = await ms_post("/panel/management/server/delist", {
+        server_uid: button.value,
+        reason: reason,
+        block_minutes: block == "" ? null : Number(block),
+    });
+
+    if (response.status == 200) {
+        window.location.href = "/panel/list";
+    } else {
+        document.getElementById("delist_message").innerText = "Delist failed: " + await response.text();
+    }
+}
+
+async function approve_pending_pressed(button) {
+    const response = await ms_post("/panel/management/pending/approve", { server_uid: button.value });
+
+    if (response.status == 200) {
+        window.location.reload();
+    } else {
+        alert("Failed to approve server: " + await response.text());
+    }
+}
+
+async function reject_pending_pressed(button) {
+    const reason = prompt("Reason for rejecting this server:");
+    if (reason == null) {
+        return;
+    }
+
+    const block = prompt("Block it from registering again for how many minutes? (empty for no block)");
+    if (block == null) {
+        return;
+    }
+
+    const response = await ms_post("/panel/management/pending/reject", {
+        server_uid: button.value,
+        reason: reason,
+        block_minutes: block == "" ? null : Number(block),
+    });
+
+    if (response.status == 200) {
+        window.location.reload();
+    } else {
+        alert("Failed to reject server: " + await response.text());
+    }
+}
+
+async function lift_block_pressed(button) {
+    const response = await ms_post("/panel/management/relist_block/lift", { address: button.value });
+
+    if (response.status == 200) {
+        window.location.reload();
+    } else {
+        alert("Failed to lift block: " + await response.text());
+    }
+}
 </script>
\ No newline at end of file
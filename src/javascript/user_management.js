@@ -0,0 +1,90 @@
+<script>
+
+function show_user_message(msg) {
+    document.getElementById("user_message").innerText = msg;
+}
+
+async function create_user() {
+    const username = document.getElementById("new_username").value;
+    const role = document.getElementById("new_role").value;
+
+    if (username == "") {
+        return;
+    }
+
+    const response = await ms_post("/panel/users/create", { username: username, role: role });
+
+    if (response.status != 200) {
+        show_user_message("Failed to create user: " + await response.text());
+        return;
+    }
+
+    const token = (await response.json())["token"];
+    show_user_message("User created, send them this invite link: " + window.location.origin + "/invite/" + token);
+}
+
+async function reset_password_pressed(button) {
+    if (!confirm("Reset the password for " + button.value + "? They will be logged out")) {
+        return;
+    }
+
+    const response = await ms_post("/panel/users/reset", { username: button.value });
+
+    if (response.status != 200) {
+        show_user_message("Failed to reset password: " + await response.text());
+        return;
+    }
+
+    const token = (await response.json())["token"];
+    show_user_message("Password reset, send them this link: " + window.location.origin + "/invite/" + token);
+}
+
+async function reset_totp_pressed(button) {
+    if (!confirm("Remove two factor authentication for " + button.value + "?")) {
+        return;
+    }
+
+    const response = await ms_post("/panel/users/reset_totp", { username: button.value });
+
+    if (response.status != 200) {
+        show_user_message("Failed to reset two factor authentication: " + await response.text());
+        return;
+    }
+
+    window.location.reload();
+}
+
+async function unlock_pressed(button) {
+    const response = await ms_post("/panel/users/unlock", { username: button.value });
+
+    if (response.status != 200) {
+        show_user_message("Failed to unlock user: " + await response.text());
+        return;
+    }
+
+    window.location.reload();
+}
+
+async function set_disabled_pressed(button, disabled) {
+    const response = await ms_post("/panel/users/disable", { username: button.value, disabled: disabled });
+
+    if (response.status != 200) {
+        show_user_message("Failed to update user: " + await response.text());
+        return;
+    }
+
+    window.location.reload();
+}
+
+async function role_changed(select) {
+    const response = await ms_post("/panel/users/role", { username: select.name, role: select.value });
+
+    if (response.status != 200) {
+        show_user_message("Failed to change role: " + await response.text());
+        return;
+    }
+
+    show_user_message("Role for " + select.name + " changed to " + select.value);
+}
+
+</script>
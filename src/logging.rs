@@ -0,0 +1,79 @@
+use {
+    shared::ms_config::{get_global_config, LogFormat},
+    tracing::error,
+    tracing_appender::non_blocking::WorkerGuard,
+    tracing_subscriber::{fmt::MakeWriter, layer::SubscriberExt, EnvFilter, Layer, Registry},
+};
+
+type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+fn format_layer<W>(writer: W, format: LogFormat, ansi: bool) -> BoxedLayer
+where
+    W: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+{
+    let layer = tracing_subscriber::fmt::layer()
+        .with_writer(writer)
+        .with_ansi(ansi);
+
+    match format {
+        LogFormat::Text => layer.boxed(),
+        LogFormat::Json => layer
+            .json()
+            .with_current_span(true)
+            .with_span_list(false)
+            .boxed(),
+    }
+}
+
+//RUST_LOG wins so a running deployment can be debugged without editing the config
+fn build_filter() -> (EnvFilter, Option<String>) {
+    let cfg = get_global_config();
+
+    let directives = match std::env::var("RUST_LOG") {
+        Ok(directives) if !directives.is_empty() => directives,
+        _ => std::iter::once(cfg.log_level.clone())
+            .chain(cfg.log_filters.iter().cloned())
+            .collect::<Vec<String>>()
+            .join(","),
+    };
+
+    match EnvFilter::try_new(&directives) {
+        Ok(filter) => (filter, None),
+        Err(err) => (
+            EnvFilter::new("info"),
+            Some(format!(
+                "Invalid log filter '{}', using info: {}",
+                directives, err
+            )),
+        ),
+    }
+}
+
+//The returned guard flushes the file writer when dropped, keep it alive for the life of the process
+pub fn init_logging() -> Option<WorkerGuard> {
+    let cfg = get_global_config();
+    let mut layers: Vec<BoxedLayer> = Vec::new();
+    let mut guard = None;
+
+    if cfg.log_stdout {
+        layers.push(format_layer(std::io::stdout, cfg.log_format, true));
+    }
+
+    if !cfg.log_directory.is_empty() {
+        let appender = tracing_appender::rolling::daily(&cfg.log_directory, &cfg.log_file_prefix);
+        let (writer, file_guard) = tracing_appender::non_blocking(appender);
+        layers.push(format_layer(writer, cfg.log_format, false));
+        guard = Some(file_guard);
+    }
+
+    let (filter, filter_error) = build_filter();
+    let subscriber = Registry::default().with(layers).with(filter);
+
+    tracing::subscriber::set_global_default(subscriber).expect("Failed to install logger");
+
+    if let Some(message) = filter_error {
+        error!("{}", message);
+    }
+
+    guard
+}
@@ -0,0 +1,104 @@
+use {
+    once_cell::sync::Lazy,
+    parking_lot::Mutex,
+    shared::ms_config::get_global_config,
+    std::{
+        collections::HashMap,
+        hash::Hash,
+        net::IpAddr,
+        time::{Duration, Instant},
+    },
+};
+
+//Failure counts are forgotten once nothing has failed for this long
+const RESET_AFTER: Duration = Duration::from_secs(60 * 60);
+//Usernames are attacker controlled, longer ones share the entry of their first characters
+const MAX_USERNAME_KEY_LENGTH: usize = 64;
+
+static LOGIN_THROTTLE: Lazy<LoginThrottle> = Lazy::new(LoginThrottle::default);
+
+pub fn get_login_throttle() -> &'static LoginThrottle {
+    &LOGIN_THROTTLE
+}
+
+struct Attempts {
+    failures: u32,
+    last_failure: Instant,
+}
+
+//Tracks failed panel logins per IP and per username, each failure past the free attempts doubles the wait
+#[derive(Default)]
+pub struct LoginThrottle {
+    by_ip: Mutex<HashMap<IpAddr, Attempts>>,
+    by_username: Mutex<HashMap<String, Attempts>>,
+}
+
+fn backoff(failures: u32) -> Duration {
+    let cfg = get_global_config();
+
+    if failures < cfg.login_free_attempts {
+        return Duration::ZERO;
+    }
+
+    let exponent = (failures - cfg.login_free_attempts).min(31);
+    Duration::from_secs((1u64 << exponent).min(cfg.login_backoff_max_seconds as u64))
+}
+
+//Every lookup and update goes through this so they always agree on the key
+fn username_key(username: &str) -> String {
+    username.chars().take(MAX_USERNAME_KEY_LENGTH).collect()
+}
+
+fn remaining<K: Eq + Hash>(map: &HashMap<K, Attempts>, key: &K) -> Option<Duration> {
+    let attempts = map.get(key)?;
+    let wait = backoff(attempts.failures).checked_sub(attempts.last_failure.elapsed())?;
+
+    match wait.is_zero() {
+        true => None,
+        false => Some(wait),
+    }
+}
+
+fn record<K: Eq + Hash>(map: &mut HashMap<K, Attempts>, key: K) -> u32 {
+    let now = Instant::now();
+    map.retain(|_, attempts| now.duration_since(attempts.last_failure) < RESET_AFTER);
+
+    let attempts = map.entry(key).or_insert(Attempts {
+        failures: 0,
+        last_failure: now,
+    });
+    attempts.failures += 1;
+    attempts.last_failure = now;
+    attempts.failures
+}
+
+impl LoginThrottle {
+    //How long the caller has to wait before another attempt is allowed, if at all
+    pub fn retry_after(&self, ip: Option<IpAddr>, username: &str) -> Option<Duration> {
+        let ip_wait = ip.and_then(|ip| remaining(&self.by_ip.lock(), &ip));
+        let username_wait = remaining(&self.by_username.lock(), &username_key(username));
+
+        ip_wait.max(username_wait)
+    }
+
+    //Returns the number of consecutive failures for the username
+    pub fn record_failure(&self, ip: Option<IpAddr>, username: &str) -> u32 {
+        if let Some(ip) = ip {
+            record(&mut self.by_ip.lock(), ip);
+        }
+
+        record(&mut self.by_username.lock(), username_key(username))
+    }
+
+    pub fn clear_username(&self, username: &str) {
+        self.by_username.lock().remove(&username_key(username));
+    }
+
+    pub fn record_success(&self, ip: Option<IpAddr>, username: &str) {
+        if let Some(ip) = ip {
+            self.by_ip.lock().remove(&ip);
+        }
+
+        self.clear_username(username);
+    }
+}
@@ -1,23 +1,34 @@
 use {
-    actix_session::{config::PersistentSession, storage::CookieSessionStore, SessionMiddleware},
-    actix_web::{
-        self,
-        cookie::{Key, SameSite},
-        App, HttpServer,
-    },
+    actix_session::{config::PersistentSession, SessionMiddleware},
+    actix_web::{self, cookie::SameSite, App, HttpServer},
     database::init_postgres_pool,
     once_cell::sync::OnceCell,
-    rustls::{Certificate, PrivateKey, ServerConfig},
-    shared::ms_config::{Config, GLOBAL_CONFIG},
+    shared::ms_config::{get_global_config, Config, GLOBAL_CONFIG},
     sqlx::Postgres,
-    std::{fs::File, io::BufReader, sync::Arc},
-    tracing::Level,
+    std::sync::Arc,
+    tracing::{info, Level},
 };
 
+pub mod announcements;
+pub mod audit;
+pub mod client_ip;
+pub mod cli;
+pub mod crypto;
 pub mod database;
 pub mod endpoints;
+pub mod logging;
+pub mod login_throttle;
+pub mod metrics;
 pub mod middleware;
+pub mod permissions;
+pub mod proxy_protocol;
+pub mod registration_limits;
+pub mod server_history;
 pub mod server_list;
+pub mod session_store;
+pub mod shutdown;
+pub mod tls;
+pub mod totp;
 pub mod wrappers;
 
 pub struct MasterServer {
@@ -42,66 +53,172 @@ pub fn get_master_server() -> &'static MasterServer {
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    
-    //Log to file as well at somepoint
-    let subscriber = tracing_subscriber::fmt()
-        .with_max_level(Level::INFO)
-        .finish();
-
-    tracing::subscriber::set_global_default(subscriber).unwrap();
+    //Logging is configured from the config file, so loading it only gets a plain stdout logger
+    let config = tracing::subscriber::with_default(
+        tracing_subscriber::fmt().with_max_level(Level::INFO).finish(),
+        Config::from_file,
+    );
 
     //I still hate this
-    if GLOBAL_CONFIG.set(Config::from_file()).is_err() {
+    if GLOBAL_CONFIG.set(config).is_err() {
         panic!("Could not create config")
     }
 
+    //Held until main returns so buffered file logs are written out
+    let _log_guard = logging::init_logging();
+
     if MASTER_SERVER.set(MasterServer::new().await).is_err() {
         panic!("Could not create masterserver data");
     }
 
-    let cert_file = &mut BufReader::new(File::open("cert.pem").expect("Could not read cert file"));
-    let key_file = &mut BufReader::new(File::open("key.pem").expect("Could not read key file"));
-
-    let cert = rustls_pemfile::certs(cert_file)?
-        .into_iter()
-        .map(Certificate)
-        .collect();
-
-    let key = PrivateKey(rustls_pemfile::pkcs8_private_keys(key_file)?.remove(0));
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Some(code) = cli::run(&args).await {
+        std::process::exit(code);
+    }
 
-    let config = ServerConfig::builder()
-        .with_safe_default_cipher_suites()
-        .with_safe_default_kx_groups()
-        .with_safe_default_protocol_versions()
-        .unwrap()
-        .with_no_client_auth()
-        .with_single_cert(cert, key);
+    if get_global_config().listeners.is_empty() {
+        panic!("No listeners configured");
+    }
 
     //This is literally only used for a single thing, is probably a way to do this in lib itself
     wrappers::init();
 
-    HttpServer::new(|| {
-        let session_store =
-            SessionMiddleware::builder(CookieSessionStore::default(), Key::generate())
-                .cookie_secure(true)
-                .cookie_content_security(actix_session::config::CookieContentSecurity::Private)
-                .session_lifecycle(
-                    PersistentSession::default()
-                        .session_ttl(actix_web::cookie::time::Duration::minutes(10)),
-                )
-                .cookie_same_site(SameSite::Strict)
-                .build();
+    if get_global_config().server_side_sessions && get_master_server().postgres_pool.is_none() {
+        panic!("Server side sessions require a database connection");
+    }
+
+    //Loaded once so every worker signs cookies with the same key
+    let session_key = session_store::load_session_key()?;
+
+    let mut server = HttpServer::new(move || {
+        let session_store = SessionMiddleware::builder(
+            session_store::MsSessionStore::from_config(),
+            session_key.clone(),
+        )
+        .cookie_secure(true)
+        .cookie_content_security(actix_session::config::CookieContentSecurity::Private)
+        .session_lifecycle(PersistentSession::default().session_ttl(
+            actix_web::cookie::time::Duration::minutes(
+                get_global_config().session_ttl_minutes as i64,
+            ),
+        ))
+        .cookie_same_site(SameSite::Strict)
+        .build();
 
         App::new()
             .wrap(session_store)
+            .wrap(middleware::plain_cookies::PlainCookies)
+            //Outermost so limited requests are turned away before the session is loaded
+            .wrap(middleware::rate_limit::RateLimit)
+            //Outside the rate limiter so even rejected requests get an id
+            .wrap(middleware::request_id::RequestId)
             .service(endpoints::eula::get_eula)
+            .service(endpoints::health::healthz)
+            .service(endpoints::health::readyz)
             .configure(endpoints::servers::servers_routes)
             .configure(endpoints::bans::ban_routes)
             .configure(endpoints::panel::panel_routes)
             .configure(wrappers::red_endpoints)
-    })
-    //Maybe allow https to be toggled for testing
-    .bind_rustls_021("127.0.0.1:443", config.unwrap())?
-    .run()
-    .await
+            .configure(|cfg| {
+                let config = get_global_config();
+                if config.metrics_enabled && config.metrics_listeners.is_empty() {
+                    cfg.service(endpoints::metrics::metrics);
+                }
+            })
+    });
+
+    let mut cert_resolvers = Vec::new();
+
+    for listener in get_global_config().listeners.iter() {
+        let address = (listener.address.as_str(), listener.port);
+
+        //PROXY protocol listeners strip the header themselves and forward to actix over loopback
+        let socket = match listener.proxy_protocol {
+            true => {
+                let internal = std::net::TcpListener::bind(("127.0.0.1", 0))?;
+                let public = tokio::net::TcpListener::bind(address).await?;
+                actix_web::rt::spawn(proxy_protocol::accept_loop(public, internal.local_addr()?));
+                internal
+            }
+            false => std::net::TcpListener::bind(address)?,
+        };
+
+        if !listener.secure_cookies() {
+            middleware::plain_cookies::add_plain_listener(socket.local_addr()?);
+        }
+
+        server = match listener.tls {
+            true => {
+                let resolver = tls::ReloadingCertResolver::new(listener)?;
+                cert_resolvers.push(resolver.clone());
+                server.listen_rustls_0_21(socket, tls::server_config_with_resolver(resolver))?
+            }
+            false => server.listen(socket)?,
+        };
+        info!(
+            "Listening on {}:{} ({}{})",
+            listener.address,
+            listener.port,
+            if listener.tls { "https" } else { "http" },
+            if listener.proxy_protocol { ", PROXY protocol" } else { "" }
+        );
+    }
+
+    let mut metrics_server = None;
+    if get_global_config().metrics_enabled && !get_global_config().metrics_listeners.is_empty() {
+        let mut server =
+            HttpServer::new(|| App::new().service(endpoints::metrics::metrics)).workers(1);
+
+        for listener in get_global_config().metrics_listeners.iter() {
+            let address = (listener.address.as_str(), listener.port);
+            server = match listener.tls {
+                true => {
+                    let resolver = tls::ReloadingCertResolver::new(listener)?;
+                    cert_resolvers.push(resolver.clone());
+                    server.bind_rustls_021(address, tls::server_config_with_resolver(resolver))?
+                }
+                false => server.bind(address)?,
+            };
+            info!(
+                "Serving metrics on {}:{} ({})",
+                listener.address,
+                listener.port,
+                if listener.tls { "https" } else { "http" }
+            );
+        }
+
+        metrics_server = Some(server.disable_signals().run());
+    }
+
+    if !cert_resolvers.is_empty() {
+        actix_web::rt::spawn(tls::cert_reload_task(cert_resolvers));
+    }
+
+    if get_global_config().require_database {
+        actix_web::rt::spawn(async {
+            database::wait_for_database().await;
+            info!("Database connected");
+        });
+    }
+
+    actix_web::rt::spawn(server_history::history_task());
+    actix_web::rt::spawn(middleware::rate_limit::prune_task());
+
+    //Signals are handled by shutdown so every server stops together and state is cleaned up after
+    let grace = get_global_config().shutdown_grace_seconds as u64;
+    let server = server.shutdown_timeout(grace).disable_signals().run();
+
+    let mut handles = vec![server.handle()];
+    handles.extend(metrics_server.as_ref().map(|server| server.handle()));
+    actix_web::rt::spawn(shutdown::stop_on_signal(handles));
+
+    let result = match metrics_server {
+        Some(metrics_server) => futures::future::try_join(server, metrics_server)
+            .await
+            .map(|_| ()),
+        None => server.await,
+    };
+
+    shutdown::finish().await;
+    result
 }
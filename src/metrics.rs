@@ -0,0 +1,190 @@
+use {
+    crate::get_master_server,
+    once_cell::sync::Lazy,
+    prometheus::{
+        exponential_buckets, Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter,
+        IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder,
+    },
+    tracing::error,
+};
+
+static METRICS: Lazy<Metrics> = Lazy::new(Metrics::new);
+
+pub fn get_metrics() -> &'static Metrics {
+    &METRICS
+}
+
+//Counters are totals, per second rates come from rate() on the Prometheus side
+pub struct Metrics {
+    registry: Registry,
+    servers: IntGaugeVec,
+    players: IntGauge,
+    pub registrations: IntCounter,
+    pub heartbeats: IntCounter,
+    validation_failures: IntCounterVec,
+    ban_checks: IntCounterVec,
+    ban_check_seconds: HistogramVec,
+    db_pool_connections: IntGaugeVec,
+    db_pool_max_connections: IntGauge,
+    pub scrub_seconds: Histogram,
+    scrub_removed: IntCounterVec,
+}
+
+impl Metrics {
+    fn new() -> Metrics {
+        let registry = Registry::new_custom(Some(String::from("ms")), None)
+            .expect("Failed to create metrics registry");
+
+        let metrics = Metrics {
+            servers: IntGaugeVec::new(
+                Opts::new("servers", "Servers on the list by visibility"),
+                &["visibility"],
+            )
+            .unwrap(),
+            players: IntGauge::new("players", "Players reported by listed servers").unwrap(),
+            registrations: IntCounter::new(
+                "registrations_total",
+                "New servers accepted by /servers/add",
+            )
+            .unwrap(),
+            heartbeats: IntCounter::new(
+                "heartbeats_total",
+                "Updates accepted from already listed servers",
+            )
+            .unwrap(),
+            validation_failures: IntCounterVec::new(
+                Opts::new(
+                    "validation_failures_total",
+                    "Rejected /servers/add requests by reason",
+                ),
+                &["reason"],
+            )
+            .unwrap(),
+            ban_checks: IntCounterVec::new(
+                Opts::new(
+                    "ban_checks_total",
+                    "Identifiers checked against the ban list",
+                ),
+                &["kind"],
+            )
+            .unwrap(),
+            ban_check_seconds: HistogramVec::new(
+                HistogramOpts::new("ban_check_seconds", "Time taken to check one identifier")
+                    .buckets(exponential_buckets(0.0005, 2.0, 14).unwrap()),
+                &["kind"],
+            )
+            .unwrap(),
+            db_pool_connections: IntGaugeVec::new(
+                Opts::new("db_pool_connections", "Database connections by state"),
+                &["state"],
+            )
+            .unwrap(),
+            db_pool_max_connections: IntGauge::new(
+                "db_pool_max_connections",
+                "Connections the database pool is allowed to open",
+            )
+            .unwrap(),
+            scrub_seconds: Histogram::with_opts(
+                HistogramOpts::new("scrub_seconds", "Time taken to scrub expired servers")
+                    .buckets(exponential_buckets(0.0001, 4.0, 10).unwrap()),
+            )
+            .unwrap(),
+            scrub_removed: IntCounterVec::new(
+                Opts::new(
+                    "scrub_removed_total",
+                    "Expired servers removed by the scrub task",
+                ),
+                &["list"],
+            )
+            .unwrap(),
+            registry,
+        };
+
+        let collectors: [Box<dyn prometheus::core::Collector>; 11] = [
+            Box::new(metrics.servers.clone()),
+            Box::new(metrics.players.clone()),
+            Box::new(metrics.registrations.clone()),
+            Box::new(metrics.heartbeats.clone()),
+            Box::new(metrics.validation_failures.clone()),
+            Box::new(metrics.ban_checks.clone()),
+            Box::new(metrics.ban_check_seconds.clone()),
+            Box::new(metrics.db_pool_connections.clone()),
+            Box::new(metrics.db_pool_max_connections.clone()),
+            Box::new(metrics.scrub_seconds.clone()),
+            Box::new(metrics.scrub_removed.clone()),
+        ];
+
+        for collector in collectors {
+            metrics
+                .registry
+                .register(collector)
+                .expect("Failed to register metric");
+        }
+
+        metrics
+    }
+
+    //Reasons are fixed strings, never user input, so the label can not blow up
+    pub fn validation_failure(&self, reason: &str) {
+        self.validation_failures.with_label_values(&[reason]).inc();
+    }
+
+    pub fn ban_check(&self, kind: &str, seconds: f64) {
+        self.ban_checks.with_label_values(&[kind]).inc();
+        self.ban_check_seconds
+            .with_label_values(&[kind])
+            .observe(seconds);
+    }
+
+    pub fn scrub_removed(&self, list: &str, count: u64) {
+        self.scrub_removed.with_label_values(&[list]).inc_by(count);
+    }
+
+    //Gauges are read from the live state on each scrape instead of being kept in sync
+    fn update_gauges(&self) {
+        let server_list = &get_master_server().server_list;
+        self.servers
+            .with_label_values(&["pending"])
+            .set(server_list.get_pending_servers().len() as i64);
+
+        let public = server_list.get_public_servers().read();
+        let hidden = server_list.get_hidden_servers().read();
+
+        self.servers
+            .with_label_values(&["public"])
+            .set(public.len() as i64);
+        self.servers
+            .with_label_values(&["hidden"])
+            .set(hidden.len() as i64);
+
+        let players: usize = public
+            .iter()
+            .chain(hidden.iter())
+            .map(|server| server.players.len())
+            .sum();
+        self.players.set(players as i64);
+
+        if let Some(pool) = &get_master_server().postgres_pool {
+            let idle = pool.num_idle() as i64;
+            self.db_pool_connections
+                .with_label_values(&["idle"])
+                .set(idle);
+            self.db_pool_connections
+                .with_label_values(&["in_use"])
+                .set(pool.size() as i64 - idle);
+            self.db_pool_max_connections
+                .set(pool.options().get_max_connections() as i64);
+        }
+    }
+
+    pub fn render(&self) -> String {
+        self.update_gauges();
+
+        let mut buffer = Vec::new();
+        if let Err(err) = TextEncoder::new().encode(&self.registry.gather(), &mut buffer) {
+            error!("Failed to encode metrics: {}", err);
+        }
+
+        String::from_utf8(buffer).unwrap_or_default()
+    }
+}
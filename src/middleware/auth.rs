@@ -1,21 +1,55 @@
 use {
+    crate::{
+        client_ip::client_ip_string,
+        database::{api_tokens::use_api_token, users::get_panel_user},
+        permissions::{PanelUser, Permission},
+    },
     actix_session::SessionExt,
     actix_web::{
         body::EitherBody,
         dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
-        http, Error, HttpResponse,
-    },
-    futures::{
-        future::{LocalBoxFuture, TryFutureExt},
-        FutureExt,
+        error, http, Error, HttpMessage, HttpResponse,
     },
+    futures::{future::LocalBoxFuture, FutureExt},
+    std::rc::Rc,
+    tracing::{debug, error, warn},
 };
 
+//Returns the token from an "Authorization: Bearer <token>" header
+fn bearer_token(req: &ServiceRequest) -> Option<String> {
+    let header = req
+        .headers()
+        .get(http::header::AUTHORIZATION)?
+        .to_str()
+        .ok()?;
+    let token = header.strip_prefix("Bearer ")?.trim();
+
+    match token.is_empty() {
+        true => None,
+        false => Some(token.to_string()),
+    }
+}
+
+async fn api_token_user(token: &str, ip: &str) -> Option<PanelUser> {
+    let (name, owner, permissions) = match use_api_token(token, ip).await {
+        Ok(Some(token)) => token,
+        Ok(None) => return None,
+        Err(err) => {
+            error!("Failed to look up API token: {}", err);
+            return None;
+        }
+    };
+
+    //Tokens stop working when their owner is disabled and never exceed the owner's current role
+    let owner = get_panel_user(&owner).await?;
+    Some(owner.with_api_token(name, &permissions))
+}
+
 pub struct ProtectedEndpoint;
 
 impl<S, B> Transform<S, ServiceRequest> for ProtectedEndpoint
 where
-    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
     S::Future: 'static,
     B: 'static,
 {
@@ -26,17 +60,19 @@ where
     type Future = std::future::Ready<Result<Self::Transform, Self::InitError>>;
 
     fn new_transform(&self, service: S) -> Self::Future {
-        std::future::ready(Ok(ProtectedEndpointMiddleware { service }))
+        std::future::ready(Ok(ProtectedEndpointMiddleware {
+            service: Rc::new(service),
+        }))
     }
 }
 
 pub struct ProtectedEndpointMiddleware<S> {
-    service: S,
+    service: Rc<S>,
 }
 
 impl<S, B> Service<ServiceRequest> for ProtectedEndpointMiddleware<S>
 where
-    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
     S::Future: 'static,
     B: 'static,
 {
@@ -46,23 +82,163 @@ where
     forward_ready!(service);
 
     fn call(&self, req: ServiceRequest) -> Self::Future {
-        if req.cookie("id").is_some() {
-            let session = req.get_session().get::<String>("user");
-            if session.is_ok() && session.unwrap().is_some() {
-                return self
-                    .service
-                    .call(req)
-                    .map_ok(ServiceResponse::map_into_left_body)
-                    .boxed_local();
+        let service = self.service.clone();
+
+        async move {
+            //Token requests never fall back to the session or the login redirect
+            if let Some(token) = bearer_token(&req) {
+                let ip = client_ip_string(req.request());
+
+                if let Some(user) = api_token_user(&token, &ip).await {
+                    req.extensions_mut().insert(user);
+                    return service
+                        .call(req)
+                        .await
+                        .map(ServiceResponse::map_into_left_body);
+                }
+
+                warn!(
+                    target: "security",
+                    event = "api_token_rejected",
+                    ip = %ip,
+                    path = req.path(),
+                    "Rejected API token"
+                );
+                let (request, _pl) = req.into_parts();
+                let response = HttpResponse::Unauthorized().finish().map_into_right_body();
+                return Ok(ServiceResponse::new(request, response));
             }
+
+            if req.cookie("id").is_some() {
+                if let Ok(Some(username)) = req.get_session().get::<String>("user") {
+                    //Looked up on every request so role changes apply straight away
+                    if let Some(user) = get_panel_user(&username).await {
+                        req.extensions_mut().insert(user);
+                        return service
+                            .call(req)
+                            .await
+                            .map(ServiceResponse::map_into_left_body);
+                    }
+                }
+            }
+
+            let (request, _pl) = req.into_parts();
+            debug!("Failed auth for {}", request.path());
+            let response = HttpResponse::Found()
+                .insert_header((http::header::LOCATION, "/login"))
+                .finish()
+                .map_into_right_body();
+
+            Ok(ServiceResponse::new(request, response))
         }
-        let (request, _pl) = req.into_parts();
-        println!("Failed auth");
-        let response = HttpResponse::Found()
-            .insert_header((http::header::LOCATION, "/login"))
-            .finish()
-            .map_into_right_body();
-
-        Box::pin(async { Ok(ServiceResponse::new(request, response)) })
+        .boxed_local()
+    }
+}
+
+//Per route check, has to sit inside a scope wrapped by ProtectedEndpoint
+pub struct RequirePermission(pub Permission);
+
+impl<S, B> Transform<S, ServiceRequest> for RequirePermission
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type InitError = ();
+    type Transform = RequirePermissionMiddleware<S>;
+    type Future = std::future::Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        std::future::ready(Ok(RequirePermissionMiddleware {
+            service,
+            permission: self.0,
+        }))
+    }
+}
+
+pub struct RequirePermissionMiddleware<S> {
+    service: S,
+    permission: Permission,
+}
+
+impl<S, B> Service<ServiceRequest> for RequirePermissionMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let allowed = req
+            .extensions()
+            .get::<PanelUser>()
+            .is_some_and(|user| user.has(self.permission));
+
+        if allowed {
+            return self.service.call(req).boxed_local();
+        }
+
+        debug!(
+            "Denied {} to user without permission {:?}",
+            req.path(),
+            self.permission
+        );
+
+        Box::pin(async { Err(error::ErrorForbidden("Insufficient permissions")) })
+    }
+}
+
+//Keeps API tokens away from routes that act on the owner's own account
+pub struct SessionOnly;
+
+impl<S, B> Transform<S, ServiceRequest> for SessionOnly
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type InitError = ();
+    type Transform = SessionOnlyMiddleware<S>;
+    type Future = std::future::Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        std::future::ready(Ok(SessionOnlyMiddleware { service }))
+    }
+}
+
+pub struct SessionOnlyMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for SessionOnlyMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let is_session = req
+            .extensions()
+            .get::<PanelUser>()
+            .is_some_and(|user| user.api_token.is_none());
+
+        if is_session {
+            return self.service.call(req).boxed_local();
+        }
+
+        Box::pin(async { Err(error::ErrorForbidden("Not available to API tokens")) })
     }
 }
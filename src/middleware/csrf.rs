@@ -0,0 +1,179 @@
+use {
+    crate::{
+        client_ip::client_ip_string,
+        crypto::{constant_time_eq, random_token},
+        permissions::PanelUser,
+    },
+    actix_session::SessionExt,
+    actix_web::{
+        dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform},
+        error, http, Error, FromRequest, HttpMessage, HttpRequest,
+    },
+    futures::{future::LocalBoxFuture, FutureExt},
+    maud::{html, Markup, Render},
+    tracing::warn,
+};
+
+const CSRF_SESSION_KEY: &str = "csrf_token";
+pub const CSRF_HEADER: &str = "X-CSRF-Token";
+
+//The session's CSRF token, rendered into panel pages as a meta tag that ms_post.js sends back as a header
+pub struct CsrfToken(pub String);
+
+impl FromRequest for CsrfToken {
+    type Error = Error;
+    type Future = std::future::Ready<Result<CsrfToken, Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let session = req.get_session();
+
+        let token = match session.get::<String>(CSRF_SESSION_KEY) {
+            Ok(Some(token)) => Ok(token),
+            _ => random_token(32)
+                .ok_or_else(|| error::ErrorInternalServerError("Failed to generate CSRF token"))
+                .and_then(|token| {
+                    session.insert(CSRF_SESSION_KEY, &token)?;
+                    Ok(token)
+                }),
+        };
+
+        std::future::ready(token.map(CsrfToken))
+    }
+}
+
+impl Render for CsrfToken {
+    fn render(&self) -> Markup {
+        html! {
+            meta name = "csrf-token" content = (self.0);
+        }
+    }
+}
+
+//The Origin header has to name the host the request was sent to, anything else is cross site
+fn origin_matches(req: &ServiceRequest) -> bool {
+    let origin = match req
+        .headers()
+        .get(http::header::ORIGIN)
+        .and_then(|origin| origin.to_str().ok())
+    {
+        Some(origin) => origin,
+        None => return false,
+    };
+
+    let info = req.connection_info();
+    origin == format!("{}://{}", info.scheme(), info.host())
+}
+
+fn token_matches(req: &ServiceRequest) -> bool {
+    let expected = match req.get_session().get::<String>(CSRF_SESSION_KEY) {
+        Ok(Some(token)) => token,
+        _ => return false,
+    };
+
+    req.headers()
+        .get(CSRF_HEADER)
+        .is_some_and(|token| constant_time_eq(token.as_bytes(), expected.as_bytes()))
+}
+
+//Checks every state changing request, safe methods pass straight through
+pub struct CsrfProtection {
+    //Pages outside the panel (login, invites) have no session to hold a token yet, so only the origin is checked
+    require_token: bool,
+}
+
+impl CsrfProtection {
+    pub fn new() -> CsrfProtection {
+        CsrfProtection {
+            require_token: true,
+        }
+    }
+
+    pub fn origin_only() -> CsrfProtection {
+        CsrfProtection {
+            require_token: false,
+        }
+    }
+}
+
+impl Default for CsrfProtection {
+    fn default() -> CsrfProtection {
+        CsrfProtection::new()
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for CsrfProtection
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type InitError = ();
+    type Transform = CsrfProtectionMiddleware<S>;
+    type Future = std::future::Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        std::future::ready(Ok(CsrfProtectionMiddleware {
+            service,
+            require_token: self.require_token,
+        }))
+    }
+}
+
+pub struct CsrfProtectionMiddleware<S> {
+    service: S,
+    require_token: bool,
+}
+
+impl<S, B> Service<ServiceRequest> for CsrfProtectionMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let safe_method = matches!(
+            *req.method(),
+            http::Method::GET | http::Method::HEAD | http::Method::OPTIONS
+        );
+
+        //Bearer tokens are never sent automatically by a browser so they can not be forged cross site
+        let api_token = req
+            .extensions()
+            .get::<PanelUser>()
+            .is_some_and(|user| user.api_token.is_some());
+
+        if safe_method || api_token {
+            return self.service.call(req).boxed_local();
+        }
+
+        let reason = if !origin_matches(&req) {
+            Some("origin")
+        } else if self.require_token && !token_matches(&req) {
+            Some("token")
+        } else {
+            None
+        };
+
+        match reason {
+            None => self.service.call(req).boxed_local(),
+            Some(reason) => {
+                warn!(
+                    target: "security",
+                    event = "csrf_rejected",
+                    reason,
+                    path = req.path(),
+                    ip = %client_ip_string(req.request()),
+                    "Rejected cross site request"
+                );
+                Box::pin(async { Err(error::ErrorForbidden("CSRF check failed")) })
+            }
+        }
+    }
+}
@@ -1 +1,5 @@
 pub mod auth;
+pub mod csrf;
+pub mod plain_cookies;
+pub mod rate_limit;
+pub mod request_id;
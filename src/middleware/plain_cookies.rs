@@ -0,0 +1,99 @@
+use {
+    actix_web::{
+        cookie::Cookie,
+        dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+        http::header::{HeaderValue, SET_COOKIE},
+        Error,
+    },
+    futures::{future::LocalBoxFuture, FutureExt},
+    once_cell::sync::Lazy,
+    parking_lot::RwLock,
+    std::{collections::HashSet, net::SocketAddr},
+};
+
+//Local addresses of the listeners whose cookies must not be marked Secure, filled in while binding
+static PLAIN_LISTENERS: Lazy<RwLock<HashSet<SocketAddr>>> =
+    Lazy::new(|| RwLock::new(HashSet::new()));
+
+pub fn add_plain_listener(address: SocketAddr) {
+    PLAIN_LISTENERS.write().insert(address);
+}
+
+//The session middleware is shared by every listener, this drops the Secure flag again on plain http listeners
+//so the browser sends the cookie back there
+pub struct PlainCookies;
+
+impl<S, B> Transform<S, ServiceRequest> for PlainCookies
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type InitError = ();
+    type Transform = PlainCookiesMiddleware<S>;
+    type Future = std::future::Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        std::future::ready(Ok(PlainCookiesMiddleware { service }))
+    }
+}
+
+pub struct PlainCookiesMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for PlainCookiesMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let plain = PLAIN_LISTENERS
+            .read()
+            .contains(&req.app_config().local_addr());
+        let future = self.service.call(req);
+
+        async move {
+            let mut res = future.await?;
+            if !plain {
+                return Ok(res);
+            }
+
+            //Anything that does not parse as a cookie is passed on untouched
+            let cookies: Vec<HeaderValue> = res
+                .headers()
+                .get_all(SET_COOKIE)
+                .map(|value| {
+                    value
+                        .to_str()
+                        .ok()
+                        .and_then(|value| Cookie::parse(value.to_string()).ok())
+                        .and_then(|mut cookie| {
+                            cookie.set_secure(false);
+                            HeaderValue::from_str(&cookie.to_string()).ok()
+                        })
+                        .unwrap_or_else(|| value.clone())
+                })
+                .collect();
+
+            if !cookies.is_empty() {
+                let headers = res.headers_mut();
+                headers.remove(SET_COOKIE);
+                for cookie in cookies {
+                    headers.append(SET_COOKIE, cookie);
+                }
+            }
+
+            Ok(res)
+        }
+        .boxed_local()
+    }
+}
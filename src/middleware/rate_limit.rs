@@ -0,0 +1,349 @@
+use {
+    crate::client_ip::{client_ip, parse_networks},
+    actix_web::{
+        body::EitherBody,
+        dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+        http, Error, HttpResponse,
+    },
+    futures::{future::LocalBoxFuture, FutureExt},
+    ipnet::IpNet,
+    once_cell::sync::Lazy,
+    parking_lot::Mutex,
+    shared::{
+        ms_config::{get_global_config, RateLimitPolicy},
+        responses::ms_error_format,
+    },
+    std::{
+        collections::HashMap,
+        net::{IpAddr, Ipv6Addr},
+        time::{Duration, Instant},
+    },
+    tracing::debug,
+};
+
+//Past this many buckets the least recently used tenth is dropped to make room
+const MAX_BUCKETS: usize = 50_000;
+//How often buckets that have filled back up are dropped, a full bucket is the same as no bucket
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+static BUCKETS: Lazy<Mutex<HashMap<(usize, IpAddr), Bucket>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+static ALLOW_LIST: Lazy<Vec<IpNet>> = Lazy::new(|| {
+    parse_networks(
+        &get_global_config().rate_limit_allow_list,
+        "rate limit allow list",
+    )
+});
+
+//Shared by everything that rate limits, the wait is in the Retry-After header and the message
+pub fn too_many_requests(message: &str, retry_after: Duration) -> HttpResponse {
+    //Round up so clients never retry a moment too early
+    let seconds = retry_after.as_secs() + 1;
+
+    HttpResponse::TooManyRequests()
+        .insert_header((http::header::RETRY_AFTER, seconds.to_string()))
+        .body(ms_error_format(format!(
+            "{}, retry in {} seconds",
+            message, seconds
+        )))
+}
+
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl Bucket {
+    fn refilled(&self, policy: &RateLimitPolicy, now: Instant) -> f64 {
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        (self.tokens + elapsed * policy.per_second).min(policy.burst as f64)
+    }
+
+    //Takes a token, or returns how long until one is available
+    fn take(&mut self, policy: &RateLimitPolicy, now: Instant) -> Result<(), Duration> {
+        self.tokens = self.refilled(policy, now);
+        self.last_refill = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return Ok(());
+        }
+
+        match policy.per_second > 0.0 {
+            true => Err(Duration::from_secs_f64(
+                (1.0 - self.tokens) / policy.per_second,
+            )),
+            //A policy that never refills only allows its burst, tell clients to come back much later
+            false => Err(Duration::from_secs(3600)),
+        }
+    }
+}
+
+//A prefix only matches whole path segments, so /servers covers /servers/add but not /serversX
+fn prefix_matches(path: &str, prefix: &str) -> bool {
+    match path.strip_prefix(prefix) {
+        Some(rest) => prefix.ends_with('/') || rest.is_empty() || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+//Index of the policy with the longest prefix matching the path
+fn policy_for(policies: &[RateLimitPolicy], path: &str) -> Option<usize> {
+    policies
+        .iter()
+        .enumerate()
+        .filter(|(_, policy)| prefix_matches(path, &policy.path_prefix))
+        .max_by_key(|(_, policy)| policy.path_prefix.len())
+        .map(|(index, _)| index)
+}
+
+//An IPv6 client usually has a whole /64 to pick addresses from, so that is what shares a bucket
+fn bucket_ip(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(_) => ip,
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => IpAddr::V4(ip),
+            None => IpAddr::V6(Ipv6Addr::from(u128::from(ip) & !(u64::MAX as u128))),
+        },
+    }
+}
+
+//Drops the least recently used tenth, sorting only happens once every MAX_BUCKETS / 10 new clients
+fn evict_oldest(buckets: &mut HashMap<(usize, IpAddr), Bucket>) {
+    let mut ages: Vec<Instant> = buckets.values().map(|bucket| bucket.last_refill).collect();
+    ages.sort_unstable();
+
+    if let Some(cutoff) = ages.get((buckets.len() / 10).saturating_sub(1)) {
+        let cutoff = *cutoff;
+        buckets.retain(|_, bucket| bucket.last_refill > cutoff);
+    }
+}
+
+//Takes a token for the client, or returns how long until one is available
+fn take_token(policy_index: usize, ip: IpAddr) -> Result<(), Duration> {
+    let policy = &get_global_config().rate_limits[policy_index];
+    let now = Instant::now();
+    let key = (policy_index, bucket_ip(ip));
+
+    let mut buckets = BUCKETS.lock();
+
+    if buckets.len() >= MAX_BUCKETS && !buckets.contains_key(&key) {
+        evict_oldest(&mut buckets);
+    }
+
+    buckets
+        .entry(key)
+        .or_insert(Bucket {
+            tokens: policy.burst as f64,
+            last_refill: now,
+        })
+        .take(policy, now)
+}
+
+//Drops the buckets that have filled back up so idle clients do not hold on to memory
+pub async fn prune_task() {
+    let mut ticker = tokio::time::interval(PRUNE_INTERVAL);
+
+    loop {
+        ticker.tick().await;
+
+        let policies = &get_global_config().rate_limits;
+        let now = Instant::now();
+        BUCKETS.lock().retain(|(index, _), bucket| {
+            let policy = &policies[*index];
+            bucket.refilled(policy, now) < policy.burst as f64
+        });
+    }
+}
+
+//Applies the configured rate_limits to every request, wrapped around the whole app
+pub struct RateLimit;
+
+impl<S, B> Transform<S, ServiceRequest> for RateLimit
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type InitError = ();
+    type Transform = RateLimitMiddleware<S>;
+    type Future = std::future::Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        std::future::ready(Ok(RateLimitMiddleware { service }))
+    }
+}
+
+pub struct RateLimitMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for RateLimitMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let ip = client_ip(req.request());
+
+        let limited = match (ip, policy_for(&get_global_config().rate_limits, req.path())) {
+            (Some(ip), Some(policy)) if !ALLOW_LIST.iter().any(|net| net.contains(&ip)) => {
+                take_token(policy, ip).err()
+            }
+            _ => None,
+        };
+
+        match limited {
+            None => self
+                .service
+                .call(req)
+                .map(|res| res.map(ServiceResponse::map_into_left_body))
+                .boxed_local(),
+            Some(retry_after) => {
+                debug!(
+                    "Rate limited {} from {}",
+                    req.path(),
+                    ip.map(|ip| ip.to_string()).unwrap_or_default()
+                );
+                let response = too_many_requests("Too many requests", retry_after);
+                Box::pin(async { Ok(req.into_response(response).map_into_right_body()) })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(path_prefix: &str, burst: u32, per_second: f64) -> RateLimitPolicy {
+        RateLimitPolicy {
+            path_prefix: path_prefix.to_string(),
+            burst,
+            per_second,
+        }
+    }
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn bucket_refills_over_time() {
+        let policy = policy("/", 2, 1.0);
+        let start = Instant::now();
+        let mut bucket = Bucket {
+            tokens: 2.0,
+            last_refill: start,
+        };
+
+        assert!(bucket.take(&policy, start).is_ok());
+        assert!(bucket.take(&policy, start).is_ok());
+        assert_eq!(bucket.take(&policy, start), Err(Duration::from_secs(1)));
+
+        //Half a token back is not enough yet
+        let half = start + Duration::from_millis(500);
+        assert_eq!(bucket.take(&policy, half), Err(Duration::from_millis(500)));
+
+        let later = start + Duration::from_secs(1);
+        assert!(bucket.take(&policy, later).is_ok());
+
+        //Never refills past the burst
+        let much_later = later + Duration::from_secs(60);
+        assert_eq!(bucket.refilled(&policy, much_later), 2.0);
+    }
+
+    #[test]
+    fn policy_without_refill_waits_an_hour() {
+        let policy = policy("/", 1, 0.0);
+        let now = Instant::now();
+        let mut bucket = Bucket {
+            tokens: 1.0,
+            last_refill: now,
+        };
+
+        assert!(bucket.take(&policy, now).is_ok());
+        assert_eq!(bucket.take(&policy, now), Err(Duration::from_secs(3600)));
+    }
+
+    #[test]
+    fn retry_after_is_rounded_up() {
+        let response = too_many_requests("Too many requests", Duration::from_millis(500));
+        assert_eq!(response.status(), http::StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(
+            response.headers().get(http::header::RETRY_AFTER).unwrap(),
+            "1"
+        );
+
+        let response = too_many_requests("Too many requests", Duration::from_secs(30));
+        assert_eq!(
+            response.headers().get(http::header::RETRY_AFTER).unwrap(),
+            "31"
+        );
+    }
+
+    #[test]
+    fn longest_whole_segment_prefix_wins() {
+        let policies = [
+            policy("/", 60, 10.0),
+            policy("/servers", 30, 2.0),
+            policy("/servers/add", 5, 1.0),
+            policy("/panel/", 60, 5.0),
+        ];
+
+        assert_eq!(policy_for(&policies, "/eula"), Some(0));
+        assert_eq!(policy_for(&policies, "/servers"), Some(1));
+        assert_eq!(policy_for(&policies, "/servers/remove"), Some(1));
+        assert_eq!(policy_for(&policies, "/servers/add"), Some(2));
+        assert_eq!(policy_for(&policies, "/serversX"), Some(0));
+        assert_eq!(policy_for(&policies, "/servers/addX"), Some(1));
+        assert_eq!(policy_for(&policies, "/panel/list"), Some(3));
+        assert_eq!(policy_for(&policies[1..], "/eula"), None);
+    }
+
+    #[test]
+    fn ipv6_clients_share_their_64() {
+        assert_eq!(bucket_ip(ip("2001:db8:1:2:aaaa::1")), ip("2001:db8:1:2::"));
+        assert_eq!(
+            bucket_ip(ip("2001:db8:1:2:ffff:ffff:ffff:ffff")),
+            bucket_ip(ip("2001:db8:1:2::5"))
+        );
+        assert_ne!(
+            bucket_ip(ip("2001:db8:1:2::1")),
+            bucket_ip(ip("2001:db8:1:3::1"))
+        );
+        assert_eq!(bucket_ip(ip("::ffff:198.51.100.7")), ip("198.51.100.7"));
+        assert_eq!(bucket_ip(ip("198.51.100.7")), ip("198.51.100.7"));
+    }
+
+    #[test]
+    fn eviction_drops_the_least_recently_used() {
+        let start = Instant::now();
+        let mut buckets = HashMap::new();
+        for i in 0..20u8 {
+            buckets.insert(
+                (0, IpAddr::from([10, 0, 0, i])),
+                Bucket {
+                    tokens: 0.0,
+                    last_refill: start + Duration::from_secs(i as u64),
+                },
+            );
+        }
+
+        evict_oldest(&mut buckets);
+
+        assert_eq!(buckets.len(), 18);
+        for i in 0..2u8 {
+            assert!(!buckets.contains_key(&(0, IpAddr::from([10, 0, 0, i]))));
+        }
+    }
+}
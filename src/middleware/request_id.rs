@@ -0,0 +1,93 @@
+use {
+    crate::client_ip::{client_ip_string, peer_is_trusted_proxy},
+    actix_web::{
+        dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+        http::header::{HeaderName, HeaderValue},
+        Error,
+    },
+    futures::{future::LocalBoxFuture, FutureExt},
+    tracing::{debug, info_span, Instrument},
+    uuid::Uuid,
+};
+
+const REQUEST_ID_HEADER: &str = "x-request-id";
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+//A trusted proxy's id is kept so a request can be followed across both logs
+fn request_id(req: &ServiceRequest) -> String {
+    let from_proxy = peer_is_trusted_proxy(req.request());
+
+    req.headers()
+        .get(REQUEST_ID_HEADER)
+        .filter(|_| from_proxy)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LENGTH)
+        .map(str::to_string)
+        .unwrap_or_else(|| Uuid::new_v4().to_string())
+}
+
+//Runs every request inside a span carrying its id, which is also returned in X-Request-Id
+pub struct RequestId;
+
+impl<S, B> Transform<S, ServiceRequest> for RequestId
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type InitError = ();
+    type Transform = RequestIdMiddleware<S>;
+    type Future = std::future::Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        std::future::ready(Ok(RequestIdMiddleware { service }))
+    }
+}
+
+pub struct RequestIdMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestIdMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let id = request_id(&req);
+        let span = info_span!(
+            "request",
+            request_id = %id,
+            method = %req.method(),
+            path = %req.path(),
+            ip = %client_ip_string(req.request()),
+        );
+
+        let future = {
+            let _enter = span.enter();
+            self.service.call(req)
+        };
+
+        async move {
+            let mut res = future.await?;
+            debug!(status = res.status().as_u16(), "Request finished");
+
+            if let Ok(value) = HeaderValue::from_str(&id) {
+                res.headers_mut()
+                    .insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+            }
+
+            Ok(res)
+        }
+        .instrument(span)
+        .boxed_local()
+    }
+}
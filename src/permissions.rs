@@ -0,0 +1,186 @@
+use {
+    serde::{Deserialize, Serialize},
+    std::{fmt, str::FromStr},
+};
+
+//Stored in the role column of the users table
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Viewer,
+    Moderator,
+    Admin,
+    //Runs game servers, only sees the keys and servers it owns
+    Host,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Permission {
+    ViewServers,
+    ViewBans,
+    KickPlayers,
+    BanPlayers,
+    ManageServers,
+    ManageSessions,
+    ManageUsers,
+    ManageApiTokens,
+    ViewAuditLog,
+    ManageConfig,
+    HostServers,
+}
+
+impl Role {
+    pub const ALL: [Role; 4] = [Role::Viewer, Role::Moderator, Role::Admin, Role::Host];
+
+    pub fn permissions(&self) -> &'static [Permission] {
+        match self {
+            Role::Viewer => &[Permission::ViewServers, Permission::ViewBans],
+            Role::Moderator => &[
+                Permission::ViewServers,
+                Permission::ViewBans,
+                Permission::KickPlayers,
+                Permission::BanPlayers,
+                Permission::ManageServers,
+            ],
+            Role::Admin => &[
+                Permission::ViewServers,
+                Permission::ViewBans,
+                Permission::KickPlayers,
+                Permission::BanPlayers,
+                Permission::ManageServers,
+                Permission::ManageSessions,
+                Permission::ManageUsers,
+                Permission::ManageApiTokens,
+                Permission::ViewAuditLog,
+                Permission::ManageConfig,
+                Permission::HostServers,
+            ],
+            Role::Host => &[Permission::HostServers],
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Moderator => "moderator",
+            Role::Admin => "admin",
+            Role::Host => "host",
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Role, String> {
+        match s {
+            "viewer" => Ok(Role::Viewer),
+            "moderator" => Ok(Role::Moderator),
+            "admin" => Ok(Role::Admin),
+            "host" => Ok(Role::Host),
+            _ => Err(format!("Unknown role '{}'", s)),
+        }
+    }
+}
+
+impl Permission {
+    pub const ALL: [Permission; 11] = [
+        Permission::ViewServers,
+        Permission::ViewBans,
+        Permission::KickPlayers,
+        Permission::BanPlayers,
+        Permission::ManageServers,
+        Permission::ManageSessions,
+        Permission::ManageUsers,
+        Permission::ManageApiTokens,
+        Permission::ViewAuditLog,
+        Permission::ManageConfig,
+        Permission::HostServers,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Permission::ViewServers => "view_servers",
+            Permission::ViewBans => "view_bans",
+            Permission::KickPlayers => "kick_players",
+            Permission::BanPlayers => "ban_players",
+            Permission::ManageServers => "manage_servers",
+            Permission::ManageSessions => "manage_sessions",
+            Permission::ManageUsers => "manage_users",
+            Permission::ManageApiTokens => "manage_api_tokens",
+            Permission::ViewAuditLog => "view_audit_log",
+            Permission::ManageConfig => "manage_config",
+            Permission::HostServers => "host_servers",
+        }
+    }
+}
+
+impl FromStr for Permission {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Permission, String> {
+        Permission::ALL
+            .into_iter()
+            .find(|permission| permission.as_str() == s)
+            .ok_or_else(|| format!("Unknown permission '{}'", s))
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+//The authenticated user for a panel request, inserted into the request extensions by ProtectedEndpoint
+#[derive(Clone)]
+pub struct PanelUser {
+    pub username: String,
+    pub role: Role,
+    pub permissions: Vec<Permission>,
+    //Set when policy requires two factor auth for the role but the user has not enrolled yet
+    pub totp_enrolment_required: bool,
+    //Name of the API token the request was authenticated with, None for browser sessions
+    pub api_token: Option<String>,
+}
+
+impl PanelUser {
+    pub fn new(username: String, role: Role) -> PanelUser {
+        PanelUser {
+            username,
+            role,
+            permissions: role.permissions().to_vec(),
+            totp_enrolment_required: false,
+            api_token: None,
+        }
+    }
+
+    //Holds no permissions so only the account page (where enrolment happens) is usable
+    pub fn pending_totp_enrolment(username: String, role: Role) -> PanelUser {
+        PanelUser {
+            username,
+            role,
+            permissions: Vec::new(),
+            totp_enrolment_required: true,
+            api_token: None,
+        }
+    }
+
+    //A token acts for its owner but only with the permissions both the token and the owner hold
+    pub fn with_api_token(self, token_name: String, token_permissions: &[Permission]) -> PanelUser {
+        PanelUser {
+            permissions: self
+                .permissions
+                .into_iter()
+                .filter(|permission| token_permissions.contains(permission))
+                .collect(),
+            api_token: Some(token_name),
+            ..self
+        }
+    }
+
+    pub fn has(&self, permission: Permission) -> bool {
+        self.permissions.contains(&permission)
+    }
+}
@@ -0,0 +1,301 @@
+use {
+    crate::client_ip::{is_trusted_proxy, register_proxied_peer},
+    std::{
+        io::{self, ErrorKind},
+        net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+        time::Duration,
+    },
+    tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    },
+    tracing::{debug, warn},
+};
+
+const V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+const V1_PREFIX: &[u8; 6] = b"PROXY ";
+//Longest possible v1 line, including the \r\n
+const V1_MAX_LENGTH: usize = 107;
+const V2_MAX_LENGTH: usize = 16 + 216;
+const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+fn parse_v1(line: &[u8]) -> io::Result<Option<SocketAddr>> {
+    let line = std::str::from_utf8(line).map_err(|_| invalid("PROXY header is not text"))?;
+    let parts: Vec<&str> = line.split(' ').collect();
+
+    match parts.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", "TCP4" | "TCP6", source, _, source_port, _] => {
+            let ip: IpAddr = source
+                .parse()
+                .map_err(|_| invalid("Bad PROXY source address"))?;
+            let port: u16 = source_port
+                .parse()
+                .map_err(|_| invalid("Bad PROXY source port"))?;
+            Ok(Some(SocketAddr::new(ip, port)))
+        }
+        _ => Err(invalid("Malformed PROXY header")),
+    }
+}
+
+fn parse_v2(header: &[u8]) -> io::Result<Option<SocketAddr>> {
+    let version_command = header[12];
+    if version_command >> 4 != 2 {
+        return Err(invalid("Unsupported PROXY protocol version"));
+    }
+
+    //LOCAL connections are health checks from the proxy itself
+    if version_command & 0x0F == 0 {
+        return Ok(None);
+    }
+
+    let addresses = &header[16..];
+    match header[13] >> 4 {
+        1 if addresses.len() >= 12 => {
+            let ip = Ipv4Addr::new(addresses[0], addresses[1], addresses[2], addresses[3]);
+            let port = u16::from_be_bytes([addresses[8], addresses[9]]);
+            Ok(Some(SocketAddr::new(IpAddr::V4(ip), port)))
+        }
+        2 if addresses.len() >= 36 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&addresses[..16]);
+            let port = u16::from_be_bytes([addresses[32], addresses[33]]);
+            Ok(Some(SocketAddr::new(
+                IpAddr::V6(Ipv6Addr::from(octets)),
+                port,
+            )))
+        }
+        //Unix sockets and unspecified families carry no usable address
+        _ => Ok(None),
+    }
+}
+
+//Returns the header length and the client address once a whole header is buffered, None if more is needed
+fn parse_header(buf: &[u8]) -> io::Result<Option<(usize, Option<SocketAddr>)>> {
+    let prefix_len = buf.len().min(V2_SIGNATURE.len());
+
+    if buf[..prefix_len] == V2_SIGNATURE[..prefix_len] {
+        if buf.len() < 16 {
+            return Ok(None);
+        }
+
+        let length = 16 + u16::from_be_bytes([buf[14], buf[15]]) as usize;
+        if length > V2_MAX_LENGTH {
+            return Err(invalid("PROXY header too long"));
+        }
+
+        return match buf.len() >= length {
+            true => Ok(Some((length, parse_v2(&buf[..length])?))),
+            false => Ok(None),
+        };
+    }
+
+    let prefix_len = buf.len().min(V1_PREFIX.len());
+    if buf[..prefix_len] != V1_PREFIX[..prefix_len] {
+        return Err(invalid("Connection did not start with a PROXY header"));
+    }
+
+    match buf.windows(2).position(|window| window == b"\r\n") {
+        Some(end) => Ok(Some((end + 2, parse_v1(&buf[..end])?))),
+        None if buf.len() >= V1_MAX_LENGTH => Err(invalid("PROXY header too long")),
+        None => Ok(None),
+    }
+}
+
+//Returns the client address and anything the client sent after the header
+async fn read_header(stream: &mut TcpStream) -> io::Result<(Option<SocketAddr>, Vec<u8>)> {
+    let mut buf = Vec::with_capacity(256);
+    let mut chunk = [0u8; 256];
+
+    loop {
+        if !buf.is_empty() {
+            if let Some((length, client)) = parse_header(&buf)? {
+                return Ok((client, buf.split_off(length)));
+            }
+        }
+
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+        buf.extend_from_slice(&chunk[..read]);
+    }
+}
+
+async fn forward(mut stream: TcpStream, peer: SocketAddr, upstream: SocketAddr) -> io::Result<()> {
+    //Anyone else could claim to be any client
+    if !is_trusted_proxy(peer.ip()) {
+        warn!(
+            target: "security",
+            event = "proxy_protocol_untrusted",
+            ip = %peer.ip(),
+            "Dropped PROXY protocol connection from an untrusted address"
+        );
+        return Ok(());
+    }
+
+    let (client, leftover) = tokio::time::timeout(HEADER_TIMEOUT, read_header(&mut stream))
+        .await
+        .map_err(|_| io::Error::from(ErrorKind::TimedOut))??;
+
+    let mut upstream = TcpStream::connect(upstream).await?;
+    let local = upstream.local_addr()?;
+
+    let _proxied = register_proxied_peer(local, client.unwrap_or(peer));
+    upstream.write_all(&leftover).await?;
+    tokio::io::copy_bidirectional(&mut stream, &mut upstream).await?;
+
+    Ok(())
+}
+
+//Accepts connections for a PROXY protocol listener and hands them to the internal actix listener,
+//recording who each one is really from so client_ip can resolve it
+pub async fn accept_loop(listener: TcpListener, upstream: SocketAddr) {
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(err) => {
+                warn!("Failed to accept PROXY protocol connection: {}", err);
+                continue;
+            }
+        };
+
+        actix_web::rt::spawn(async move {
+            if let Err(err) = forward(stream, peer, upstream).await {
+                debug!("PROXY protocol connection from {} closed: {}", peer, err);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v2_header(command: u8, family: u8, addresses: &[u8]) -> Vec<u8> {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.push(0x20 | command);
+        header.push(family << 4 | 1);
+        header.extend_from_slice(&(addresses.len() as u16).to_be_bytes());
+        header.extend_from_slice(addresses);
+        header
+    }
+
+    fn v2_ipv4() -> Vec<u8> {
+        //198.51.100.7:4711 to 10.0.0.1:443
+        let mut addresses = vec![198, 51, 100, 7, 10, 0, 0, 1];
+        addresses.extend_from_slice(&4711u16.to_be_bytes());
+        addresses.extend_from_slice(&443u16.to_be_bytes());
+        v2_header(1, 1, &addresses)
+    }
+
+    fn addr(addr: &str) -> Option<SocketAddr> {
+        Some(addr.parse().unwrap())
+    }
+
+    #[test]
+    fn v1_tcp4_and_tcp6() {
+        let buf = b"PROXY TCP4 198.51.100.7 10.0.0.1 4711 443\r\nGET /";
+        let (length, client) = parse_header(buf).unwrap().unwrap();
+        assert_eq!(&buf[length..], b"GET /");
+        assert_eq!(client, addr("198.51.100.7:4711"));
+
+        let buf = b"PROXY TCP6 2001:db8::1 2001:db8::2 4711 443\r\n";
+        let (_, client) = parse_header(buf).unwrap().unwrap();
+        assert_eq!(client, addr("[2001:db8::1]:4711"));
+    }
+
+    #[test]
+    fn v1_unknown_has_no_client() {
+        let (_, client) = parse_header(b"PROXY UNKNOWN\r\n").unwrap().unwrap();
+        assert_eq!(client, None);
+    }
+
+    #[test]
+    fn v1_truncated_waits_for_more() {
+        assert!(parse_header(b"PRO").unwrap().is_none());
+        assert!(parse_header(b"PROXY TCP4 198.51.100.7 10.0.0.1 4711 44")
+            .unwrap()
+            .is_none());
+        assert!(parse_header(b"PROXY TCP4 198.51.100.7 10.0.0.1 4711 443\r")
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn v1_too_long_is_rejected() {
+        let mut buf = b"PROXY TCP4 ".to_vec();
+        buf.resize(V1_MAX_LENGTH, b'1');
+        assert!(parse_header(&buf).is_err());
+    }
+
+    #[test]
+    fn v1_malformed_is_rejected() {
+        assert!(parse_header(b"PROXY TCP4 not-an-ip 10.0.0.1 4711 443\r\n").is_err());
+        assert!(parse_header(b"PROXY TCP4 198.51.100.7 10.0.0.1 99999 443\r\n").is_err());
+        assert!(parse_header(b"PROXY TCP4 198.51.100.7\r\n").is_err());
+        assert!(parse_header(b"GET / HTTP/1.1\r\n").is_err());
+    }
+
+    #[test]
+    fn v2_ipv4_and_ipv6() {
+        let mut buf = v2_ipv4();
+        let header_length = buf.len();
+        buf.extend_from_slice(b"GET /");
+        let (length, client) = parse_header(&buf).unwrap().unwrap();
+        assert_eq!(length, header_length);
+        assert_eq!(client, addr("198.51.100.7:4711"));
+
+        let mut addresses = "2001:db8::1".parse::<Ipv6Addr>().unwrap().octets().to_vec();
+        addresses.extend_from_slice(&"2001:db8::2".parse::<Ipv6Addr>().unwrap().octets());
+        addresses.extend_from_slice(&4711u16.to_be_bytes());
+        addresses.extend_from_slice(&443u16.to_be_bytes());
+        let (_, client) = parse_header(&v2_header(1, 2, &addresses)).unwrap().unwrap();
+        assert_eq!(client, addr("[2001:db8::1]:4711"));
+    }
+
+    #[test]
+    fn v2_local_has_no_client() {
+        let (_, client) = parse_header(&v2_header(0, 0, &[])).unwrap().unwrap();
+        assert_eq!(client, None);
+    }
+
+    #[test]
+    fn v2_truncated_waits_for_more() {
+        let buf = v2_ipv4();
+        for length in 1..buf.len() {
+            assert!(
+                parse_header(&buf[..length]).unwrap().is_none(),
+                "{}",
+                length
+            );
+        }
+    }
+
+    #[test]
+    fn v2_too_long_is_rejected() {
+        let mut buf = V2_SIGNATURE.to_vec();
+        buf.extend_from_slice(&[0x21, 0x11]);
+        buf.extend_from_slice(&((V2_MAX_LENGTH - 16 + 1) as u16).to_be_bytes());
+        assert!(parse_header(&buf).is_err());
+    }
+
+    #[test]
+    fn v2_short_address_block_has_no_client() {
+        let (_, client) = parse_header(&v2_header(1, 1, &[198, 51, 100, 7]))
+            .unwrap()
+            .unwrap();
+        assert_eq!(client, None);
+    }
+
+    #[test]
+    fn v2_bad_version_is_rejected() {
+        let mut buf = v2_ipv4();
+        buf[12] = 0x11;
+        assert!(parse_header(&buf).is_err());
+    }
+}
@@ -0,0 +1,121 @@
+use {
+    once_cell::sync::Lazy,
+    parking_lot::Mutex,
+    shared::ms_config::get_global_config,
+    std::{
+        collections::{HashMap, VecDeque},
+        net::IpAddr,
+        time::{Duration, Instant},
+    },
+    tracing::warn,
+};
+
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+static REGISTRATION_LIMITS: Lazy<RegistrationLimits> = Lazy::new(RegistrationLimits::default);
+
+pub fn get_registration_limits() -> &'static RegistrationLimits {
+    &REGISTRATION_LIMITS
+}
+
+//Sliding window rate limits for /servers/add, registrations and heartbeats are counted separately
+#[derive(Default)]
+pub struct RegistrationLimits {
+    registrations: Mutex<HashMap<IpAddr, VecDeque<Instant>>>,
+    heartbeats: Mutex<HashMap<IpAddr, VecDeque<Instant>>>,
+    //Every registration across all IPs, for the flood detector
+    recent_registrations: Mutex<VecDeque<Instant>>,
+    flooding: Mutex<bool>,
+}
+
+fn prune(times: &mut VecDeque<Instant>, window: Duration, now: Instant) {
+    while times
+        .front()
+        .is_some_and(|time| now.duration_since(*time) >= window)
+    {
+        times.pop_front();
+    }
+}
+
+//Records the request if it is under the limit, otherwise returns how long until it would be
+fn take(
+    map: &Mutex<HashMap<IpAddr, VecDeque<Instant>>>,
+    ip: IpAddr,
+    limit: u32,
+) -> Result<(), Duration> {
+    if limit == 0 {
+        return Ok(());
+    }
+
+    let now = Instant::now();
+    let mut map = map.lock();
+
+    //Drop IPs that have gone quiet so the map does not grow forever
+    if map.len() > 10_000 {
+        map.retain(|_, times| {
+            prune(times, RATE_WINDOW, now);
+            !times.is_empty()
+        });
+    }
+
+    let times = map.entry(ip).or_default();
+    prune(times, RATE_WINDOW, now);
+
+    if times.len() >= limit as usize {
+        let oldest = times.front().copied().unwrap_or(now);
+        return Err(RATE_WINDOW.saturating_sub(now.duration_since(oldest)));
+    }
+
+    times.push_back(now);
+    Ok(())
+}
+
+impl RegistrationLimits {
+    pub fn take_registration(&self, ip: IpAddr) -> Result<(), Duration> {
+        take(
+            &self.registrations,
+            ip,
+            get_global_config().registration_rate_per_minute,
+        )
+    }
+
+    pub fn take_heartbeat(&self, ip: IpAddr) -> Result<(), Duration> {
+        take(
+            &self.heartbeats,
+            ip,
+            get_global_config().heartbeat_rate_per_minute,
+        )
+    }
+
+    //Returns true while registrations across all IPs are coming in faster than the flood threshold,
+    //anything registered then is quarantined
+    pub fn record_registration(&self) -> bool {
+        let cfg = get_global_config();
+        if cfg.flood_registration_threshold == 0 {
+            return false;
+        }
+
+        let now = Instant::now();
+        let window = Duration::from_secs(cfg.flood_window_seconds as u64);
+
+        let mut recent = self.recent_registrations.lock();
+        prune(&mut recent, window, now);
+        recent.push_back(now);
+
+        let flooding = recent.len() > cfg.flood_registration_threshold as usize;
+        let mut was_flooding = self.flooding.lock();
+        if flooding != *was_flooding {
+            warn!(
+                target: "security",
+                event = "registration_flood",
+                flooding,
+                registrations = recent.len(),
+                "Registration flood {}",
+                if flooding { "detected, quarantining new servers" } else { "over" }
+            );
+            *was_flooding = flooding;
+        }
+
+        flooding
+    }
+}
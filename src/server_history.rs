@@ -0,0 +1,217 @@
+use {
+    crate::{
+        database::{
+            server_history::{
+                delete_history_before, end_open_sessions, insert_network_sample,
+                upsert_server_sessions, ServerSessionRow,
+            },
+            wait_for_database,
+        },
+        get_master_server,
+        server_list::current_time,
+    },
+    chrono::Utc,
+    once_cell::sync::Lazy,
+    parking_lot::Mutex,
+    shared::{ms_config::get_global_config, server::ServerInfo},
+    std::{
+        collections::{HashMap, HashSet},
+        time::Duration,
+    },
+    tracing::{error, info},
+};
+
+//Distinct maps and playlists kept per session, a server cycling through more only records the first ones
+const MAX_TRACKED_NAMES: usize = 32;
+
+static SERVER_HISTORY: Lazy<ServerHistory> = Lazy::new(ServerHistory::default);
+
+pub fn get_server_history() -> &'static ServerHistory {
+    &SERVER_HISTORY
+}
+
+struct TrackedSession {
+    row: ServerSessionRow,
+    //Changed since it was last written
+    dirty: bool,
+}
+
+//Heartbeats are counted in memory and written out every history_flush_seconds, rather than once per heartbeat
+#[derive(Default)]
+pub struct ServerHistory {
+    sessions: Mutex<HashMap<String, TrackedSession>>,
+}
+
+fn push_distinct(names: &mut Vec<String>, name: &str) {
+    if names.len() < MAX_TRACKED_NAMES && !names.iter().any(|known| known == name) {
+        names.push(name.to_string());
+    }
+}
+
+impl ServerHistory {
+    //Called for every accepted heartbeat of a listed server
+    pub fn record_heartbeat(&self, server: &ServerInfo) {
+        if !get_global_config().history_enabled {
+            return;
+        }
+
+        let now = Utc::now();
+        let players = server.server.player_count as i64;
+        let mut sessions = self.sessions.lock();
+
+        let session = sessions
+            .entry(server.internal.uid.clone())
+            .or_insert_with(|| TrackedSession {
+                row: ServerSessionRow {
+                    uid: server.internal.uid.clone(),
+                    server_key: server.server.key.clone(),
+                    name: String::new(),
+                    address: String::new(),
+                    hidden: server.server.hidden,
+                    owner: None,
+                    first_seen: now,
+                    last_heartbeat: now,
+                    ended: false,
+                    heartbeats: 0,
+                    player_total: 0,
+                    peak_players: 0,
+                    maps: Vec::new(),
+                    playlists: Vec::new(),
+                },
+                dirty: true,
+            });
+
+        let row = &mut session.row;
+        row.name = server.server.name.clone();
+        row.address = format!("{}:{}", server.server.ip, server.server.port);
+        row.hidden = server.server.hidden;
+        row.owner = server.internal.owner.clone();
+        row.last_heartbeat = now;
+        row.ended = false;
+        row.heartbeats += 1;
+        row.player_total += players;
+        row.peak_players = row.peak_players.max(players as i32);
+        push_distinct(&mut row.maps, &server.server.map);
+        push_distinct(&mut row.playlists, &server.server.playlist);
+        session.dirty = true;
+    }
+
+    //Writes changed sessions and a network sample, sessions no longer on the list are closed.
+    //end_all closes every session, for shutdown
+    pub async fn flush(&self, end_all: bool) {
+        if !get_global_config().history_enabled {
+            return;
+        }
+
+        let server_list = &get_master_server().server_list;
+        let now = current_time();
+        //Every uid still in a list, and the ones that have not expired
+        let mut present: HashSet<String> = HashSet::new();
+        let mut live: HashSet<String> = HashSet::new();
+        let mut servers = 0;
+        let mut players = 0;
+
+        for list in [
+            server_list.get_public_servers(),
+            server_list.get_hidden_servers(),
+        ] {
+            //Expired servers stay in the list until the next scrub, but are no longer shown to anyone
+            for server in list.read().iter() {
+                present.insert(server.internal.uid.clone());
+
+                if server.internal.server_expiry_time >= now {
+                    live.insert(server.internal.uid.clone());
+                    servers += 1;
+                    players += server.server.player_count as i32;
+                }
+            }
+        }
+
+        let rows: Vec<ServerSessionRow> = {
+            let mut sessions = self.sessions.lock();
+            for session in sessions.values_mut() {
+                let ended = end_all || !live.contains(&session.row.uid);
+                if ended != session.row.ended {
+                    session.row.ended = ended;
+                    session.dirty = true;
+                }
+            }
+
+            sessions
+                .values()
+                .filter(|session| session.dirty)
+                .map(|session| session.row.clone())
+                .collect()
+        };
+
+        //An ended session is kept while the server is still in a list, a late heartbeat carries it on
+        let keep = |uid: &String, session: &TrackedSession| {
+            !session.row.ended || session.dirty || present.contains(uid)
+        };
+
+        //Without a database there is nowhere to keep history, so only the memory is tidied up
+        if get_master_server().postgres_pool.is_none() {
+            self.sessions
+                .lock()
+                .retain(|uid, session| !session.row.ended || present.contains(uid));
+            return;
+        }
+
+        if let Err(err) = upsert_server_sessions(&rows).await {
+            //Left dirty so the next flush tries again
+            error!("Failed to write server history: {}", err);
+            return;
+        }
+
+        {
+            let mut sessions = self.sessions.lock();
+            for row in rows.iter() {
+                if let Some(session) = sessions.get_mut(&row.uid) {
+                    //A heartbeat that came in during the write leaves it dirty
+                    if session.row.heartbeats == row.heartbeats && session.row.ended == row.ended {
+                        session.dirty = false;
+                    }
+                }
+            }
+            sessions.retain(|uid, session| keep(uid, session));
+        }
+
+        if let Err(err) = insert_network_sample(servers, players).await {
+            error!("Failed to write network sample: {}", err);
+        }
+    }
+}
+
+//Closes sessions a previous run left open, then flushes and prunes old history on the configured cadence
+pub async fn history_task() {
+    let cfg = get_global_config();
+    if !cfg.history_enabled {
+        return;
+    }
+
+    if get_master_server().postgres_pool.is_some() {
+        //Sessions are only closed once the database is there to close them in
+        wait_for_database().await;
+
+        match end_open_sessions().await {
+            Ok(0) => {}
+            Ok(count) => info!("Closed {} server sessions left open by the last run", count),
+            Err(err) => error!("Failed to close open server sessions: {}", err),
+        }
+    }
+
+    let mut ticker =
+        tokio::time::interval(Duration::from_secs(cfg.history_flush_seconds.max(1) as u64));
+
+    loop {
+        ticker.tick().await;
+        get_server_history().flush(false).await;
+
+        if cfg.history_retention_days != 0 && get_master_server().postgres_pool.is_some() {
+            let before = Utc::now() - chrono::Duration::days(cfg.history_retention_days as i64);
+            if let Err(err) = delete_history_before(before).await {
+                error!("Failed to prune server history: {}", err);
+            }
+        }
+    }
+}
@@ -1,77 +1,256 @@
 use {
+    crate::{
+        announcements::get_announcements,
+        crypto::{constant_time_eq, get_system_random, random_token, sha256_hex},
+        metrics::get_metrics,
+        server_history::get_server_history,
+    },
     parking_lot,
     shared::{
         ms_config::get_global_config,
-        server::{HostInfo, InternalServerData, ServerInfo, ServerWithUID},
+        responses::BanIdentifiers,
+        server::{
+            CommandKind, HostInfo, InternalServerData, Player, ServerCommand, ServerInfo,
+            ServerWithUID,
+        },
     },
     std::{
+        collections::HashMap,
         net::SocketAddr,
         sync::{
-            atomic::{AtomicBool, Ordering},
+            atomic::{AtomicBool, AtomicU64, Ordering},
             Arc,
         },
-        time::{Duration, SystemTime, UNIX_EPOCH},
+        time::{Duration, Instant, SystemTime, UNIX_EPOCH},
     },
-    tracing::{debug, error},
-    ring::{rand::{generate, SystemRandom, Random}, digest},
+    tracing::{debug, error, info},
+    ring::{rand::{generate, Random}, digest},
     uuid::Uuid,
-    once_cell::sync::OnceCell,
 };
 
-static SYSTEM_RANDOM: OnceCell<SystemRandom> = OnceCell::new();
+//How long finished commands stay visible in the server management page
+const COMMAND_HISTORY_SECONDS: u64 = 600;
+
+//Longest a kicked player can be kept off a server
+pub const MAX_KICK_COOLDOWN_SECONDS: u64 = 365 * 24 * 60 * 60;
+
+static NEXT_COMMAND_ID: AtomicU64 = AtomicU64::new(1);
+
+//Keeps a delisted server from posting again until the block runs out
+#[derive(Clone)]
+pub struct RelistBlock {
+    pub reason: String,
+    pub until: u64,
+    pub issued_by: String,
+}
+
+pub enum ServerAuthError {
+    MissingSecret,
+    BadSecret,
+    AddressMismatch,
+}
+
+impl ServerAuthError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ServerAuthError::MissingSecret => "missing_secret",
+            ServerAuthError::BadSecret => "bad_secret",
+            ServerAuthError::AddressMismatch => "address_mismatch",
+        }
+    }
+
+    pub fn message(&self) -> &'static str {
+        match self {
+            ServerAuthError::MissingSecret => {
+                "This server's secret has to be sent with every update, register again without a uid to get one"
+            }
+            ServerAuthError::BadSecret => "Invalid secret for this server",
+            ServerAuthError::AddressMismatch => "Updates for this server have to come from the address that registered it",
+        }
+    }
+}
 
-fn get_system_random() -> &'static SystemRandom {
-    SYSTEM_RANDOM.get_or_init(SystemRandom::new)
+pub enum AddServerError {
+    Internal,
+    Unauthorized(ServerAuthError),
+    //The uid belongs to a server listed with the other visibility
+    VisibilityMismatch,
+    //A heartbeat with a different key than the server registered with, which would dodge the per key limit
+    KeyMismatch,
+    //A heartbeat for a server that expired while its post was being checked
+    NotListed,
+}
+
+//Whether a post to /servers/add updates a server we have or registers a new one
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum PostKind {
+    Heartbeat,
+    Registration,
 }
 
 pub struct ServerList {
     pub scrub_needed: AtomicBool,
-    pub last_scrub_time: std::sync::Mutex<SystemTime>,
+    //Monotonic so clock changes can not stall or rush scrubbing
+    pub last_scrub: parking_lot::Mutex<Instant>,
+    //Unix time of the scrub task's last wake up, so readiness checks can tell it is still running
+    pub scrub_alive_at: AtomicU64,
+    scrub_task: parking_lot::Mutex<Option<tokio::task::JoinHandle<()>>>,
     pub public_servers: parking_lot::RwLock<Vec<ServerInfo>>,
     pub hidden_servers: parking_lot::RwLock<Vec<ServerInfo>>,
+    //Registrations held back by the flood detector until approved or rejected in the panel
+    pub pending_servers: parking_lot::RwLock<Vec<ServerInfo>>,
+    //Keyed by the server's ip:port, as a relisted server is given a new uid
+    pub relist_blocks: parking_lot::RwLock<HashMap<String, RelistBlock>>,
 }
 
 impl ServerList {
     pub fn new() -> Arc<ServerList> {
         let list = Arc::new(ServerList {
             scrub_needed: false.into(),
-            last_scrub_time: SystemTime::now().into(),
+            last_scrub: parking_lot::Mutex::new(Instant::now()),
+            scrub_alive_at: current_time().into(),
+            scrub_task: parking_lot::Mutex::new(None),
             public_servers: parking_lot::RwLock::new(Vec::new()),
             hidden_servers: parking_lot::RwLock::new(Vec::new()),
+            pending_servers: parking_lot::RwLock::new(Vec::new()),
+            relist_blocks: parking_lot::RwLock::new(HashMap::new()),
         });
-        let srv_list_handle = list.clone();
-        std::thread::spawn(move || {
-            srv_list_handle.scrub_server_list_thread();
-        });
+        let task = actix_web::rt::spawn(scrub_task(list.clone()));
+        *list.scrub_task.lock() = Some(task);
         list
     }
 }
 
+//Random extra delay so instances started together do not scrub in lockstep
+fn scrub_jitter() -> Duration {
+    let max = get_global_config().scrub_jitter_ms;
+    if max == 0 {
+        return Duration::ZERO;
+    }
+
+    match generate::<[u8; 4]>(get_system_random()) {
+        Ok(bytes) => {
+            Duration::from_millis(u32::from_le_bytes(bytes.expose()) as u64 % (max as u64 + 1))
+        }
+        Err(_) => Duration::ZERO,
+    }
+}
+
+//Wakes every scrub_interval_seconds and scrubs once an expired server has been seen,
+//or scrub_max_interval_seconds after the last scrub
+async fn scrub_task(list: Arc<ServerList>) {
+    let cfg = get_global_config();
+    let interval = Duration::from_secs(cfg.scrub_interval_seconds.max(1) as u64);
+    let max_interval = Duration::from_secs(cfg.scrub_max_interval_seconds as u64);
+
+    loop {
+        tokio::time::sleep(interval + scrub_jitter()).await;
+        list.scrub_alive_at.store(current_time(), Ordering::Relaxed);
+
+        if list.scrub_needed.load(Ordering::Relaxed)
+            || list.last_scrub.lock().elapsed() >= max_interval
+        {
+            list.scrub();
+        }
+    }
+}
+
 impl ServerList {
+    //Looks the uid up the same way add_server does, so registration limits are applied to exactly what it would push.
+    //A uid on the list with the other visibility is rejected rather than registered again
+    pub fn post_kind(&self, uid: &str, hidden: bool) -> Result<PostKind, AddServerError> {
+        if let Some(pending_hidden) = self
+            .pending_servers
+            .read()
+            .iter()
+            .find(|server| server.internal.uid == uid)
+            .map(|server| server.server.hidden)
+        {
+            return match pending_hidden == hidden {
+                true => Ok(PostKind::Heartbeat),
+                false => Err(AddServerError::VisibilityMismatch),
+            };
+        }
+
+        let (list, other) = match hidden {
+            false => (&self.public_servers, &self.hidden_servers),
+            true => (&self.hidden_servers, &self.public_servers),
+        };
+
+        if list.read().iter().any(|server| server.internal.uid == uid) {
+            return Ok(PostKind::Heartbeat);
+        }
+
+        match other.read().iter().any(|server| server.internal.uid == uid) {
+            true => Err(AddServerError::VisibilityMismatch),
+            false => Ok(PostKind::Registration),
+        }
+    }
+
+    //kind is what post_kind returned, a heartbeat is never turned into a new server here as the registration limits were not applied to it.
+    //quarantine only applies to registrations
     pub async fn add_server(
         &self,
         mut server_request: ServerWithUID,
         adr: SocketAddr,
-    ) -> Option<HostInfo> {
+        owner: Option<String>,
+        kind: PostKind,
+        quarantine: bool,
+    ) -> Result<HostInfo, AddServerError> {
         let current_time = match SystemTime::now().duration_since(UNIX_EPOCH) {
             Ok(duration) => duration.as_secs(),
             Err(_) => {
                 error!("Failed to get current system timestamp");
-                return None;
+                return Err(AddServerError::Internal);
             }
         };
 
         let timeout_time = current_time + get_global_config().server_timeout as u64;
 
         //Grab the correct list for the server visibility
-        let server_list = match server_request.server.hidden {
-            false => &self.public_servers,
-            true => &self.hidden_servers,
+        let (server_list, other_list) = match server_request.server.hidden {
+            false => (&self.public_servers, &self.hidden_servers),
+            true => (&self.hidden_servers, &self.public_servers),
         };
 
+        //Checked before the write lock below, taking the other list's lock while holding it could deadlock
+        if other_list
+            .read()
+            .iter()
+            .any(|server| server.internal.uid == server_request.uid)
+        {
+            return Err(AddServerError::VisibilityMismatch);
+        }
+
         //Set the servers ip to the one that made the initial post request
         server_request.server.ip = adr.ip().to_string();
 
+        //Quarantined servers keep posting as normal but stay off the list until approved
+        {
+            let mut pending = self.pending_servers.write();
+            if let Some(server) = pending
+                .iter_mut()
+                .find(|server| server.internal.uid == server_request.uid)
+            {
+                if server.server.hidden != server_request.server.hidden {
+                    return Err(AddServerError::VisibilityMismatch);
+                }
+
+                let secret =
+                    refresh_server(server, server_request, owner, timeout_time, current_time)?;
+
+                return Ok(HostInfo {
+                    ip: server.server.ip.clone(),
+                    port: server.server.port,
+                    uid: server.internal.uid.clone(),
+                    token: server.internal.token,
+                    commands: Vec::new(),
+                    secret,
+                    pending: true,
+                });
+            }
+        }
+
         let mut server_list = server_list.write();
         //The most servers we can ever remove is the number we have so do this to save unneeded allocation
         let mut servers_to_remove: Vec<usize> = Vec::with_capacity(server_list.len());
@@ -85,14 +264,19 @@ impl ServerList {
             if itr.internal.uid == *server_request.uid {
                 debug!("Found server with UID {}", itr.internal.uid);
 
-                itr.server = server_request.server;
-                itr.internal.server_expiry_time = timeout_time;
+                let secret =
+                    refresh_server(itr, server_request, owner, timeout_time, current_time)?;
+                get_announcements().deliver(itr, current_time);
+                get_server_history().record_heartbeat(itr);
 
                 let host_data = HostInfo {
                     ip: itr.server.ip.clone(),
                     port: itr.server.port,
-                    uid: server_request.uid.clone(),
+                    uid: itr.internal.uid.clone(),
                     token: itr.internal.token,
+                    commands: pending_commands(itr, current_time),
+                    secret,
+                    pending: false,
                 };
 
                 //Go through all the servers we added in reverse,
@@ -101,7 +285,7 @@ impl ServerList {
                     server_list.swap_remove(*server);
                 }
 
-                return Some(host_data);
+                return Ok(host_data);
             }
 
             //If the current server isn't the one we are looking for
@@ -117,6 +301,10 @@ impl ServerList {
             server_list.swap_remove(*i);
         }
 
+        if kind == PostKind::Heartbeat {
+            return Err(AddServerError::NotListed);
+        }
+
         debug!(
             "Did not find server with UID '{}' Pushing new server",
             server_request.uid
@@ -124,9 +312,11 @@ impl ServerList {
 
         let rand_bytes: Random<[u8;4]> = match generate(get_system_random()) {
             Ok(bytes) => {bytes},
-            Err(_) => {return None}
+            Err(_) => {return Err(AddServerError::Internal)}
         };
 
+        let secret = random_token(32).ok_or(AddServerError::Internal)?;
+
         let mut ctx = digest::Context::new(&digest::SHA1_FOR_LEGACY_USE_ONLY);
         ctx.update(server_request.server.ip.as_bytes());
         ctx.update(&server_request.server.port.to_le_bytes());
@@ -140,6 +330,11 @@ impl ServerList {
             region: String::new(),
             token: None,
             time_stamp: server_request.time_stamp,
+            secret_hash: sha256_hex(&secret),
+            pending_secret: None,
+            secret_issued_at: current_time,
+            secret_in_use: false,
+            owner,
         };
 
         debug!(
@@ -154,19 +349,37 @@ impl ServerList {
         let server_ip = server_request.server.ip.clone();
         let server_port = server_request.server.port;
 
-        server_list.push(ServerInfo {
+        let mut server = ServerInfo {
             server: server_request.server,
             players: Vec::new(),
             internal: internal_store.clone(),
-            kick_list: Vec::new(),
-        });
+            commands: Vec::new(),
+        };
+
+        let commands = match quarantine {
+            true => {
+                self.pending_servers.write().push(server);
+                Vec::new()
+            }
+            false => {
+                //New servers get any running announcements straight away
+                get_announcements().deliver(&mut server, current_time);
+                get_server_history().record_heartbeat(&server);
+                let commands = pending_commands(&mut server, current_time);
+                server_list.push(server);
+                commands
+            }
+        };
 
         //Return the info the game expects
-        Some(HostInfo {
+        Ok(HostInfo {
             ip: server_ip,
             port: server_port,
             uid: internal_store.uid,
             token: internal_store.token,
+            commands,
+            secret: Some(secret),
+            pending: quarantine,
         })
     }
 
@@ -178,45 +391,66 @@ impl ServerList {
         &self.hidden_servers
     }
 
-    pub fn scrub_server_list_thread(&self) {
-        loop {
-            std::thread::sleep(Duration::from_secs(5));
-
-            let duration_since_last_scrub =
-                match SystemTime::now().duration_since(*self.last_scrub_time.lock().unwrap()) {
-                    Ok(duration) => duration,
-                    Err(_) => {
-                        continue;
-                    }
-                };
-
-            if !self.scrub_needed.load(Ordering::Relaxed)
-                && duration_since_last_scrub < Duration::from_secs(1800)
-            {
-                continue;
+    //Drops every server whose heartbeat is overdue, logging each one
+    pub fn scrub(&self) {
+        let time = match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(time) => time.as_secs(),
+            Err(err) => {
+                //Better to keep stale servers a little longer than drop live ones on a bad clock
+                error!("System clock is before the unix epoch, skipping scrub: {}", err);
+                return;
             }
+        };
 
-            let tme = SystemTime::now().duration_since(UNIX_EPOCH);
-            let time = match tme {
-                Ok(tme) => tme.as_secs(),
-                Err(_) => {
-                    eprint!("Failed to get current system timestamp");
-                    return;
+        let started = Instant::now();
+
+        for (list, list_name) in [
+            (&self.public_servers, "public"),
+            (&self.hidden_servers, "hidden"),
+            (&self.pending_servers, "pending"),
+        ] {
+            let mut expired = Vec::new();
+
+            list.write().retain(|server| {
+                let keep = server.internal.server_expiry_time > time;
+                if !keep {
+                    expired.push((
+                        server.internal.uid.clone(),
+                        server.server.name.clone(),
+                        format!("{}:{}", server.server.ip, server.server.port),
+                    ));
                 }
-            };
-
-            {
-                let mut server_list = self.public_servers.write();
-                server_list.retain(|server| server.internal.server_expiry_time > time);
+                keep
+            });
+
+            for (uid, name, address) in expired.iter() {
+                info!(
+                    event = "server_expired",
+                    list = list_name,
+                    uid = %uid,
+                    name = %name,
+                    address = %address,
+                    "Removed expired server"
+                );
             }
 
-            {
-                let mut server_list = self.hidden_servers.write();
-                server_list.retain(|server| server.internal.server_expiry_time > time);
-            }
+            get_metrics().scrub_removed(list_name, expired.len() as u64);
+        }
+
+        get_metrics()
+            .scrub_seconds
+            .observe(started.elapsed().as_secs_f64());
+        self.scrub_needed.store(false, Ordering::Relaxed);
+        *self.last_scrub.lock() = Instant::now();
+    }
+
+    //Stops the scrub task, a scrub never awaits so it can not be cut off half way
+    pub async fn stop_scrub(&self) {
+        let task = self.scrub_task.lock().take();
 
-            self.scrub_needed.store(false, Ordering::Relaxed);
-            *self.last_scrub_time.lock().unwrap() = SystemTime::now();
+        if let Some(task) = task {
+            task.abort();
+            let _ = task.await;
         }
     }
 
@@ -236,15 +470,183 @@ impl ServerList {
         None
     }
 
-    pub fn update_kick_list(&self, uid: String, player_uids: Vec<u64>) -> bool {
+    //Returns the id of the queued command, or None if the server is not listed
+    pub fn queue_command(&self, uid: &str, kind: CommandKind, issued_by: &str) -> Option<u64> {
+        self.queue_commands(uid, vec![kind], issued_by)
+            .and_then(|ids| ids.first().copied())
+    }
+
+    //Queues every command under one lock, so they all go to the server or none do
+    fn queue_commands(
+        &self,
+        uid: &str,
+        kinds: Vec<CommandKind>,
+        issued_by: &str,
+    ) -> Option<Vec<u64>> {
         for list in [&self.hidden_servers, &self.public_servers] {
             let mut servers = list.write();
             if let Some(server) = servers.iter_mut().find(|server| server.internal.uid == uid) {
-                server.kick_list = player_uids;
-                return true;
+                let issued_at = current_time();
+                return Some(
+                    kinds
+                        .into_iter()
+                        .map(|kind| {
+                            let id = next_command_id();
+                            server.commands.push(ServerCommand {
+                                id,
+                                kind,
+                                issued_by: issued_by.to_string(),
+                                issued_at,
+                                delivered_at: None,
+                                acknowledged_at: None,
+                            });
+                            id
+                        })
+                        .collect(),
+                );
             }
         }
-        false
+        None
+    }
+
+    //Returns how many kicks were queued
+    pub fn queue_kicks(
+        &self,
+        uid: String,
+        player_uids: Vec<u64>,
+        reason: String,
+        cooldown_seconds: Option<u64>,
+        issued_by: String,
+    ) -> Result<usize, String> {
+        if player_uids.is_empty() {
+            return Err(String::from("No players selected"));
+        }
+
+        let cooldown_until = match cooldown_seconds {
+            Some(seconds) if seconds > MAX_KICK_COOLDOWN_SECONDS => {
+                return Err(format!(
+                    "Cooldown can be at most {} seconds",
+                    MAX_KICK_COOLDOWN_SECONDS
+                ))
+            }
+            Some(seconds) => Some(
+                current_time()
+                    .checked_add(seconds)
+                    .ok_or_else(|| String::from("Cooldown is too long"))?,
+            ),
+            None => None,
+        };
+        let kicks = player_uids
+            .into_iter()
+            .map(|player_uid| CommandKind::Kick {
+                player_uid,
+                reason: reason.clone(),
+                cooldown_until,
+            })
+            .collect();
+
+        match self.queue_commands(&uid, kicks, &issued_by) {
+            Some(ids) => Ok(ids.len()),
+            None => Err(String::from("Could not find server")),
+        }
+    }
+
+    //Takes the server off the list straight away instead of waiting for it to time out
+    pub fn remove_server(&self, uid: &str) -> Option<ServerInfo> {
+        for list in [&self.hidden_servers, &self.public_servers] {
+            let mut servers = list.write();
+            if let Some(index) = servers.iter().position(|server| server.internal.uid == uid) {
+                return Some(servers.swap_remove(index));
+            }
+        }
+        None
+    }
+
+    //Listed and quarantined servers that match, used for the per IP and per key limits
+    pub fn count_servers(&self, matches: impl Fn(&ServerInfo) -> bool) -> usize {
+        [&self.hidden_servers, &self.public_servers, &self.pending_servers]
+            .iter()
+            .map(|list| list.read().iter().filter(|server| matches(server)).count())
+            .sum()
+    }
+
+    pub fn get_pending_servers(&self) -> Vec<ServerInfo> {
+        self.pending_servers.read().clone()
+    }
+
+    //Moves a quarantined server onto the list it registered for
+    pub fn approve_pending(&self, uid: &str) -> Option<ServerInfo> {
+        let server = self.remove_pending(uid)?;
+        let list = match server.server.hidden {
+            false => &self.public_servers,
+            true => &self.hidden_servers,
+        };
+        list.write().push(server.clone());
+        Some(server)
+    }
+
+    pub fn remove_pending(&self, uid: &str) -> Option<ServerInfo> {
+        let mut pending = self.pending_servers.write();
+        let index = pending.iter().position(|server| server.internal.uid == uid)?;
+        Some(pending.swap_remove(index))
+    }
+
+    //Used when a key is revoked
+    pub fn remove_servers_with_key(&self, key: &str) -> Vec<ServerInfo> {
+        let mut removed = Vec::new();
+        for list in [&self.hidden_servers, &self.public_servers, &self.pending_servers] {
+            let mut servers = list.write();
+            let (with_key, rest) = servers
+                .drain(..)
+                .partition(|server: &ServerInfo| server.server.key == key);
+            *servers = rest;
+            removed.extend(with_key);
+        }
+        removed
+    }
+
+    pub fn servers_owned_by(&self, owner: &str) -> Vec<ServerInfo> {
+        let mut owned = Vec::new();
+        for list in [&self.hidden_servers, &self.public_servers] {
+            let servers = list.read();
+            owned.extend(
+                servers
+                    .iter()
+                    .filter(|server| server.internal.owner.as_deref() == Some(owner))
+                    .cloned(),
+            );
+        }
+        owned
+    }
+
+    pub fn block_relist(&self, ip: &str, port: u16, block: RelistBlock) {
+        let mut blocks = self.relist_blocks.write();
+        let time = current_time();
+        blocks.retain(|_, block| block.until > time);
+        blocks.insert(format!("{}:{}", ip, port), block);
+    }
+
+    //Returns the block if the server at this address is not allowed back on the list yet
+    pub fn relist_block(&self, ip: &str, port: u16) -> Option<RelistBlock> {
+        self.relist_blocks
+            .read()
+            .get(&format!("{}:{}", ip, port))
+            .filter(|block| block.until > current_time())
+            .cloned()
+    }
+
+    pub fn active_relist_blocks(&self) -> Vec<(String, RelistBlock)> {
+        let time = current_time();
+        self.relist_blocks
+            .read()
+            .iter()
+            .filter(|(_, block)| block.until > time)
+            .map(|(address, block)| (address.clone(), block.clone()))
+            .collect()
+    }
+
+    pub fn lift_relist_block(&self, address: &str) -> Option<RelistBlock> {
+        self.relist_blocks.write().remove(address)
     }
 
     pub fn find_server_from_uid(&self, uid: String) -> Option<ServerInfo> {
@@ -257,6 +659,17 @@ impl ServerList {
         None
     }
 
+    //Runs f against a listed server under the read lock, for lookups that do not need their own copy
+    pub fn with_listed_server<T>(&self, uid: &str, f: impl FnOnce(&ServerInfo) -> T) -> Option<T> {
+        for list in [&self.hidden_servers, &self.public_servers] {
+            let servers = list.read();
+            if let Some(server) = servers.iter().find(|server| server.internal.uid == uid) {
+                return Some(f(server));
+            }
+        }
+        None
+    }
+
     pub fn does_server_exist(&self, uid: &String) -> bool {
         for list in [&self.hidden_servers, &self.public_servers] {
             let servers = list.read();
@@ -269,3 +682,190 @@ impl ServerList {
     }
 
 }
+
+pub fn next_command_id() -> u64 {
+    NEXT_COMMAND_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+pub fn current_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or(0)
+}
+
+//Checks the secret sent with an update, a rotated secret replaces the old one the first time it is used
+//Returns whether the update carried the secret, only those are handed a new one
+pub fn authenticate_server(
+    server: &mut ServerInfo,
+    secret: Option<&str>,
+    ip: &str,
+    time: u64,
+) -> Result<bool, ServerAuthError> {
+    let internal = &mut server.internal;
+    let secret = match secret {
+        Some(secret) => secret,
+        //A server that has sent its secret before is known to support it, so dropping it is not an old build
+        None if get_global_config().server_secret_required || internal.secret_in_use => {
+            return Err(ServerAuthError::MissingSecret)
+        }
+        //Old builds are only tied to their server by the address it is listed under
+        None if server.server.ip != ip => return Err(ServerAuthError::AddressMismatch),
+        None => return Ok(false),
+    };
+
+    let hash = sha256_hex(secret);
+    if constant_time_eq(hash.as_bytes(), internal.secret_hash.as_bytes()) {
+        internal.secret_in_use = true;
+        return Ok(true);
+    }
+
+    match &internal.pending_secret {
+        Some(pending) if constant_time_eq(secret.as_bytes(), pending.as_bytes()) => {
+            internal.secret_hash = hash;
+            internal.pending_secret = None;
+            internal.secret_issued_at = time;
+            internal.secret_in_use = true;
+            Ok(true)
+        }
+        _ => Err(ServerAuthError::BadSecret),
+    }
+}
+
+//Applies a heartbeat to a server we already have, returns the secret to hand back if any
+fn refresh_server(
+    server: &mut ServerInfo,
+    request: ServerWithUID,
+    owner: Option<String>,
+    timeout_time: u64,
+    time: u64,
+) -> Result<Option<String>, AddServerError> {
+    if get_global_config().server_bind_ip && server.server.ip != request.server.ip {
+        return Err(AddServerError::Unauthorized(ServerAuthError::AddressMismatch));
+    }
+
+    if server.server.key != request.server.key {
+        return Err(AddServerError::KeyMismatch);
+    }
+
+    let secret = request.secret.as_deref();
+    let authenticated = authenticate_server(server, secret, &request.server.ip, time)
+        .map_err(AddServerError::Unauthorized)?;
+
+    server.server = request.server;
+    server.internal.server_expiry_time = timeout_time;
+    server.internal.owner = owner;
+
+    acknowledge_commands(server, &request.acknowledged_commands, time);
+
+    //Anyone who knows the uid can send a heartbeat without the secret, so they never get one back
+    Ok(match authenticated {
+        true => rotate_secret(server, time),
+        false => None,
+    })
+}
+
+//The new secret is sent with every heartbeat until the server starts using it, so a lost response can not lock it out
+fn rotate_secret(server: &mut ServerInfo, time: u64) -> Option<String> {
+    let rotation_seconds = get_global_config().server_secret_rotation_minutes as u64 * 60;
+    let internal = &mut server.internal;
+
+    if internal.pending_secret.is_none()
+        && rotation_seconds != 0
+        && internal.secret_issued_at + rotation_seconds <= time
+    {
+        internal.pending_secret = random_token(32);
+    }
+
+    internal.pending_secret.clone()
+}
+
+fn acknowledge_commands(server: &mut ServerInfo, acknowledged: &[u64], time: u64) {
+    for command in server.commands.iter_mut() {
+        if command.acknowledged_at.is_none() && acknowledged.contains(&command.id) {
+            command.acknowledged_at = Some(time);
+
+            if let CommandKind::Announcement {
+                announcement_id, ..
+            } = command.kind
+            {
+                get_announcements().acknowledged(announcement_id, &server.internal.uid);
+            }
+        }
+    }
+
+    server.commands.retain(|command| {
+        !command.is_finished(time)
+            || command.acknowledged_at.unwrap_or(0) + COMMAND_HISTORY_SECONDS > time
+    });
+}
+
+//Everything not yet acknowledged, sent again on every heartbeat and bulk check until it is
+fn pending_commands(server: &mut ServerInfo, time: u64) -> Vec<ServerCommand> {
+    server
+        .commands
+        .iter_mut()
+        .filter(|command| command.acknowledged_at.is_none())
+        .map(|command| {
+            command.delivered_at.get_or_insert(time);
+            command.clone()
+        })
+        .collect()
+}
+
+//Called with the server's current players on each bulk check
+//Kicks are also returned as banned players for servers that do not understand commands yet
+pub fn process_bulk_check(
+    server: &mut ServerInfo,
+    acknowledged: &[u64],
+    players: &[Player],
+) -> (Vec<BanIdentifiers>, Vec<ServerCommand>) {
+    let time = current_time();
+    let mut kicks = Vec::new();
+
+    acknowledge_commands(server, acknowledged, time);
+    get_announcements().deliver(server, time);
+
+    for command in server.commands.iter_mut() {
+        let (player_uid, reason) = match &command.kind {
+            CommandKind::Kick {
+                player_uid, reason, ..
+            } => (*player_uid, reason.clone()),
+            _ => continue,
+        };
+        let player_present = players.iter().any(|player| player.uid == Some(player_uid));
+
+        //A delivered kick whose player has gone was acted on, even if the server did not say so
+        if command.acknowledged_at.is_none() && command.delivered_at.is_some() && !player_present {
+            command.acknowledged_at = Some(time);
+        }
+
+        //During the cooldown the kick is sent again whenever the player rejoins
+        if command.acknowledged_at.is_none() || (command.in_cooldown(time) && player_present) {
+            command.delivered_at.get_or_insert(time);
+            kicks.push(BanIdentifiers {
+                uid: None,
+                id: Some(player_uid),
+                ip: None,
+                reason: Some(reason),
+                kick_id: Some(command.id),
+            });
+        }
+    }
+
+    (kicks, pending_commands(server, time))
+}
+
+//Whether the player is still serving a kick cooldown on the server
+pub fn kick_cooldown_reason(server: &ServerInfo, player_uid: u64) -> Option<String> {
+    let time = current_time();
+
+    server.commands.iter().find_map(|command| match &command.kind {
+        CommandKind::Kick {
+            player_uid: uid,
+            reason,
+            ..
+        } if *uid == player_uid && command.in_cooldown(time) => Some(reason.clone()),
+        _ => None,
+    })
+}
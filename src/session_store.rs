@@ -0,0 +1,222 @@
+use {
+    crate::database::sessions,
+    actix_session::storage::{
+        generate_session_key, CookieSessionStore, LoadError, SaveError, SessionKey, SessionStore,
+        UpdateError,
+    },
+    actix_web::cookie::{time::Duration, Key},
+    shared::ms_config::get_global_config,
+    std::{
+        collections::HashMap,
+        fs::{self, OpenOptions},
+        io::{self, Write},
+    },
+    tracing::info,
+};
+
+type SessionState = HashMap<String, String>;
+
+//Key used to sign and encrypt the session cookie, it has to be the same across every worker and restart
+pub fn load_session_key() -> io::Result<Key> {
+    let cfg = get_global_config();
+
+    if !cfg.session_key.is_empty() {
+        let bytes = hex::decode(&cfg.session_key).map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid session_key: {}", err),
+            )
+        })?;
+
+        return Key::try_from(bytes.as_slice()).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "session_key must be at least 64 bytes",
+            )
+        });
+    }
+
+    match fs::read(&cfg.session_key_path) {
+        Ok(bytes) => Key::try_from(bytes.as_slice()).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Session key file '{}' is too short", cfg.session_key_path),
+            )
+        }),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            let key = Key::generate();
+            write_key_file(&cfg.session_key_path, key.master())?;
+            info!("Generated new session key at '{}'", cfg.session_key_path);
+            Ok(key)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+//Only the server user may read the key, anyone who can could forge sessions.
+//create_new so an existing file, or a symlink planted in its place, is never written through
+fn write_key_file(path: &str, key: &[u8]) -> io::Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    let mut file = options.open(path)?;
+    file.write_all(key)?;
+    file.sync_all()
+}
+
+//The "user" entry is stored json encoded by actix-session
+fn session_username(state: &SessionState) -> Option<String> {
+    state
+        .get("user")
+        .and_then(|user| serde_json::from_str::<String>(user).ok())
+}
+
+pub struct PostgresSessionStore;
+
+impl SessionStore for PostgresSessionStore {
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<SessionState>, LoadError> {
+        let state = sessions::load_session(session_key.as_ref())
+            .await
+            .map_err(LoadError::Other)?;
+
+        match state {
+            Some(state) => serde_json::from_str(&state)
+                .map(Some)
+                .map_err(|err| LoadError::Deserialization(err.into())),
+            None => Ok(None),
+        }
+    }
+
+    async fn save(
+        &self,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, SaveError> {
+        let body = serde_json::to_string(&session_state)
+            .map_err(|err| SaveError::Serialization(err.into()))?;
+        let key = generate_session_key();
+
+        sessions::insert_session(
+            key.as_ref(),
+            session_username(&session_state),
+            body,
+            ttl.whole_seconds(),
+        )
+        .await
+        .map_err(SaveError::Other)?;
+
+        Ok(key)
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        let body = serde_json::to_string(&session_state)
+            .map_err(|err| UpdateError::Serialization(err.into()))?;
+
+        let updated = sessions::update_session(
+            session_key.as_ref(),
+            session_username(&session_state),
+            body,
+            ttl.whole_seconds(),
+        )
+        .await
+        .map_err(UpdateError::Other)?;
+
+        if updated {
+            return Ok(session_key);
+        }
+
+        //The row is gone (expired or revoked), the request's state still carries the user so only an empty session
+        //is handed out under a fresh key. Saving the state would bring back a session revoked mid request
+        self.save(SessionState::new(), ttl)
+            .await
+            .map_err(|err| match err {
+                SaveError::Serialization(err) => UpdateError::Serialization(err),
+                SaveError::Other(err) => UpdateError::Other(err),
+            })
+    }
+
+    async fn update_ttl(
+        &self,
+        session_key: &SessionKey,
+        ttl: &Duration,
+    ) -> Result<(), anyhow::Error> {
+        sessions::update_session_ttl(session_key.as_ref(), ttl.whole_seconds()).await
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<(), anyhow::Error> {
+        sessions::delete_session(session_key.as_ref()).await
+    }
+}
+
+//Lets the store be picked from the config while keeping a single SessionMiddleware type
+pub enum MsSessionStore {
+    Cookie(CookieSessionStore),
+    Postgres(PostgresSessionStore),
+}
+
+impl MsSessionStore {
+    pub fn from_config() -> MsSessionStore {
+        match get_global_config().server_side_sessions {
+            true => MsSessionStore::Postgres(PostgresSessionStore),
+            false => MsSessionStore::Cookie(CookieSessionStore::default()),
+        }
+    }
+}
+
+impl SessionStore for MsSessionStore {
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<SessionState>, LoadError> {
+        match self {
+            MsSessionStore::Cookie(store) => store.load(session_key).await,
+            MsSessionStore::Postgres(store) => store.load(session_key).await,
+        }
+    }
+
+    async fn save(
+        &self,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, SaveError> {
+        match self {
+            MsSessionStore::Cookie(store) => store.save(session_state, ttl).await,
+            MsSessionStore::Postgres(store) => store.save(session_state, ttl).await,
+        }
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        match self {
+            MsSessionStore::Cookie(store) => store.update(session_key, session_state, ttl).await,
+            MsSessionStore::Postgres(store) => store.update(session_key, session_state, ttl).await,
+        }
+    }
+
+    async fn update_ttl(
+        &self,
+        session_key: &SessionKey,
+        ttl: &Duration,
+    ) -> Result<(), anyhow::Error> {
+        match self {
+            MsSessionStore::Cookie(store) => store.update_ttl(session_key, ttl).await,
+            MsSessionStore::Postgres(store) => store.update_ttl(session_key, ttl).await,
+        }
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<(), anyhow::Error> {
+        match self {
+            MsSessionStore::Cookie(store) => store.delete(session_key).await,
+            MsSessionStore::Postgres(store) => store.delete(session_key).await,
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use {
+    crate::{get_master_server, server_history::get_server_history},
+    actix_web::dev::ServerHandle,
+    std::sync::atomic::{AtomicBool, Ordering},
+    tracing::{error, info},
+};
+
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+
+//Set once a shutdown signal arrives, new registrations are refused and /readyz fails from then on
+pub fn is_shutting_down() -> bool {
+    SHUTTING_DOWN.load(Ordering::Relaxed)
+}
+
+#[cfg(unix)]
+async fn terminate_signal() {
+    match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+        Ok(mut signal) => {
+            signal.recv().await;
+        }
+        Err(err) => {
+            error!("Failed to register SIGTERM handler: {}", err);
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn terminate_signal() {
+    std::future::pending::<()>().await
+}
+
+//Failing to listen for ctrl+c must not stop SIGTERM from being handled, so it waits forever instead
+async fn interrupt_signal() {
+    if let Err(err) = tokio::signal::ctrl_c().await {
+        error!("Failed to listen for ctrl+c: {}", err);
+        std::future::pending::<()>().await;
+    }
+}
+
+//Waits for SIGTERM or ctrl+c, then stops the web servers letting in flight requests finish within the grace period
+pub async fn stop_on_signal(servers: Vec<ServerHandle>) {
+    tokio::select! {
+        _ = terminate_signal() => info!("SIGTERM received, shutting down"),
+        _ = interrupt_signal() => info!("Interrupt received, shutting down"),
+    }
+
+    SHUTTING_DOWN.store(true, Ordering::Relaxed);
+
+    futures::future::join_all(servers.iter().map(|server| server.stop(true))).await;
+}
+
+//Runs after the web servers have stopped, nothing is serving requests anymore
+pub async fn finish() {
+    SHUTTING_DOWN.store(true, Ordering::Relaxed);
+
+    get_master_server().server_list.stop_scrub().await;
+
+    //Every listed server's session ends here, the next run hands out new uids
+    get_server_history().flush(true).await;
+
+    if let Some(pool) = &get_master_server().postgres_pool {
+        pool.close().await;
+    }
+
+    info!("Shutdown complete");
+}
@@ -0,0 +1,292 @@
+use {
+    parking_lot::{Mutex, RwLock},
+    rustls::{
+        server::{ClientHello, ResolvesServerCert},
+        sign::{self, CertifiedKey},
+        Certificate, PrivateKey, ServerConfig, SignatureScheme,
+    },
+    rustls_pemfile::Item,
+    shared::ms_config::{get_global_config, ListenerConfig},
+    std::{
+        fs::File,
+        io::{self, BufReader, ErrorKind},
+        sync::Arc,
+        time::{Duration, SystemTime},
+    },
+    tracing::{error, info, warn},
+};
+
+//Schemes we are willing to use when checking a private key matches its certificate
+const VALIDATION_SCHEMES: [SignatureScheme; 4] = [
+    SignatureScheme::ECDSA_NISTP256_SHA256,
+    SignatureScheme::ECDSA_NISTP384_SHA384,
+    SignatureScheme::ED25519,
+    SignatureScheme::RSA_PKCS1_SHA256,
+];
+
+const VALIDATION_MESSAGE: &[u8] = b"r5r_ms_rs certificate validation";
+
+//Warn about certificates that are this close to expiring
+const EXPIRY_WARNING: Duration = Duration::from_secs(14 * 24 * 60 * 60);
+
+pub fn load_certs(path: &str) -> io::Result<Vec<Certificate>> {
+    let file = File::open(path).map_err(|err| {
+        io::Error::new(
+            err.kind(),
+            format!("Could not read cert file '{}': {}", path, err),
+        )
+    })?;
+
+    let certs: Vec<Certificate> = rustls_pemfile::certs(&mut BufReader::new(file))?
+        .into_iter()
+        .map(Certificate)
+        .collect();
+
+    if certs.is_empty() {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!("No certificates found in '{}'", path),
+        ));
+    }
+
+    Ok(certs)
+}
+
+//Takes the first private key in the file, whether it is PKCS#8, PKCS#1 (RSA) or SEC1 (EC)
+pub fn load_private_key(path: &str) -> io::Result<PrivateKey> {
+    let file = File::open(path).map_err(|err| {
+        io::Error::new(
+            err.kind(),
+            format!("Could not read key file '{}': {}", path, err),
+        )
+    })?;
+    let mut reader = BufReader::new(file);
+
+    while let Some(item) = rustls_pemfile::read_one(&mut reader)? {
+        match item {
+            Item::PKCS8Key(key) | Item::RSAKey(key) | Item::ECKey(key) => {
+                return Ok(PrivateKey(key))
+            }
+            _ => continue,
+        }
+    }
+
+    Err(io::Error::new(
+        ErrorKind::InvalidData,
+        format!("No private key found in '{}'", path),
+    ))
+}
+
+fn invalid_data(msg: impl Into<String>) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, msg.into())
+}
+
+fn webpki_algorithm(scheme: SignatureScheme) -> Option<&'static webpki::SignatureAlgorithm> {
+    match scheme {
+        SignatureScheme::ECDSA_NISTP256_SHA256 => Some(&webpki::ECDSA_P256_SHA256),
+        SignatureScheme::ECDSA_NISTP384_SHA384 => Some(&webpki::ECDSA_P384_SHA384),
+        SignatureScheme::ED25519 => Some(&webpki::ED25519),
+        SignatureScheme::RSA_PKCS1_SHA256 => Some(&webpki::RSA_PKCS1_2048_8192_SHA256),
+        _ => None,
+    }
+}
+
+//Loads the cert chain and key and makes sure the key actually belongs to the leaf certificate,
+//this is done by signing a message with the key and verifying it against the certificate
+fn load_certified_key(cert_path: &str, key_path: &str) -> io::Result<CertifiedKey> {
+    let certs = load_certs(cert_path)?;
+    let key = load_private_key(key_path)?;
+
+    let signing_key = sign::any_supported_type(&key)
+        .map_err(|_| invalid_data(format!("Unsupported private key type in '{}'", key_path)))?;
+
+    let signer = signing_key
+        .choose_scheme(&VALIDATION_SCHEMES)
+        .ok_or_else(|| invalid_data("No supported signature scheme for private key"))?;
+
+    let signature = signer
+        .sign(VALIDATION_MESSAGE)
+        .map_err(|err| invalid_data(format!("Failed to sign with private key: {}", err)))?;
+
+    let algorithm = webpki_algorithm(signer.scheme())
+        .ok_or_else(|| invalid_data("No supported signature scheme for private key"))?;
+
+    let leaf = webpki::EndEntityCert::try_from(certs[0].0.as_slice())
+        .map_err(|err| invalid_data(format!("Failed to parse certificate: {:?}", err)))?;
+
+    if leaf
+        .verify_signature(algorithm, VALIDATION_MESSAGE, &signature)
+        .is_err()
+    {
+        return Err(invalid_data(format!(
+            "Private key '{}' does not match certificate '{}'",
+            key_path, cert_path
+        )));
+    }
+
+    log_expiry(cert_path, &certs[0]);
+
+    Ok(CertifiedKey::new(certs, signing_key))
+}
+
+fn log_expiry(cert_path: &str, cert: &Certificate) {
+    let parsed = match x509_parser::parse_x509_certificate(&cert.0) {
+        Ok((_, parsed)) => parsed,
+        Err(err) => {
+            warn!("Could not read expiry date of '{}': {}", cert_path, err);
+            return;
+        }
+    };
+
+    let validity = parsed.validity();
+    match validity.time_to_expiration() {
+        None => error!(
+            "Certificate '{}' for '{}' expired on {}",
+            cert_path,
+            parsed.subject(),
+            validity.not_after
+        ),
+        Some(remaining) if remaining < EXPIRY_WARNING => warn!(
+            "Certificate '{}' for '{}' expires soon, on {}",
+            cert_path,
+            parsed.subject(),
+            validity.not_after
+        ),
+        Some(_) => info!(
+            "Loaded certificate '{}' for '{}', valid until {}",
+            cert_path,
+            parsed.subject(),
+            validity.not_after
+        ),
+    }
+}
+
+fn modified_time(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .ok()
+}
+
+//Serves whatever cert is currently loaded, allowing it to be swapped without restarting the listener
+pub struct ReloadingCertResolver {
+    cert_path: String,
+    key_path: String,
+    current: RwLock<Arc<CertifiedKey>>,
+    last_modified: Mutex<(Option<SystemTime>, Option<SystemTime>)>,
+}
+
+impl ReloadingCertResolver {
+    pub fn new(listener: &ListenerConfig) -> io::Result<Arc<ReloadingCertResolver>> {
+        let key = load_certified_key(&listener.cert_path, &listener.key_path)?;
+
+        Ok(Arc::new(ReloadingCertResolver {
+            cert_path: listener.cert_path.clone(),
+            key_path: listener.key_path.clone(),
+            current: RwLock::new(Arc::new(key)),
+            last_modified: Mutex::new((
+                modified_time(&listener.cert_path),
+                modified_time(&listener.key_path),
+            )),
+        }))
+    }
+
+    //Only swaps the key in if the new pair loaded and validated, otherwise the old one is kept
+    pub fn reload(&self) {
+        let modified = (
+            modified_time(&self.cert_path),
+            modified_time(&self.key_path),
+        );
+
+        //Track the attempt even if it fails so a bad pair is only retried after the files change again
+        *self.last_modified.lock() = modified;
+
+        match load_certified_key(&self.cert_path, &self.key_path) {
+            Ok(key) => {
+                *self.current.write() = Arc::new(key);
+                info!("Reloaded certificate '{}'", self.cert_path);
+            }
+            Err(err) => {
+                error!(
+                    "Failed to reload certificate '{}', keeping the current one: {}",
+                    self.cert_path, err
+                );
+            }
+        }
+    }
+
+    pub fn reload_if_changed(&self) {
+        let modified = (
+            modified_time(&self.cert_path),
+            modified_time(&self.key_path),
+        );
+        if modified != *self.last_modified.lock() {
+            self.reload();
+        }
+    }
+}
+
+impl ResolvesServerCert for ReloadingCertResolver {
+    fn resolve(&self, _: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().clone())
+    }
+}
+
+pub fn server_config_with_resolver(resolver: Arc<ReloadingCertResolver>) -> ServerConfig {
+    ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_cert_resolver(resolver)
+}
+
+#[cfg(unix)]
+async fn hangup_signal(signal: &mut Option<tokio::signal::unix::Signal>) -> Option<()> {
+    match signal {
+        Some(signal) => signal.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(not(unix))]
+async fn hangup_signal(_: &mut Option<()>) -> Option<()> {
+    std::future::pending().await
+}
+
+//Polls the cert files for changes and reloads every resolver on SIGHUP
+pub async fn cert_reload_task(resolvers: Vec<Arc<ReloadingCertResolver>>) {
+    #[cfg(unix)]
+    let mut hangup = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+        Ok(signal) => Some(signal),
+        Err(err) => {
+            error!("Failed to register SIGHUP handler: {}", err);
+            None
+        }
+    };
+    #[cfg(not(unix))]
+    let mut hangup: Option<()> = None;
+
+    let interval = get_global_config().tls_reload_interval;
+    let mut ticker = tokio::time::interval(Duration::from_secs(interval.max(1) as u64));
+
+    loop {
+        tokio::select! {
+            _ = ticker.tick(), if interval != 0 => {
+                for resolver in resolvers.iter() {
+                    resolver.reload_if_changed();
+                }
+            }
+            received = hangup_signal(&mut hangup) => match received {
+                Some(_) => {
+                    info!("SIGHUP received, reloading certificates");
+                    for resolver in resolvers.iter() {
+                        resolver.reload();
+                    }
+                }
+                //The stream ended, waiting on it again would return straight away
+                None => {
+                    error!("SIGHUP handler closed, certificates are only reloaded on change");
+                    hangup = None;
+                }
+            },
+        }
+    }
+}
@@ -0,0 +1,218 @@
+use {
+    crate::crypto::{constant_time_eq, random_bytes},
+    data_encoding::BASE32_NOPAD,
+    qrcode::{render::svg, QrCode},
+    ring::hmac,
+    std::time::{SystemTime, UNIX_EPOCH},
+};
+
+const STEP_SECONDS: u64 = 30;
+const DIGITS: u32 = 6;
+const ISSUER: &str = "R5R Master Server";
+
+//Codes from one step either side are accepted to allow for clock drift
+const ALLOWED_DRIFT: i64 = 1;
+
+pub fn generate_secret() -> Option<String> {
+    random_bytes(20).map(|bytes| BASE32_NOPAD.encode(&bytes))
+}
+
+fn url_encode(str: &str) -> String {
+    str.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+pub fn provisioning_uri(username: &str, secret: &str) -> String {
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&digits={}&period={}",
+        url_encode(ISSUER),
+        url_encode(username),
+        secret,
+        url_encode(ISSUER),
+        DIGITS,
+        STEP_SECONDS
+    )
+}
+
+pub fn qr_code_svg(data: &str) -> Option<String> {
+    let code = QrCode::new(data.as_bytes()).ok()?;
+    Some(code.render::<svg::Color>().min_dimensions(200, 200).build())
+}
+
+fn hotp(key: &hmac::Key, counter: u64) -> u32 {
+    let tag = hmac::sign(key, &counter.to_be_bytes());
+    let digest = tag.as_ref();
+
+    //Dynamic truncation as described in RFC 4226
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+
+    binary % 10u32.pow(DIGITS)
+}
+
+fn current_step() -> Option<i64> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?;
+    Some((now.as_secs() / STEP_SECONDS) as i64)
+}
+
+//Returns the time step the code matched, steps at or before last_step are rejected so a code can only be used once
+pub fn verify_code(secret: &str, code: &str, last_step: Option<i64>) -> Option<i64> {
+    verify_code_at(secret, code, last_step, current_step()?)
+}
+
+fn verify_code_at(secret: &str, code: &str, last_step: Option<i64>, now: i64) -> Option<i64> {
+    let code = code.trim();
+    if code.len() != DIGITS as usize || !code.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+
+    let secret = BASE32_NOPAD.decode(secret.as_bytes()).ok()?;
+    let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, &secret);
+
+    //Every step in the window is checked so the time taken does not depend on which one matched
+    (now - ALLOWED_DRIFT..=now + ALLOWED_DRIFT)
+        .filter(|step| *step >= 0 && last_step.is_none_or(|last| *step > last))
+        .fold(None, |matched, step| {
+            let expected = format!(
+                "{:0width$}",
+                hotp(&key, step as u64),
+                width = DIGITS as usize
+            );
+            match constant_time_eq(expected.as_bytes(), code.as_bytes()) {
+                true => matched.or(Some(step)),
+                false => matched,
+            }
+        })
+}
+
+pub const RECOVERY_CODE_COUNT: usize = 10;
+
+//Recovery codes are stored hashed in their normalised form so dashes and case do not matter
+pub fn normalise_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|char| char.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_lowercase()
+}
+
+pub fn generate_recovery_codes() -> Option<Vec<String>> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let code = hex::encode(random_bytes(10)?);
+            Some(
+                code.as_bytes()
+                    .chunks(5)
+                    .map(|chunk| String::from_utf8_lossy(chunk).into_owned())
+                    .collect::<Vec<String>>()
+                    .join("-"),
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //The RFC 4226 and RFC 6238 test secret, "12345678901234567890"
+    const RFC_SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+    fn rfc_key() -> hmac::Key {
+        hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, b"12345678901234567890")
+    }
+
+    #[test]
+    fn hotp_matches_rfc4226_vectors() {
+        let expected = [
+            755224, 287082, 359152, 969429, 338314, 254676, 287922, 162583, 399871, 520489,
+        ];
+
+        for (counter, code) in expected.iter().enumerate() {
+            assert_eq!(
+                hotp(&rfc_key(), counter as u64),
+                *code,
+                "counter {}",
+                counter
+            );
+        }
+    }
+
+    #[test]
+    fn totp_matches_rfc6238_vectors() {
+        //The RFC lists 8 digit codes, these are their last 6 digits
+        let expected = [
+            (59, "287082"),
+            (1111111109, "081804"),
+            (1111111111, "050471"),
+            (1234567890, "005924"),
+            (2000000000, "279037"),
+            (20000000000, "353130"),
+        ];
+
+        for (time, code) in expected {
+            let step = (time / STEP_SECONDS) as i64;
+            assert_eq!(
+                verify_code_at(RFC_SECRET, code, None, step),
+                Some(step),
+                "time {}",
+                time
+            );
+        }
+    }
+
+    #[test]
+    fn accepts_one_step_either_side() {
+        let step = 1111111111 / STEP_SECONDS as i64;
+
+        assert_eq!(
+            verify_code_at(RFC_SECRET, "050471", None, step - 1),
+            Some(step)
+        );
+        assert_eq!(
+            verify_code_at(RFC_SECRET, "050471", None, step + 1),
+            Some(step)
+        );
+        assert_eq!(verify_code_at(RFC_SECRET, "050471", None, step - 2), None);
+        assert_eq!(verify_code_at(RFC_SECRET, "050471", None, step + 2), None);
+    }
+
+    #[test]
+    fn rejects_replayed_steps() {
+        let step = 1111111111 / STEP_SECONDS as i64;
+
+        assert_eq!(
+            verify_code_at(RFC_SECRET, "050471", Some(step - 1), step),
+            Some(step)
+        );
+        assert_eq!(verify_code_at(RFC_SECRET, "050471", Some(step), step), None);
+        assert_eq!(
+            verify_code_at(RFC_SECRET, "050471", Some(step + 1), step),
+            None
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_codes() {
+        let step = 1111111111 / STEP_SECONDS as i64;
+
+        assert_eq!(
+            verify_code_at(RFC_SECRET, " 050471 ", None, step),
+            Some(step)
+        );
+        assert_eq!(verify_code_at(RFC_SECRET, "50471", None, step), None);
+        assert_eq!(verify_code_at(RFC_SECRET, "+50471", None, step), None);
+        assert_eq!(verify_code_at(RFC_SECRET, "0504710", None, step), None);
+        assert_eq!(verify_code_at("not base32!", "050471", None, step), None);
+    }
+}